@@ -0,0 +1,74 @@
+use anyhow::Result;
+use clap::Command;
+use clap_complete::Shell;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+pub fn generate<W: Write>(
+    shell: Shell,
+    cmd: &mut Command,
+    print_install_instructions: bool,
+    out: &mut W,
+) -> Result<()> {
+    let bin_name = cmd.get_name().to_string();
+
+    if print_install_instructions {
+        let home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("~"));
+        writeln!(out, "{}", install_instructions(shell, &bin_name, &home))?;
+    }
+
+    clap_complete::generate(shell, cmd, bin_name, out);
+    Ok(())
+}
+
+/// Build a comment block explaining where the completion script should be
+/// saved for `shell`, noting when the target directory does not exist yet.
+pub fn install_instructions(shell: Shell, bin_name: &str, home: &Path) -> String {
+    let (dir, file, extra): (PathBuf, String, Option<String>) = match shell {
+        Shell::Zsh => (
+            home.join(".zfunc"),
+            format!("_{bin_name}"),
+            Some("add 'fpath=(~/.zfunc $fpath)' to .zshrc".to_string()),
+        ),
+        Shell::Bash => (home.join(".bash_completion.d"), bin_name.to_string(), None),
+        Shell::Fish => (
+            home.join(".config").join("fish").join("completions"),
+            format!("{bin_name}.fish"),
+            None,
+        ),
+        Shell::Elvish => (
+            home.join(".config").join("elvish").join("lib"),
+            format!("{bin_name}.elv"),
+            Some(format!("add 'use {bin_name}' to rc.elv")),
+        ),
+        Shell::PowerShell => (
+            home.join(".config").join("powershell"),
+            format!("{bin_name}.ps1"),
+            Some("dot-source it from $PROFILE".to_string()),
+        ),
+        _ => (home.to_path_buf(), bin_name.to_string(), None),
+    };
+
+    let target = display_with_tilde(&dir.join(&file), home);
+    let mut lines = Vec::new();
+    match extra {
+        Some(extra) => lines.push(format!("# Save to {target} and {extra}")),
+        None => lines.push(format!("# Save to {target}")),
+    }
+
+    if !dir.exists() {
+        lines.push(format!(
+            "# Note: {} does not exist yet; create it first",
+            display_with_tilde(&dir, home)
+        ));
+    }
+
+    lines.join("\n")
+}
+
+fn display_with_tilde(path: &Path, home: &Path) -> String {
+    match path.strip_prefix(home) {
+        Ok(relative) => format!("~/{}", relative.display()),
+        Err(_) => path.display().to_string(),
+    }
+}
@@ -0,0 +1,146 @@
+use crate::config;
+use crate::proxy::{FISH_MANAGED_END, FISH_MANAGED_START, MANAGED_END, MANAGED_START};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Another proxy tool or hand-written setting that may override proxyctl-rs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictWarning {
+    pub tool: String,
+    pub path: PathBuf,
+    pub description: String,
+}
+
+const SHELL_PROFILES: [&str; 6] = [
+    ".bashrc",
+    ".bash_profile",
+    ".profile",
+    ".zshrc",
+    ".zprofile",
+    ".zshenv",
+];
+
+pub fn find_conflicting_tools() -> Vec<ConflictWarning> {
+    let home = dirs::home_dir().unwrap_or_default();
+    let ssh_config =
+        config::get_ssh_config_path().unwrap_or_else(|_| home.join(".ssh").join("config"));
+    find_conflicting_tools_in(
+        &home,
+        Path::new("/"),
+        &ssh_config,
+        std::env::var("ALL_PROXY").ok(),
+    )
+}
+
+/// Scan `home`, the system config under `root` and the SSH config at
+/// `ssh_config` for conflicting proxy setups. `all_proxy` is the current
+/// `$ALL_PROXY` value, if any.
+pub fn find_conflicting_tools_in(
+    home: &Path,
+    root: &Path,
+    ssh_config: &Path,
+    all_proxy: Option<String>,
+) -> Vec<ConflictWarning> {
+    let mut warnings = Vec::new();
+
+    let config_files = [
+        (
+            "proxychains",
+            home.join(".proxychains").join("proxychains.conf"),
+        ),
+        ("proxychains", root.join("etc").join("proxychains.conf")),
+        ("privoxy", root.join("etc").join("privoxy").join("config")),
+        (
+            "tinyproxy",
+            root.join("etc").join("tinyproxy").join("tinyproxy.conf"),
+        ),
+    ];
+    for (tool, path) in config_files {
+        if path.exists() {
+            warnings.push(ConflictWarning {
+                tool: tool.to_string(),
+                description: format!(
+                    "{tool} config found — ensure it doesn't override proxyctl-rs settings"
+                ),
+                path,
+            });
+        }
+    }
+
+    let mut managed_blocks = String::new();
+    for name in SHELL_PROFILES {
+        let path = home.join(name);
+        let Ok(content) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let (managed, unmanaged) = split_managed_lines(&content);
+        managed_blocks.push_str(&managed);
+
+        if !unmanaged.is_empty() {
+            warnings.push(ConflictWarning {
+                tool: "shell profile".to_string(),
+                description: format!(
+                    "{} proxy setting(s) outside the proxyctl-rs block in {name} (e.g. `{}`)",
+                    unmanaged.len(),
+                    unmanaged[0]
+                ),
+                path,
+            });
+        }
+    }
+
+    if let Some(value) = all_proxy.filter(|value| !value.trim().is_empty()) {
+        if !managed_blocks.contains(value.trim()) {
+            warnings.push(ConflictWarning {
+                tool: "ALL_PROXY".to_string(),
+                path: PathBuf::new(),
+                description: "$ALL_PROXY is set to a URL not managed by proxyctl-rs".to_string(),
+            });
+        }
+    }
+
+    if let Ok(content) = fs::read_to_string(ssh_config) {
+        let foreign = content
+            .lines()
+            .filter_map(|line| line.trim().split_once(char::is_whitespace))
+            .filter(|(keyword, command)| {
+                keyword.eq_ignore_ascii_case("proxycommand")
+                    && !config::is_managed_proxy_command(command)
+            })
+            .count();
+        if foreign > 0 {
+            warnings.push(ConflictWarning {
+                tool: "ssh".to_string(),
+                path: ssh_config.to_path_buf(),
+                description: format!(
+                    "{foreign} ProxyCommand line(s) not managed by proxyctl-rs in SSH config"
+                ),
+            });
+        }
+    }
+
+    warnings
+}
+
+// Returns the managed block contents and the proxy-related lines outside it.
+fn split_managed_lines(content: &str) -> (String, Vec<String>) {
+    let mut managed = String::new();
+    let mut unmanaged = Vec::new();
+    let mut in_block = false;
+
+    for line in content.lines() {
+        let trimmed = line.trim();
+        if trimmed == MANAGED_START || trimmed == FISH_MANAGED_START {
+            in_block = true;
+        } else if trimmed == MANAGED_END || trimmed == FISH_MANAGED_END {
+            in_block = false;
+        } else if in_block {
+            managed.push_str(line);
+            managed.push('\n');
+        } else if !trimmed.starts_with('#') && trimmed.to_ascii_lowercase().contains("proxy") {
+            unmanaged.push(trimmed.to_string());
+        }
+    }
+
+    (managed, unmanaged)
+}
@@ -1,11 +1,20 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use turso::transaction::TransactionBehavior;
 use turso::Builder;
 
 use crate::config;
 
-#[derive(Debug, Default, Clone, PartialEq)]
+// How long env state reads and writes wait on another process's transaction
+// before giving up with "database is locked".
+const ENV_STATE_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct EnvState {
     pub http_proxy: Option<String>,
     pub https_proxy: Option<String>,
@@ -13,6 +22,62 @@ pub struct EnvState {
     pub all_proxy: Option<String>,
     pub proxy_rsync: Option<String>,
     pub no_proxy: Option<String>,
+    /// Label given with `proxy on --tag` for the current session.
+    pub session_tag: Option<String>,
+    /// Config profile selected with `--profile` when the proxy was enabled.
+    pub active_profile: Option<String>,
+    /// When the proxy was enabled, as a Unix timestamp.
+    pub activated_at: Option<i64>,
+}
+
+/// One enable/disable event recorded in the `proxy_history` table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub id: i64,
+    pub action: String,
+    pub proxy_url: Option<String>,
+    pub activated_at: String,
+    pub comment: Option<String>,
+    /// Session tag the proxy was enabled with.
+    pub tag: Option<String>,
+}
+
+/// Most history entries kept; older ones are dropped as new ones are recorded.
+pub const HISTORY_MAX_ROWS: usize = 1_000;
+
+const CREATE_HISTORY_TABLE: &str = r#"CREATE TABLE IF NOT EXISTS proxy_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    action TEXT NOT NULL,
+    proxy_url TEXT,
+    activated_at TEXT NOT NULL,
+    comment TEXT,
+    tag TEXT
+)"#;
+
+// Create the history table, adding the `tag` column to tables created
+// before it existed.
+async fn ensure_history_table(conn: &turso::Connection) -> Result<()> {
+    conn.execute(CREATE_HISTORY_TABLE, ()).await?;
+    if conn
+        .prepare("SELECT tag FROM proxy_history LIMIT 0")
+        .await
+        .is_err()
+    {
+        conn.execute("ALTER TABLE proxy_history ADD COLUMN tag TEXT", ())
+            .await?;
+    }
+    Ok(())
+}
+
+const CREATE_WPAD_CACHE_TABLE: &str = r#"CREATE TABLE IF NOT EXISTS wpad_cache (
+    url TEXT PRIMARY KEY,
+    response TEXT,
+    fetched_at INTEGER
+)"#;
+
+fn unix_seconds(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
 }
 
 async fn migrate_db_if_needed() -> Result<()> {
@@ -47,18 +112,91 @@ pub async fn init_db(db_path: &str) -> Result<()> {
         (),
     )
     .await?;
+    ensure_history_table(&conn).await?;
+    conn.execute(CREATE_WPAD_CACHE_TABLE, ()).await?;
+    let cutoff = SystemTime::now()
+        .checked_sub(config::get_wpad_cache_ttl())
+        .unwrap_or(UNIX_EPOCH);
+    conn.execute(
+        "DELETE FROM wpad_cache WHERE fetched_at < ?1",
+        (unix_seconds(cutoff),),
+    )
+    .await?;
     Ok(())
 }
 
-pub async fn save_env_state(db_path: &str, state: &EnvState) -> Result<()> {
+/// Fail unless every table `init_db` creates exists in the database at `db_path`.
+pub async fn verify_schema(db_path: &str) -> Result<()> {
     let db = Builder::new_local(db_path).build().await?;
     let conn = db.connect()?;
+    for table in ["env_state", "proxy_history", "wpad_cache"] {
+        conn.prepare(&format!("SELECT * FROM {table} LIMIT 0"))
+            .await
+            .map_err(|err| anyhow!("table {table} is missing: {err}"))?;
+    }
+    Ok(())
+}
+
+/// Store the WPAD file fetched from `url`, replacing any earlier copy.
+pub async fn save_wpad_cache(db_path: &str, url: &str, response: &str) -> Result<()> {
+    let db = Builder::new_local(db_path).build().await?;
+    let conn = db.connect()?;
+    conn.execute(CREATE_WPAD_CACHE_TABLE, ()).await?;
+    conn.execute(
+        "INSERT INTO wpad_cache (url, response, fetched_at) VALUES (?1, ?2, ?3)
+         ON CONFLICT(url) DO UPDATE SET response = excluded.response, fetched_at = excluded.fetched_at",
+        (url, response, unix_seconds(SystemTime::now())),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Delete every cached WPAD file, returning how many there were.
+pub async fn clear_wpad_cache(db_path: &str) -> Result<u64> {
+    let db = Builder::new_local(db_path).build().await?;
+    let conn = db.connect()?;
+    conn.execute(CREATE_WPAD_CACHE_TABLE, ()).await?;
+    Ok(conn.execute("DELETE FROM wpad_cache", ()).await?)
+}
+
+/// The cached WPAD file for `url` and when it was fetched.
+pub async fn load_wpad_cache(db_path: &str, url: &str) -> Result<Option<(String, SystemTime)>> {
+    let db = Builder::new_local(db_path).build().await?;
+    let conn = db.connect()?;
+    conn.execute(CREATE_WPAD_CACHE_TABLE, ()).await?;
+    let mut stmt = conn
+        .prepare("SELECT response, fetched_at FROM wpad_cache WHERE url = ?1")
+        .await?;
+    let mut rows = stmt.query((url,)).await?;
+    let Some(row) = rows.next().await? else {
+        return Ok(None);
+    };
+    let response: String = row.get(0)?;
+    let fetched_at: i64 = row.get(1)?;
+    let fetched_at = UNIX_EPOCH + Duration::from_secs(fetched_at.max(0) as u64);
+    Ok(Some((response, fetched_at)))
+}
+
+pub async fn save_env_state(db_path: &str, state: &EnvState) -> Result<()> {
+    let db = Builder::new_local(db_path).build().await?;
+    let mut conn = db.connect()?;
+    conn.busy_timeout(ENV_STATE_BUSY_TIMEOUT)?;
     conn.execute(
         "CREATE TABLE IF NOT EXISTS env_state (key TEXT PRIMARY KEY, value TEXT)",
         (),
     )
     .await?;
-    // Clear existing
+    // Replace the rows in one transaction so readers never see a cleared table.
+    let tx = conn
+        .transaction_with_behavior(TransactionBehavior::Exclusive)
+        .await?;
+    replace_env_state(&tx, state).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+// Replace the env_state rows with `state` inside the caller's transaction.
+async fn replace_env_state(conn: &turso::Connection, state: &EnvState) -> Result<()> {
     conn.execute("DELETE FROM env_state", ()).await?;
     // Insert new
     if let Some(ref v) = state.http_proxy {
@@ -103,18 +241,43 @@ pub async fn save_env_state(db_path: &str, state: &EnvState) -> Result<()> {
         )
         .await?;
     }
+    if let Some(ref v) = state.session_tag {
+        conn.execute(
+            "INSERT INTO env_state (key, value) VALUES (?1, ?2)",
+            ("session_tag", v.as_str()),
+        )
+        .await?;
+    }
+    if let Some(ref v) = state.active_profile {
+        conn.execute(
+            "INSERT INTO env_state (key, value) VALUES (?1, ?2)",
+            ("active_profile", v.as_str()),
+        )
+        .await?;
+    }
+    if let Some(v) = state.activated_at {
+        conn.execute(
+            "INSERT INTO env_state (key, value) VALUES (?1, ?2)",
+            ("activated_at", v.to_string()),
+        )
+        .await?;
+    }
     Ok(())
 }
 
 pub async fn load_env_state(db_path: &str) -> Result<EnvState> {
     let db = Builder::new_local(db_path).build().await?;
-    let conn = db.connect()?;
+    let mut conn = db.connect()?;
+    conn.busy_timeout(ENV_STATE_BUSY_TIMEOUT)?;
     conn.execute(
         "CREATE TABLE IF NOT EXISTS env_state (key TEXT PRIMARY KEY, value TEXT)",
         (),
     )
     .await?;
-    let mut stmt = conn.prepare("SELECT key, value FROM env_state").await?;
+    let tx = conn
+        .transaction_with_behavior(TransactionBehavior::Deferred)
+        .await?;
+    let mut stmt = tx.prepare("SELECT key, value FROM env_state").await?;
     let mut rows = stmt.query(()).await?;
     let mut state = EnvState::default();
     while let Some(row) = rows.next().await? {
@@ -127,12 +290,390 @@ pub async fn load_env_state(db_path: &str) -> Result<EnvState> {
             "all_proxy" => state.all_proxy = Some(value),
             "proxy_rsync" => state.proxy_rsync = Some(value),
             "no_proxy" => state.no_proxy = Some(value),
+            "session_tag" => state.session_tag = Some(value),
+            "active_profile" => state.active_profile = Some(value),
+            "activated_at" => state.activated_at = value.parse().ok(),
             _ => {}
         }
     }
+    tx.commit().await?;
     Ok(state)
 }
 
+pub async fn record_history(
+    db_path: &str,
+    action: &str,
+    proxy_url: Option<&str>,
+    comment: Option<&str>,
+) -> Result<()> {
+    record_tagged_history(db_path, action, proxy_url, comment, None).await
+}
+
+/// Like [`record_history`], also storing the session tag.
+pub async fn record_tagged_history(
+    db_path: &str,
+    action: &str,
+    proxy_url: Option<&str>,
+    comment: Option<&str>,
+    tag: Option<&str>,
+) -> Result<()> {
+    let db = Builder::new_local(db_path).build().await?;
+    let conn = db.connect()?;
+    ensure_history_table(&conn).await?;
+    conn.execute(
+        "INSERT INTO proxy_history (action, proxy_url, activated_at, comment, tag) VALUES (?1, ?2, ?3, ?4, ?5)",
+        (
+            action,
+            proxy_url,
+            chrono::Utc::now().to_rfc3339(),
+            comment,
+            tag,
+        ),
+    )
+    .await?;
+    trim_history(db_path, HISTORY_MAX_ROWS).await
+}
+
+/// Delete all but the newest `keep` history entries.
+pub async fn trim_history(db_path: &str, keep: usize) -> Result<()> {
+    let db = Builder::new_local(db_path).build().await?;
+    let conn = db.connect()?;
+    ensure_history_table(&conn).await?;
+    let mut stmt = conn
+        .prepare("SELECT id FROM proxy_history ORDER BY id DESC LIMIT 1 OFFSET ?1")
+        .await?;
+    let mut rows = stmt.query((keep as i64,)).await?;
+    let Some(row) = rows.next().await? else {
+        return Ok(());
+    };
+    let newest_dropped: i64 = row.get(0)?;
+    conn.execute(
+        "DELETE FROM proxy_history WHERE id <= ?1",
+        (newest_dropped,),
+    )
+    .await?;
+    Ok(())
+}
+
+/// History entries, newest first.
+pub async fn load_history(db_path: &str) -> Result<Vec<HistoryEntry>> {
+    load_history_since(db_path, None).await
+}
+
+/// The newest `limit` history entries, newest first.
+pub async fn load_proxy_history(db_path: &str, limit: usize) -> Result<Vec<HistoryEntry>> {
+    let db = Builder::new_local(db_path).build().await?;
+    let conn = db.connect()?;
+    ensure_history_table(&conn).await?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, action, proxy_url, activated_at, comment, tag FROM proxy_history ORDER BY id DESC LIMIT ?1",
+        )
+        .await?;
+    let mut rows = stmt.query((limit as i64,)).await?;
+    let mut entries = Vec::new();
+    while let Some(row) = rows.next().await? {
+        entries.push(history_entry(&row)?);
+    }
+    Ok(entries)
+}
+
+/// History entries recorded at or after `since` (all of them when `None`),
+/// newest first.
+pub async fn load_history_since(
+    db_path: &str,
+    since: Option<chrono::DateTime<chrono::Utc>>,
+) -> Result<Vec<HistoryEntry>> {
+    let db = Builder::new_local(db_path).build().await?;
+    let conn = db.connect()?;
+    ensure_history_table(&conn).await?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, action, proxy_url, activated_at, comment, tag FROM proxy_history WHERE activated_at >= ?1 ORDER BY id DESC",
+        )
+        .await?;
+    // Timestamps are stored as UTC RFC 3339 strings, which sort chronologically.
+    let since = since.map(|since| since.to_rfc3339()).unwrap_or_default();
+    let mut rows = stmt.query((since,)).await?;
+    let mut entries = Vec::new();
+    while let Some(row) = rows.next().await? {
+        entries.push(history_entry(&row)?);
+    }
+    Ok(entries)
+}
+
+// A `proxy_history` row selected as id, action, proxy_url, activated_at, comment, tag.
+fn history_entry(row: &turso::Row) -> Result<HistoryEntry> {
+    Ok(HistoryEntry {
+        id: row.get(0)?,
+        action: row.get(1)?,
+        proxy_url: row.get(2)?,
+        activated_at: row.get(3)?,
+        comment: row.get(4)?,
+        tag: row.get(5)?,
+    })
+}
+
+/// Replace the comment on the most recent history entry. Returns false when
+/// the history is empty.
+pub async fn tag_latest_history(db_path: &str, comment: &str) -> Result<bool> {
+    let Some(latest) = load_history(db_path).await?.into_iter().next() else {
+        return Ok(false);
+    };
+
+    let db = Builder::new_local(db_path).build().await?;
+    let conn = db.connect()?;
+    conn.execute(
+        "UPDATE proxy_history SET comment = ?1 WHERE id = ?2",
+        (comment, latest.id),
+    )
+    .await?;
+    Ok(true)
+}
+
+/// Parse a duration such as `30s`, `15m`, `2h` or `1d`.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let invalid =
+        || anyhow!("invalid duration '{s}'; expected a number followed by s, m, h or d (e.g. 30m)");
+    let unit_at = s.len().checked_sub(1).ok_or_else(invalid)?;
+    if !s.is_char_boundary(unit_at) {
+        return Err(invalid());
+    }
+    let (amount, unit) = s.split_at(unit_at);
+    if amount.is_empty() || !amount.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let seconds_per_unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+    amount
+        .parse::<u64>()
+        .ok()
+        .and_then(|amount| amount.checked_mul(seconds_per_unit))
+        .map(Duration::from_secs)
+        .ok_or_else(invalid)
+}
+
+/// File format of `db export` and `db import`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// The current state and every history entry.
+    #[default]
+    Json,
+    /// History only, as action,proxy_url,activated_at,comment,tag rows.
+    Csv,
+}
+
+/// Everything `db export` writes as JSON.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DbDump {
+    pub current_state: EnvState,
+    /// Oldest first.
+    pub history: Vec<HistoryEntry>,
+}
+
+impl DbDump {
+    /// The dump with every proxy URL passed through `mask`, so that exports
+    /// leave out proxy passwords unless asked for them.
+    pub fn masked(mut self, mask: impl Fn(&str) -> String) -> Self {
+        let state = &mut self.current_state;
+        for value in [
+            &mut state.http_proxy,
+            &mut state.https_proxy,
+            &mut state.ftp_proxy,
+            &mut state.all_proxy,
+            &mut state.proxy_rsync,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *value = mask(value);
+        }
+        for entry in &mut self.history {
+            if let Some(url) = &mut entry.proxy_url {
+                *url = mask(url);
+            }
+        }
+        self
+    }
+}
+
+/// Write `text` to `path`, readable only by the current user, since exports
+/// can hold proxy credentials.
+pub fn write_private_file(path: &Path, text: &str) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // `mode` only applies to new files; tighten an existing one too.
+        if path.exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    options.open(path)?.write_all(text.as_bytes())?;
+    Ok(())
+}
+
+/// The current state and the whole history, oldest first.
+pub async fn load_dump(db_path: &str) -> Result<DbDump> {
+    let mut history = load_history(db_path).await?;
+    history.reverse();
+    Ok(DbDump {
+        current_state: load_env_state(db_path).await?,
+        history,
+    })
+}
+
+/// `dump` as pretty JSON or, for CSV, its history.
+pub fn render_dump(dump: &DbDump, format: ExportFormat) -> Result<String> {
+    match format {
+        ExportFormat::Json => Ok(serde_json::to_string_pretty(dump)? + "\n"),
+        ExportFormat::Csv => {
+            let mut csv = format!("{CSV_HEADER}\n");
+            for entry in &dump.history {
+                let fields = [
+                    entry.action.as_str(),
+                    entry.proxy_url.as_deref().unwrap_or(""),
+                    entry.activated_at.as_str(),
+                    entry.comment.as_deref().unwrap_or(""),
+                    entry.tag.as_deref().unwrap_or(""),
+                ];
+                let fields: Vec<String> = fields.into_iter().map(csv_field).collect();
+                csv.push_str(&fields.join(","));
+                csv.push('\n');
+            }
+            Ok(csv)
+        }
+    }
+}
+
+const CSV_HEADER: &str = "action,proxy_url,activated_at,comment,tag";
+
+/// History entries from `db export --format csv` output, numbered from 1.
+pub fn parse_history_csv(text: &str) -> Result<Vec<HistoryEntry>> {
+    let mut records = split_csv_records(text)?.into_iter();
+    match records.next() {
+        Some(header) if header.join(",") == CSV_HEADER => {}
+        _ => return Err(anyhow!("expected a {CSV_HEADER} header")),
+    }
+    let non_empty = |value: String| Some(value).filter(|value| !value.is_empty());
+    records
+        .enumerate()
+        .map(|(index, fields)| {
+            let [action, proxy_url, activated_at, comment, tag] = <[String; 5]>::try_from(fields)
+                .map_err(|fields| {
+                anyhow!(
+                    "expected 5 fields in row {}: {}",
+                    index + 1,
+                    fields.join(",")
+                )
+            })?;
+            Ok(HistoryEntry {
+                id: index as i64 + 1,
+                action,
+                proxy_url: non_empty(proxy_url),
+                activated_at,
+                comment: non_empty(comment),
+                tag: non_empty(tag),
+            })
+        })
+        .collect()
+}
+
+/// Replace the history with `history` (oldest first, ids kept) and, when
+/// given, the current state with `state`.
+pub async fn import_dump(
+    db_path: &str,
+    state: Option<&EnvState>,
+    history: &[HistoryEntry],
+) -> Result<()> {
+    let db = Builder::new_local(db_path).build().await?;
+    let mut conn = db.connect()?;
+    ensure_history_table(&conn).await?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS env_state (key TEXT PRIMARY KEY, value TEXT)",
+        (),
+    )
+    .await?;
+    // One transaction, so a failure leaves the old state and history intact.
+    let tx = conn
+        .transaction_with_behavior(TransactionBehavior::Exclusive)
+        .await?;
+    if let Some(state) = state {
+        replace_env_state(&tx, state).await?;
+    }
+    tx.execute("DELETE FROM proxy_history", ()).await?;
+    for entry in history {
+        tx.execute(
+            "INSERT INTO proxy_history (id, action, proxy_url, activated_at, comment, tag) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            (
+                entry.id,
+                entry.action.as_str(),
+                entry.proxy_url.as_deref(),
+                entry.activated_at.as_str(),
+                entry.comment.as_deref(),
+                entry.tag.as_deref(),
+            ),
+        )
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+// A CSV field, quoted when it holds a comma, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// The rows of `text`, skipping blank lines. Quoted fields may span lines,
+// as comments can hold line breaks.
+fn split_csv_records(text: &str) -> Result<Vec<Vec<String>>> {
+    let mut records = Vec::new();
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                fields.push(std::mem::take(&mut field));
+                let row = std::mem::take(&mut fields);
+                if row.iter().any(|value| !value.trim().is_empty()) {
+                    records.push(row);
+                }
+            }
+            _ => field.push(c),
+        }
+    }
+    if quoted {
+        return Err(anyhow!("unterminated quote in CSV"));
+    }
+    fields.push(field);
+    if fields.iter().any(|value| !value.trim().is_empty()) {
+        records.push(fields);
+    }
+    Ok(records)
+}
+
 fn db_file_path() -> Result<PathBuf> {
     Ok(config::get_data_dir()?.join("env_state.db"))
 }
@@ -11,3 +11,150 @@ pub fn default_no_proxy() -> String {
 pub fn default_wpad_url() -> String {
     env::var("DEFAULT_WPAD_URL").unwrap_or_else(|_| "http://wpad.local/wpad.dat".to_string())
 }
+
+/// Seconds to keep retrying WPAD when `wpad_wait_on_startup` is set without
+/// `wpad_wait_timeout_seconds`
+pub const DEFAULT_WPAD_WAIT_TIMEOUT_SECONDS: u64 = 30;
+
+/// Seconds a fetched WPAD file is reused (including by `--no-wpad`) when
+/// `wpad_cache_ttl_seconds` is not set
+pub const DEFAULT_WPAD_CACHE_TTL_SECONDS: u64 = 300;
+
+/// Seconds a fetched `remote_hosts_files` URL is reused before `ssh add`
+/// fetches it again
+pub const REMOTE_HOSTS_CACHE_MAX_AGE_SECONDS: u64 = 3_600;
+
+/// Seconds allowed to download a remote hosts file
+pub const REMOTE_HOSTS_TIMEOUT_SECONDS: u64 = 10;
+
+/// Dated SSH config backups kept when `ssh_backup_count` is not set
+pub const DEFAULT_SSH_BACKUP_COUNT: usize = 5;
+
+/// netcat binary used by the `netcat-connect` proxy mechanism when
+/// `nc_path` is not set
+pub const DEFAULT_NC_PATH: &str = "/usr/bin/nc";
+
+/// macOS network service `manage_system_proxy` configures when
+/// `macos_network_service` is not set
+pub const DEFAULT_MACOS_NETWORK_SERVICE: &str = "Wi-Fi";
+
+/// Milliseconds allowed to connect to the WPAD server when
+/// `wpad_connect_timeout_ms` is not set
+pub const DEFAULT_WPAD_CONNECT_TIMEOUT_MS: u64 = 5_000;
+
+/// Milliseconds allowed between reads of the WPAD response when
+/// `wpad_read_timeout_ms` is not set
+pub const DEFAULT_WPAD_READ_TIMEOUT_MS: u64 = 10_000;
+
+/// Milliseconds allowed for a whole WPAD request when `wpad_timeout_ms` is
+/// not set
+pub const DEFAULT_WPAD_TIMEOUT_MS: u64 = 5_000;
+
+/// Retries of a WPAD request that fails to connect when `wpad_max_retries`
+/// is not set
+pub const DEFAULT_WPAD_MAX_RETRIES: u8 = 3;
+
+/// Milliseconds before the first WPAD retry when `wpad_retry_delay_ms` is
+/// not set; each further retry waits twice as long
+pub const DEFAULT_WPAD_RETRY_DELAY_MS: u64 = 500;
+
+/// Milliseconds allowed for the TCP connect that ranks each WPAD proxy by
+/// latency when `detect_timeout_ms` is not set; slower proxies are treated
+/// as unreachable
+pub const DEFAULT_DETECT_TIMEOUT_MS: u64 = 2_000;
+
+/// URL passed to `FindProxyForURL` when evaluating a PAC file without an
+/// explicit target; an external site, so the script picks its proxy path
+pub const DEFAULT_PAC_EVALUATION_URL: &str = "http://example.com/";
+
+/// Milliseconds a PAC script may run before evaluation is abandoned
+pub const PAC_EVALUATION_TIMEOUT_MS: u64 = 2_000;
+
+/// Bytes a PAC script may allocate before evaluation is abandoned
+pub const PAC_EVALUATION_MEMORY_LIMIT_BYTES: usize = 16 * 1024 * 1024;
+
+/// Address whose route gives PAC `myIpAddress` the outbound interface; no
+/// packet is sent to it
+pub const PAC_ROUTE_PROBE_ADDRESS: &str = "192.0.2.1:9";
+
+/// CIDR blocks of the subnets this machine reaches directly (no gateway),
+/// for use as `no_proxy` entries. Loopback is left out.
+pub fn local_subnet_entries() -> anyhow::Result<Vec<String>> {
+    if cfg!(target_os = "macos") {
+        let output = std::process::Command::new("ifconfig").output()?;
+        Ok(subnets_from_ifconfig(&String::from_utf8_lossy(
+            &output.stdout,
+        )))
+    } else {
+        Ok(subnets_from_proc_net_route(&std::fs::read_to_string(
+            "/proc/net/route",
+        )?))
+    }
+}
+
+/// Directly connected routes from a Linux `/proc/net/route` table, whose
+/// addresses and masks are little-endian hex.
+pub fn subnets_from_proc_net_route(table: &str) -> Vec<String> {
+    let mut entries = Vec::new();
+    for line in table.lines().skip(1) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [iface, destination, gateway, _, _, _, _, mask, ..] = fields[..] else {
+            continue;
+        };
+        let parse = |hex: &str| u32::from_str_radix(hex, 16).ok().map(u32::swap_bytes);
+        let (Some(destination), Some(gateway), Some(mask)) =
+            (parse(destination), parse(gateway), parse(mask))
+        else {
+            continue;
+        };
+        if iface == "lo" || gateway != 0 || mask == 0 {
+            continue;
+        }
+        push_subnet(&mut entries, destination, mask);
+    }
+    entries
+}
+
+/// Subnets of the `inet` addresses in macOS `ifconfig` output, where the
+/// netmask is printed as hex (`netmask 0xffffff00`).
+pub fn subnets_from_ifconfig(output: &str) -> Vec<String> {
+    let mut entries = Vec::new();
+    for line in output.lines() {
+        let mut fields = line.split_whitespace();
+        let (Some("inet"), Some(address), Some("netmask"), Some(mask)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let Ok(address) = address.parse::<std::net::Ipv4Addr>() else {
+            continue;
+        };
+        let Ok(mask) = u32::from_str_radix(mask.trim_start_matches("0x"), 16) else {
+            continue;
+        };
+        if address.is_loopback() || mask == 0 {
+            continue;
+        }
+        push_subnet(&mut entries, u32::from(address) & mask, mask);
+    }
+    entries
+}
+
+// Masks that aren't contiguous can't be written as a prefix length; skip them.
+fn push_subnet(entries: &mut Vec<String>, network: u32, mask: u32) {
+    let prefix = mask.leading_ones();
+    if mask.checked_shl(prefix).unwrap_or(0) != 0 {
+        return;
+    }
+    let network = std::net::Ipv4Addr::from(network & mask);
+    if network.is_loopback() {
+        return;
+    }
+    let entry = format!("{network}/{prefix}");
+    if !entries.contains(&entry) {
+        entries.push(entry);
+    }
+}
+
+/// Seconds `doctor run` waits for a TCP connection to the stored proxy
+pub const PROXY_REACHABILITY_TIMEOUT_SECONDS: u64 = 3;
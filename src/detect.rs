@@ -1,59 +1,864 @@
-use crate::config;
-use anyhow::{anyhow, Result};
+use crate::config::{self, WpadDiscoveryMethod};
+use crate::db;
+use crate::defaults;
+use crate::output;
+use anyhow::{anyhow, Context, Result};
 use regex::Regex;
 use reqwest::Client;
+use serde::Serialize;
+use std::fs;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 // PAC entries typically follow the pattern "PROXY host:port" or variations
 // such as "HTTPS host:port". We capture the target component while skipping
 // trailing directives like DIRECT. Case-insensitive to support mixed casing.
 const PROXY_TARGET_REGEX: &str = r#"(?i)\b(?:PROXY|HTTPS?|SOCKS[45]?)\s+([^;\s"]+)"#;
 
-pub async fn detect_best_proxy() -> Result<String> {
-    let (enabled, url) = config::get_wpad_config()?;
+// Regional proxies are usually named `proxy-<region>`, e.g. proxy-us or
+// proxy-emea2; longer suffixes like proxy-backup are not regions.
+const PROXY_REGION_REGEX: &str = r"(?i)^proxy[-_]([a-z]{2,4})\d*$";
 
-    if !enabled {
-        return Err(anyhow!("WPAD proxy discovery is disabled in configuration"));
+// dhclient lease and config files that may carry DHCP option 252 as
+// `option wpad "<url>";`, once dhclient.conf declares the option.
+const DHCLIENT_FILE_GLOBS: [&str; 5] = [
+    "/var/lib/dhcp/dhclient.conf",
+    "/var/lib/dhcp/dhclient*.leases",
+    "/var/lib/dhclient/dhclient*.leases",
+    "/var/lib/NetworkManager/dhclient-*.conf",
+    "/var/lib/NetworkManager/dhclient-*.lease",
+];
+
+// First sleep between --wait-for-wpad attempts; each later one doubles.
+const WPAD_WAIT_BACKOFF_BASE: Duration = Duration::from_secs(1);
+
+const FIND_PROXY_FUNCTION_REGEX: &str = r"\bfunction\s+FindProxyForURL\b";
+
+// The standard PAC helpers. dnsResolve and myIpAddress call into Rust
+// (`__proxyctlResolve`, `__proxyctlMyIp`) so scripts that branch on the
+// client's subnet see the real addresses.
+const PAC_PRELUDE: &str = r#"
+function isPlainHostName(host) { return host.indexOf('.') < 0; }
+function dnsDomainIs(host, domain) {
+    return host.length >= domain.length
+        && host.substring(host.length - domain.length).toLowerCase() === domain.toLowerCase();
+}
+function localHostOrDomainIs(host, hostdom) {
+    return host === hostdom || hostdom.lastIndexOf(host + '.', 0) === 0;
+}
+function dnsDomainLevels(host) { return host.split('.').length - 1; }
+function shExpMatch(str, shexp) {
+    var pattern = shexp.replace(/[.+^${}()|[\]\\]/g, '\\$&').replace(/\*/g, '.*').replace(/\?/g, '.');
+    return new RegExp('^' + pattern + '$').test(str);
+}
+function dnsResolve(host) {
+    var ip = __proxyctlResolve(String(host));
+    return ip === undefined ? null : ip;
+}
+function isResolvable(host) { return dnsResolve(host) !== null; }
+function myIpAddress() { return __proxyctlMyIp(); }
+function isInNet(host, pattern, mask) {
+    var ip = dnsResolve(host);
+    if (ip === null) { return false; }
+    function toInt(addr) {
+        return addr.split('.').reduce(function (acc, part) { return acc * 256 + Number(part); }, 0);
+    }
+    var m = toInt(mask);
+    return (toInt(ip) & m) === (toInt(pattern) & m);
+}
+function weekdayRange() { return true; }
+function dateRange() { return true; }
+function timeRange() { return true; }
+function alert() {}
+"#;
+
+/// What a PAC file contains, reported by `on --validate-pac` and
+/// `detect --validate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacAnalysis {
+    pub proxy_count: usize,
+    pub has_direct_fallback: bool,
+    pub regions: Vec<String>,
+    pub is_function_based: bool,
+}
+
+impl PacAnalysis {
+    pub fn render(&self) -> String {
+        let yes_no = |flag: bool| if flag { "yes" } else { "no" };
+        let regions = if self.regions.is_empty() {
+            "none detected".to_string()
+        } else {
+            self.regions.join(", ")
+        };
+        let style = if self.is_function_based {
+            "FindProxyForURL function"
+        } else {
+            "variable assignments"
+        };
+        [
+            "PAC structure:".to_string(),
+            format!("  Proxies: {}", self.proxy_count),
+            format!("  Regions: {regions}"),
+            format!("  DIRECT fallback: {}", yes_no(self.has_direct_fallback)),
+            format!("  Style: {style}"),
+        ]
+        .join("\n")
+    }
+}
+
+/// A WPAD proxy and how long it took to accept a TCP connection.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProxyCandidate {
+    pub host: String,
+    pub latency_ms: Option<u64>,
+    pub reachable: bool,
+}
+
+impl ProxyCandidate {
+    /// A proxy taken as reachable without testing it.
+    pub fn untested(host: String) -> Self {
+        Self {
+            host,
+            latency_ms: None,
+            reachable: true,
+        }
+    }
+
+    /// `host` followed by its latency, or "unreachable".
+    pub fn render(&self) -> String {
+        match (self.reachable, self.latency_ms) {
+            (true, Some(latency_ms)) => format!("{}  {latency_ms}ms", self.host),
+            (true, None) => self.host.clone(),
+            (false, _) => format!("{}  unreachable", self.host),
+        }
+    }
+}
+
+/// Timeouts and retries for the WPAD request, whether a cached response may
+/// be used instead (or written, unless `read_only`), and whether the proxies
+/// it returns are re-ordered by connect latency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WpadClientConfig {
+    pub connect_timeout_ms: u64,
+    pub read_timeout_ms: u64,
+    /// Limit on the whole request, connecting included.
+    pub timeout_ms: u64,
+    pub latency_test: bool,
+    pub refresh: bool,
+    pub max_retries: u8,
+    pub retry_delay_ms: u64,
+    pub read_only: bool,
+}
+
+impl Default for WpadClientConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout_ms: defaults::DEFAULT_WPAD_CONNECT_TIMEOUT_MS,
+            read_timeout_ms: defaults::DEFAULT_WPAD_READ_TIMEOUT_MS,
+            timeout_ms: defaults::DEFAULT_WPAD_TIMEOUT_MS,
+            latency_test: true,
+            refresh: false,
+            max_retries: defaults::DEFAULT_WPAD_MAX_RETRIES,
+            retry_delay_ms: defaults::DEFAULT_WPAD_RETRY_DELAY_MS,
+            read_only: false,
+        }
+    }
+}
+
+impl WpadClientConfig {
+    pub fn builder() -> WpadClientConfigBuilder {
+        WpadClientConfigBuilder::default()
     }
 
-    let client = Client::new();
+    /// The timeouts from the config file, falling back to the defaults.
+    pub fn from_config() -> Self {
+        Self::builder().build()
+    }
+
+    fn client(&self) -> Result<Client> {
+        Ok(Client::builder()
+            .connect_timeout(Duration::from_millis(self.connect_timeout_ms))
+            .read_timeout(Duration::from_millis(self.read_timeout_ms))
+            .timeout(Duration::from_millis(self.timeout_ms))
+            .build()?)
+    }
+
+    // The error for a WPAD request that hit one of the configured timeouts,
+    // naming the overall limit only when it is the tightest of the three.
+    fn timeout_error(&self) -> anyhow::Error {
+        if self.timeout_ms <= self.connect_timeout_ms.min(self.read_timeout_ms) {
+            anyhow!("WPAD request timed out after {}ms", self.timeout_ms)
+        } else {
+            anyhow!(
+                "WPAD request timed out (connect timeout {}ms, read timeout {}ms)",
+                self.connect_timeout_ms,
+                self.read_timeout_ms
+            )
+        }
+    }
+}
+
+/// Builds a [`WpadClientConfig`] from per-invocation overrides (e.g. CLI
+/// flags), filling the rest from `wpad_connect_timeout_ms` /
+/// `wpad_read_timeout_ms` / `wpad_timeout_ms` / `wpad_max_retries` /
+/// `wpad_retry_delay_ms` in the config and then the defaults.
+#[derive(Debug, Clone, Default)]
+pub struct WpadClientConfigBuilder {
+    connect_timeout_ms: Option<u64>,
+    read_timeout_ms: Option<u64>,
+    timeout_ms: Option<u64>,
+    skip_latency_test: bool,
+    refresh: bool,
+    no_retry: bool,
+    read_only: bool,
+}
 
-    let response = client
-        .get(&url)
-        .header("noproxy", "*")
-        .send()
-        .await?
-        .text()
-        .await?;
+impl WpadClientConfigBuilder {
+    pub fn connect_timeout_ms(mut self, timeout_ms: Option<u64>) -> Self {
+        self.connect_timeout_ms = timeout_ms;
+        self
+    }
+
+    pub fn read_timeout_ms(mut self, timeout_ms: Option<u64>) -> Self {
+        self.read_timeout_ms = timeout_ms;
+        self
+    }
+
+    /// Limit on the whole request, connecting included.
+    pub fn timeout_ms(mut self, timeout_ms: Option<u64>) -> Self {
+        self.timeout_ms = timeout_ms;
+        self
+    }
+
+    /// Keep the PAC ordering instead of ranking proxies by latency.
+    pub fn skip_latency_test(mut self, skip: bool) -> Self {
+        self.skip_latency_test = skip;
+        self
+    }
 
-    detect_proxy_candidates_from_response(&response)
+    /// Fetch the WPAD file even when a cached copy is still fresh.
+    pub fn refresh(mut self, refresh: bool) -> Self {
+        self.refresh = refresh;
+        self
+    }
+
+    /// Make a single WPAD request, however `wpad_max_retries` is set.
+    pub fn no_retry(mut self, no_retry: bool) -> Self {
+        self.no_retry = no_retry;
+        self
+    }
+
+    /// Leave the WPAD caches untouched, as dry runs and previews must.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    pub fn build(self) -> WpadClientConfig {
+        let config = config::load_config().ok();
+        let defaults = WpadClientConfig::default();
+        WpadClientConfig {
+            connect_timeout_ms: self
+                .connect_timeout_ms
+                .or_else(|| config.as_ref()?.wpad_connect_timeout_ms)
+                .unwrap_or(defaults.connect_timeout_ms),
+            read_timeout_ms: self
+                .read_timeout_ms
+                .or_else(|| config.as_ref()?.wpad_read_timeout_ms)
+                .unwrap_or(defaults.read_timeout_ms),
+            timeout_ms: self
+                .timeout_ms
+                .or_else(|| config.as_ref()?.wpad_timeout_ms)
+                .unwrap_or(defaults.timeout_ms),
+            latency_test: !self.skip_latency_test,
+            refresh: self.refresh,
+            max_retries: if self.no_retry {
+                0
+            } else {
+                config
+                    .as_ref()
+                    .and_then(|config| config.wpad_max_retries)
+                    .unwrap_or(defaults.max_retries)
+            },
+            retry_delay_ms: config
+                .as_ref()
+                .and_then(|config| config.wpad_retry_delay_ms)
+                .unwrap_or(defaults.retry_delay_ms),
+            read_only: self.read_only,
+        }
+    }
+}
+
+/// The fastest reachable proxy the WPAD file offers for `pac_url` (see
+/// [`detect_proxy_candidates`]). Without the latency test, the first proxy
+/// in PAC order, untested.
+pub async fn detect_best_proxy(
+    client_config: &WpadClientConfig,
+    pac_url: Option<&str>,
+) -> Result<String> {
+    let (_, mut candidates) = detect_proxy_candidates(client_config, pac_url).await?;
+    Ok(candidates.swap_remove(0))
+}
+
+/// The first reachable proxy in `candidates`, scored by [`score_candidates`].
+pub fn best_candidate(candidates: &[ProxyCandidate]) -> Result<String> {
+    Ok(reachable_hosts(candidates)?.swap_remove(0))
+}
+
+// The reachable hosts in `candidates`, in order; an error when there are none.
+fn reachable_hosts(candidates: &[ProxyCandidate]) -> Result<Vec<String>> {
+    let hosts: Vec<String> = candidates
+        .iter()
+        .filter(|candidate| candidate.reachable)
+        .map(|candidate| candidate.host.clone())
+        .collect();
+    if hosts.is_empty() {
+        return Err(anyhow!(
+            "no proxy from the WPAD response is reachable ({} tried)",
+            candidates.len()
+        ));
+    }
+    Ok(hosts)
+}
+
+/// The WPAD URL `method` finds (see [`WpadDiscoveryMethod`]).
+pub fn discover_wpad(method: WpadDiscoveryMethod) -> Result<String> {
+    match method {
+        WpadDiscoveryMethod::Http => Ok(config::get_wpad_config()?.1),
+        WpadDiscoveryMethod::Static => config::load_config()?.wpad_url.ok_or_else(|| {
+            anyhow!("wpad_discovery_method = \"static\" requires wpad_url to be set")
+        }),
+        WpadDiscoveryMethod::Dhcp => discover_wpad_via_dhcp(),
+        WpadDiscoveryMethod::Dns => discover_wpad_via_dns(),
+    }
+}
+
+/// The WPAD URL the DHCP server sent as option 252: from `ipconfig` on
+/// macOS, elsewhere from dhclient's files, then NetworkManager.
+pub fn discover_wpad_via_dhcp() -> Result<String> {
+    let url = if cfg!(target_os = "macos") {
+        let output = Command::new("ipconfig")
+            .args(["getoption", "en0", "wpad-url"])
+            .output()
+            .context("running ipconfig getoption")?;
+        let url = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        (output.status.success() && !url.is_empty()).then_some(url)
+    } else {
+        dhclient_wpad_url().or_else(nmcli_wpad_url)
+    };
+
+    let url = url.ok_or_else(|| anyhow!("no WPAD URL (DHCP option 252) offered by DHCP"))?;
+    output::detail(format!("WPAD URL from DHCP: {url}"));
+    Ok(url)
+}
+
+fn dhclient_wpad_url() -> Option<String> {
+    newest_dhcp_wpad(
+        DHCLIENT_FILE_GLOBS
+            .iter()
+            .filter_map(|pattern| glob::glob(pattern).ok())
+            .flatten()
+            .flatten(),
+    )
+}
+
+/// The option 252 URL from the most recently written of the dhclient lease
+/// and config files `paths` that has one (see [`parse_dhcp_wpad`]).
+pub fn newest_dhcp_wpad<I>(paths: I) -> Option<String>
+where
+    I: IntoIterator<Item = PathBuf>,
+{
+    paths
         .into_iter()
-        .next()
-        .ok_or_else(|| anyhow!("Could not parse proxies from WPAD response"))
+        .filter_map(|path| {
+            let modified = fs::metadata(&path).and_then(|meta| meta.modified()).ok()?;
+            let url = parse_dhcp_wpad(&fs::read_to_string(&path).ok()?)?;
+            Some((modified, url))
+        })
+        .max_by_key(|(modified, _)| *modified)
+        .map(|(_, url)| url)
 }
 
-pub async fn detect_proxy_candidates() -> Result<Vec<String>> {
-    let (enabled, url) = config::get_wpad_config()?;
+// `nmcli connection show` only lists DHCP4 fields for a named profile, so
+// ask for every device's DHCP options instead.
+fn nmcli_wpad_url() -> Option<String> {
+    let output = Command::new("nmcli")
+        .args(["-t", "-f", "DHCP4", "device", "show"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_dhcp_wpad(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// The last option 252 URL in dhclient lease/config text
+/// (`option wpad "http://...";`) or `nmcli` DHCP4 output
+/// (`DHCP4.OPTION[7]:wpad = http://...`).
+pub fn parse_dhcp_wpad(text: &str) -> Option<String> {
+    text.lines().rev().find_map(|line| {
+        let line = line.trim();
+        let (name, value) = if let Some(rest) = line.strip_prefix("option ") {
+            rest.split_once(char::is_whitespace)?
+        } else if line.starts_with("DHCP4.OPTION") {
+            line.split_once(':')?.1.trim().split_once(" = ")?
+        } else {
+            return None;
+        };
+        if !matches!(name, "wpad" | "wpad-url" | "option-252") {
+            return None;
+        }
+        // Some servers send the URL NUL- or newline-terminated, which
+        // dhclient writes as an octal escape.
+        let value = value
+            .trim()
+            .trim_end_matches(';')
+            .trim()
+            .trim_matches('"')
+            .trim_end_matches("\\000")
+            .trim_end_matches("\\012");
+        value.contains("://").then(|| value.to_string())
+    })
+}
+
+/// `http://wpad.<domain>/wpad.dat` for the DNS search domain in
+/// `/etc/resolv.conf`.
+pub fn discover_wpad_via_dns() -> Result<String> {
+    let resolv_conf = fs::read_to_string("/etc/resolv.conf").context("reading /etc/resolv.conf")?;
+    wpad_url_for_resolv_conf(&resolv_conf)
+        .ok_or_else(|| anyhow!("no search domain in /etc/resolv.conf to build a WPAD URL from"))
+}
+
+/// The WPAD URL for the first `search` or `domain` entry in `resolv.conf`
+/// text.
+pub fn wpad_url_for_resolv_conf(resolv_conf: &str) -> Option<String> {
+    resolv_conf.lines().find_map(|line| {
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("search" | "domain") => fields
+                .next()
+                .map(|domain| format!("http://wpad.{}/wpad.dat", domain.trim_end_matches('.'))),
+            _ => None,
+        }
+    })
+}
+
+// The discovered WPAD URL and the PAC body it returned. When HTTP WPAD
+// fails, the URL offered by DHCP (option 252) is tried before giving up.
+async fn fetch_wpad(client_config: &WpadClientConfig) -> Result<(String, String)> {
+    let (enabled, _) = config::get_wpad_config()?;
 
     if !enabled {
         return Err(anyhow!("WPAD proxy discovery is disabled in configuration"));
     }
+    let method = config::get_wpad_discovery_method();
+    let url = discover_wpad(method)?;
+    let err = match fetch_wpad_from(client_config, &url).await {
+        Ok(response) => return Ok((url, response)),
+        Err(err) => err,
+    };
+    if method != WpadDiscoveryMethod::Http {
+        return Err(err);
+    }
+    match discover_wpad_via_dhcp() {
+        Ok(dhcp_url) if dhcp_url != url => {
+            output::detail(format!("{err:#}; trying the WPAD URL from DHCP"));
+            let response = fetch_wpad_from(client_config, &dhcp_url)
+                .await
+                .map_err(|dhcp_err| {
+                    err.context(format!("DHCP fallback failed too: {dhcp_err:#}"))
+                })?;
+            Ok((dhcp_url, response))
+        }
+        _ => Err(err),
+    }
+}
 
-    let client = Client::new();
-    let response = client
-        .get(&url)
-        .header("noproxy", "*")
-        .send()
-        .await?
-        .text()
-        .await?;
+// The WPAD file at `url`, from the cache while it is younger than
+// `wpad_cache_ttl_seconds`.
+async fn fetch_wpad_from(client_config: &WpadClientConfig, url: &str) -> Result<String> {
+    let db_path = db::get_db_path();
+    let ttl = config::get_wpad_cache_ttl();
+    if !client_config.refresh && !ttl.is_zero() {
+        if let Ok(Some((response, fetched_at))) = db::load_wpad_cache(&db_path, url).await {
+            let age = fetched_at.elapsed().unwrap_or_default();
+            if age < ttl {
+                output::detail(format!(
+                    "Using WPAD file cached {}s ago (--refresh to fetch it again)",
+                    age.as_secs()
+                ));
+                return Ok(response);
+            }
+        }
+    }
 
-    let proxies = detect_proxy_candidates_from_response(&response);
+    output::detail(format!("Fetching WPAD file from {url}"));
+    let _spinner = output::spinner(format!("Fetching WPAD file from {url}..."));
+    let client = client_config.client()?;
+    let mut attempt = 0;
+    let response = loop {
+        let (err, retryable) = match client.get(url).header("noproxy", "*").send().await {
+            Ok(response) => break response,
+            Err(err) if err.is_timeout() => (client_config.timeout_error(), false),
+            // Refused, reset or dropped connections; anything slower than
+            // that already spent its timeout and is not worth repeating.
+            Err(err) => {
+                let retryable = err.is_connect() || err.is_request();
+                (anyhow::Error::new(err), retryable)
+            }
+        };
+        if !retryable || attempt >= client_config.max_retries {
+            let tries = u32::from(attempt) + 1;
+            return Err(err.context(format!(
+                "could not fetch the WPAD file from {url} ({tries} attempt{})",
+                if tries == 1 { "" } else { "s" }
+            )));
+        }
+        let delay = wpad_retry_delay(client_config.retry_delay_ms, attempt.into());
+        output::detail(format!(
+            "WPAD request failed ({err:#}); retrying in {}ms",
+            delay.as_millis()
+        ));
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    };
+    let status = response.status();
+    if !status.is_success() {
+        return Err(anyhow!("WPAD server at {url} answered {status}"));
+    }
+    let response = response.text().await.map_err(|err| {
+        let err = if err.is_timeout() {
+            client_config.timeout_error()
+        } else {
+            anyhow::Error::new(err)
+        };
+        err.context(format!("could not read the WPAD file from {url}"))
+    })?;
+    if !ttl.is_zero() && !client_config.read_only {
+        if let Err(err) = db::save_wpad_cache(&db_path, url, &response).await {
+            output::detail(format!("Could not cache WPAD file: {err}"));
+        }
+    }
+    Ok(response)
+}
 
-    if proxies.is_empty() {
-        Err(anyhow!("Could not parse proxies from WPAD response"))
+/// How long to wait before WPAD retry number `attempt` (from 0):
+/// `base_ms * 2^attempt`, plus up to a quarter more as jitter so clients
+/// retrying together spread out.
+pub fn wpad_retry_delay(base_ms: u64, attempt: u32) -> Duration {
+    let delay = backoff_delay(Duration::from_millis(base_ms), attempt);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.subsec_nanos());
+    let jitter = nanos % (delay.as_millis() as u32 / 4 + 1);
+    delay + Duration::from_millis(jitter.into())
+}
+
+// `base * 2^attempt`, the back-off shared by WPAD retries and --wait-for-wpad.
+fn backoff_delay(base: Duration, attempt: u32) -> Duration {
+    base.saturating_mul(1 << attempt.min(16))
+}
+
+/// Fetch the WPAD file and describe its structure.
+pub async fn analyze_wpad(client_config: &WpadClientConfig) -> Result<PacAnalysis> {
+    let (_, response) = fetch_wpad(client_config).await?;
+    Ok(analyze_pac_structure(&response))
+}
+
+/// The WPAD URL and the proxies from its file that can be used: with the
+/// latency test, the reachable ones fastest first (an error when none is),
+/// otherwise all of them in PAC order. See [`detect_scored_candidates`].
+pub async fn detect_proxy_candidates(
+    client_config: &WpadClientConfig,
+    pac_url: Option<&str>,
+) -> Result<(String, Vec<String>)> {
+    let (url, candidates) = detect_scored_candidates(client_config, pac_url).await?;
+    Ok((url, reachable_hosts(&candidates)?))
+}
+
+/// The WPAD URL and every proxy from its file. Scripts defining `FindProxyForURL` are run
+/// for `pac_url` (default [`defaults::DEFAULT_PAC_EVALUATION_URL`]); other
+/// files, or scripts that fail or go DIRECT, fall back to every proxy in the
+/// text. With the latency test each is tested with a TCP connect allowed
+/// `detect_timeout_ms`: reachable ones fastest first, then unreachable ones
+/// in PAC order. Without it they keep PAC order and count as reachable.
+pub async fn detect_scored_candidates(
+    client_config: &WpadClientConfig,
+    pac_url: Option<&str>,
+) -> Result<(String, Vec<ProxyCandidate>)> {
+    let (url, proxies) = wpad_proxies(client_config, pac_url).await?;
+    let candidates = if client_config.latency_test {
+        score_candidates(proxies, config::get_detect_timeout()).await
     } else {
-        Ok(proxies)
+        proxies.into_iter().map(ProxyCandidate::untested).collect()
+    };
+    Ok((url, candidates))
+}
+
+// The WPAD URL and the proxies its PAC file lists, in PAC order.
+async fn wpad_proxies(
+    client_config: &WpadClientConfig,
+    pac_url: Option<&str>,
+) -> Result<(String, Vec<String>)> {
+    let (url, response) = fetch_wpad(client_config).await?;
+    let proxies = detect_proxy_candidates_async(&response, pac_url).await;
+    if proxies.is_empty() {
+        return Err(anyhow!("Could not parse proxies from WPAD response"));
+    }
+    Ok((url, proxies))
+}
+
+/// Time to open a TCP connection to a `host:port` proxy, or `None` when it
+/// can't be reached within `timeout`.
+pub async fn measure_proxy_latency(proxy_host: &str, timeout: Duration) -> Option<Duration> {
+    let address = proxy_host
+        .rsplit_once("://")
+        .map_or(proxy_host, |(_, rest)| rest)
+        .trim_end_matches('/');
+    let started = Instant::now();
+    match tokio::time::timeout(timeout, tokio::net::TcpStream::connect(address)).await {
+        Ok(Ok(_)) => Some(started.elapsed()),
+        _ => None,
+    }
+}
+
+/// Measure every candidate concurrently, one task each, and order them
+/// fastest first. Unreachable candidates go last, keeping their PAC order.
+pub async fn score_candidates(candidates: Vec<String>, timeout: Duration) -> Vec<ProxyCandidate> {
+    let tasks = candidates.iter().map(|candidate| {
+        let candidate = candidate.clone();
+        tokio::spawn(async move { measure_proxy_latency(&candidate, timeout).await })
+    });
+    let latencies: Vec<Option<Duration>> = futures::future::join_all(tasks)
+        .await
+        .into_iter()
+        .map(|latency| latency.ok().flatten())
+        .collect();
+
+    for (candidate, latency) in candidates.iter().zip(&latencies) {
+        match latency {
+            Some(latency) => output::detail(format!("{candidate}: {}ms", latency.as_millis())),
+            None => output::detail(format!("{candidate}: unreachable")),
+        }
+    }
+
+    let mut scored: Vec<ProxyCandidate> = candidates
+        .into_iter()
+        .zip(latencies)
+        .map(|(host, latency)| ProxyCandidate {
+            host,
+            latency_ms: latency.map(|latency| latency.as_millis() as u64),
+            reachable: latency.is_some(),
+        })
+        .collect();
+    scored.sort_by_key(|candidate| (!candidate.reachable, candidate.latency_ms));
+    scored
+}
+
+/// The WPAD URL and the proxies from its cached WPAD file, for the URL
+/// `wpad_discovery_method` finds (or, for HTTP discovery, its DHCP
+/// fallback), provided the file is younger than `wpad_cache_ttl_seconds`.
+/// Nothing is fetched; this is what `--no-wpad` uses.
+pub async fn cached_proxy_candidates() -> Result<(String, Vec<String>)> {
+    let method = config::get_wpad_discovery_method();
+    let wpad_url = discover_wpad(method)?;
+    let db_path = db::get_db_path();
+    if !Path::new(&db_path).exists() {
+        return Err(anyhow!("No cached WPAD file for {wpad_url}"));
+    }
+
+    let mut urls = vec![wpad_url.clone()];
+    if method == WpadDiscoveryMethod::Http {
+        urls.extend(discover_wpad_via_dhcp().ok().filter(|url| *url != wpad_url));
+    }
+    for url in urls {
+        let Some((response, fetched_at)) = db::load_wpad_cache(&db_path, &url).await? else {
+            continue;
+        };
+        if fetched_at.elapsed().unwrap_or_default() >= config::get_wpad_cache_ttl() {
+            return Err(anyhow!("Cached WPAD file for {url} has expired"));
+        }
+        let proxies = detect_proxy_candidates_async(&response, None).await;
+        if proxies.is_empty() {
+            return Err(anyhow!("Cached WPAD file for {url} has no proxies"));
+        }
+        return Ok((url, proxies));
     }
+    Err(anyhow!("No cached WPAD file for {wpad_url}"))
+}
+
+/// Delete the cached WPAD files that [`fetch_wpad`] and `--no-wpad` reuse.
+/// Returns whether there was anything cached.
+pub async fn flush_wpad_cache() -> Result<bool> {
+    let db_path = db::get_db_path();
+    if !Path::new(&db_path).exists() {
+        return Ok(false);
+    }
+    Ok(db::clear_wpad_cache(&db_path).await? > 0)
+}
+
+/// Retry [`detect_proxy_candidates`] with exponential back-off (1s, 2s, 4s, ...)
+/// until it succeeds or `timeout` has elapsed. Each attempt is a single
+/// request; this loop replaces the per-request retries.
+pub async fn wait_for_proxy_candidates(
+    timeout: Duration,
+    client_config: &WpadClientConfig,
+) -> Result<(String, Vec<String>)> {
+    let client_config = &WpadClientConfig {
+        max_retries: 0,
+        ..*client_config
+    };
+    let (enabled, _) = config::get_wpad_config()?;
+    if !enabled {
+        return detect_proxy_candidates(client_config, None).await;
+    }
+
+    let max_attempts = wpad_attempts_within(timeout);
+    let started = Instant::now();
+    let mut attempt = 1;
+    loop {
+        output::info(format!(
+            "Waiting for WPAD... (attempt {attempt}/{max_attempts}, {}s elapsed)",
+            started.elapsed().as_secs()
+        ));
+        let err = match detect_proxy_candidates(client_config, None).await {
+            Ok(proxies) => return Ok(proxies),
+            Err(err) => err,
+        };
+
+        let remaining = timeout.saturating_sub(started.elapsed());
+        if attempt >= max_attempts || remaining.is_zero() {
+            return Err(err.context(format!(
+                "WPAD did not respond within {}s",
+                timeout.as_secs()
+            )));
+        }
+        let delay = backoff_delay(WPAD_WAIT_BACKOFF_BASE, attempt - 1);
+        tokio::time::sleep(delay.min(remaining)).await;
+        attempt += 1;
+    }
+}
+
+// One attempt up front plus one after each back-off sleep that starts before
+// the timeout; the last sleep is cut short to end at the timeout.
+fn wpad_attempts_within(timeout: Duration) -> u32 {
+    let mut attempts = 1;
+    let mut elapsed = Duration::ZERO;
+    while elapsed < timeout {
+        elapsed += backoff_delay(WPAD_WAIT_BACKOFF_BASE, attempts - 1).min(timeout - elapsed);
+        attempts += 1;
+    }
+    attempts
+}
+
+/// Parse proxy candidates on the blocking pool so that heavier PAC
+/// evaluation never stalls the async runtime.
+pub async fn detect_proxy_candidates_async(response: &str, pac_url: Option<&str>) -> Vec<String> {
+    let response = response.to_string();
+    let pac_url = pac_url
+        .unwrap_or(defaults::DEFAULT_PAC_EVALUATION_URL)
+        .to_string();
+    match tokio::task::spawn_blocking(move || evaluate_or_parse_candidates(&response, &pac_url))
+        .await
+    {
+        Ok(proxies) => proxies,
+        Err(err) => match err.try_into_panic() {
+            Ok(payload) => std::panic::resume_unwind(payload),
+            Err(_) => Vec::new(),
+        },
+    }
+}
+
+fn evaluate_or_parse_candidates(response: &str, pac_url: &str) -> Vec<String> {
+    let function_re = Regex::new(FIND_PROXY_FUNCTION_REGEX).expect("invalid FindProxyForURL regex");
+    if function_re.is_match(response) {
+        match detect_proxy_for_url(response, pac_url) {
+            Ok(proxies) if !proxies.is_empty() => return proxies,
+            Ok(_) => output::detail(format!(
+                "PAC file returns DIRECT for {pac_url}; using every proxy it lists"
+            )),
+            Err(err) => output::detail(format!(
+                "Could not evaluate PAC file ({err}); using every proxy it lists"
+            )),
+        }
+    }
+    detect_proxy_candidates_from_response(response)
+}
+
+/// Run the PAC script's `FindProxyForURL` for `url` and return the proxies
+/// it answers with, in order. DIRECT entries are dropped.
+pub fn detect_proxy_for_url(pac_text: &str, url: &str) -> Result<Vec<String>> {
+    let host = reqwest::Url::parse(url)
+        .ok()
+        .and_then(|parsed| parsed.host_str().map(str::to_string))
+        .ok_or_else(|| anyhow!("'{url}' is not a URL with a host"))?;
+
+    let runtime = rquickjs::Runtime::new()?;
+    let deadline = Instant::now() + Duration::from_millis(defaults::PAC_EVALUATION_TIMEOUT_MS);
+    runtime.set_interrupt_handler(Some(Box::new(move || Instant::now() > deadline)));
+    runtime.set_memory_limit(defaults::PAC_EVALUATION_MEMORY_LIMIT_BYTES);
+    let context = rquickjs::Context::full(&runtime)?;
+
+    let result: Result<String> = context.with(|ctx| {
+        let describe = |err: rquickjs::Error| {
+            let exception = ctx.catch();
+            let message = exception
+                .as_exception()
+                .and_then(|exception| exception.message())
+                .unwrap_or_else(|| err.to_string());
+            anyhow!("PAC script error: {message}")
+        };
+        let globals = ctx.globals();
+        globals
+            .set(
+                "__proxyctlResolve",
+                rquickjs::Function::new(ctx.clone(), pac_dns_resolve)?,
+            )
+            .map_err(describe)?;
+        globals
+            .set(
+                "__proxyctlMyIp",
+                rquickjs::Function::new(ctx.clone(), pac_my_ip_address)?,
+            )
+            .map_err(describe)?;
+        ctx.eval::<(), _>(PAC_PRELUDE).map_err(describe)?;
+        ctx.eval::<(), _>(pac_text).map_err(describe)?;
+        let find_proxy: rquickjs::Function = ctx
+            .globals()
+            .get("FindProxyForURL")
+            .map_err(|_| anyhow!("PAC script does not define FindProxyForURL"))?;
+        find_proxy.call((url, host.as_str())).map_err(describe)
+    });
+
+    Ok(detect_proxy_candidates_from_response(&result?))
+}
+
+// PAC `dnsResolve`: the first IPv4 address of `host`, or null.
+fn pac_dns_resolve(host: String) -> Option<String> {
+    if let Ok(ip) = host.parse::<std::net::Ipv4Addr>() {
+        return Some(ip.to_string());
+    }
+    (host.as_str(), 0)
+        .to_socket_addrs()
+        .ok()?
+        .find(|address| address.is_ipv4())
+        .map(|address| address.ip().to_string())
+}
+
+// PAC `myIpAddress`: the address of the interface the default route uses.
+// Connecting a UDP socket only picks a route; nothing is sent.
+fn pac_my_ip_address() -> String {
+    std::net::UdpSocket::bind("0.0.0.0:0")
+        .and_then(|socket| {
+            socket.connect(defaults::PAC_ROUTE_PROBE_ADDRESS)?;
+            socket.local_addr()
+        })
+        .map(|address| address.ip())
+        .ok()
+        .filter(|ip| !ip.is_unspecified())
+        .map_or_else(|| "127.0.0.1".to_string(), |ip| ip.to_string())
 }
 
 fn detect_proxy_candidates_from_response(response: &str) -> Vec<String> {
@@ -65,9 +870,190 @@ fn detect_proxy_candidates_from_response(response: &str) -> Vec<String> {
         .collect()
 }
 
+/// Count the distinct proxies in a PAC file, the regions their host names
+/// point at, and whether it falls back to DIRECT or defines FindProxyForURL.
+pub fn analyze_pac_structure(content: &str) -> PacAnalysis {
+    let region_re = Regex::new(PROXY_REGION_REGEX).expect("invalid proxy region regex");
+    let direct_re = Regex::new(r"\bDIRECT\b").expect("invalid DIRECT regex");
+    let function_re = Regex::new(FIND_PROXY_FUNCTION_REGEX).expect("invalid FindProxyForURL regex");
+
+    let mut proxies: Vec<String> = Vec::new();
+    let mut regions: Vec<String> = Vec::new();
+    for proxy in detect_proxy_candidates_from_response(content) {
+        let host = proxy
+            .rsplit_once("://")
+            .map_or(proxy.as_str(), |(_, rest)| rest);
+        let label = host.split(['.', ':']).next().unwrap_or_default();
+        if let Some(region) = region_re.captures(label).and_then(|caps| caps.get(1)) {
+            let region = region.as_str().to_ascii_lowercase();
+            if !regions.contains(&region) {
+                regions.push(region);
+            }
+        }
+        if !proxies.contains(&proxy) {
+            proxies.push(proxy);
+        }
+    }
+
+    PacAnalysis {
+        proxy_count: proxies.len(),
+        has_direct_fallback: direct_re.is_match(content),
+        regions,
+        is_function_based: function_re.is_match(content),
+    }
+}
+
 #[cfg(test)]
 mod detect_tests {
-    use super::detect_proxy_candidates_from_response;
+    use super::{
+        analyze_pac_structure, best_candidate, detect_proxy_candidates_async,
+        detect_proxy_candidates_from_response, detect_proxy_for_url, measure_proxy_latency,
+        score_candidates, wpad_attempts_within, wpad_retry_delay, ProxyCandidate, WpadClientConfig,
+    };
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    // A port nothing listens on: bind one, then release it.
+    async fn closed_port() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn latency_is_measured_for_reachable_proxies_only() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let timeout = Duration::from_secs(1);
+
+        assert!(measure_proxy_latency(&address, timeout).await.is_some());
+        assert!(
+            measure_proxy_latency(&format!("http://{address}/"), timeout)
+                .await
+                .is_some()
+        );
+        assert_eq!(
+            measure_proxy_latency(&closed_port().await, timeout).await,
+            None
+        );
+        assert_eq!(measure_proxy_latency("not a host", timeout).await, None);
+    }
+
+    #[tokio::test]
+    async fn candidates_are_ranked_fastest_first_with_unreachable_last() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let reachable = listener.local_addr().unwrap().to_string();
+        let first_closed = closed_port().await;
+        let second_closed = closed_port().await;
+
+        let ranked: Vec<String> = score_candidates(
+            vec![
+                first_closed.clone(),
+                reachable.clone(),
+                second_closed.clone(),
+            ],
+            Duration::from_secs(1),
+        )
+        .await
+        .into_iter()
+        .map(|candidate| candidate.host)
+        .collect();
+        assert_eq!(ranked, vec![reachable, first_closed, second_closed]);
+    }
+
+    #[tokio::test]
+    async fn scored_candidates_record_latency_and_reachability() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let reachable = listener.local_addr().unwrap().to_string();
+        let closed = closed_port().await;
+
+        let scored = score_candidates(
+            vec![closed.clone(), reachable.clone()],
+            Duration::from_secs(1),
+        )
+        .await;
+        assert_eq!(scored[0].host, reachable);
+        assert!(scored[0].reachable);
+        assert!(scored[0].latency_ms.is_some());
+        assert_eq!(
+            scored[1],
+            ProxyCandidate {
+                host: closed,
+                latency_ms: None,
+                reachable: false,
+            }
+        );
+        assert_eq!(best_candidate(&scored).unwrap(), reachable);
+        assert!(best_candidate(&scored[1..]).is_err());
+    }
+
+    #[test]
+    fn latency_test_can_be_skipped() {
+        assert!(WpadClientConfig::default().latency_test);
+        let config = WpadClientConfig::builder().skip_latency_test(true).build();
+        assert!(!config.latency_test);
+    }
+
+    #[test]
+    fn wpad_client_builder_prefers_explicit_timeouts() {
+        let config = WpadClientConfig::builder()
+            .connect_timeout_ms(Some(250))
+            .read_timeout_ms(Some(750))
+            .build();
+        assert_eq!(
+            config,
+            WpadClientConfig {
+                connect_timeout_ms: 250,
+                read_timeout_ms: 750,
+                latency_test: true,
+                refresh: false,
+                ..WpadClientConfig::default()
+            }
+        );
+    }
+
+    #[test]
+    fn wpad_requests_default_to_a_five_second_overall_timeout() {
+        assert_eq!(WpadClientConfig::default().timeout_ms, 5_000);
+        let config = WpadClientConfig::builder().timeout_ms(Some(2_000)).build();
+        assert_eq!(config.timeout_ms, 2_000);
+    }
+
+    #[test]
+    fn no_retry_makes_a_single_wpad_request() {
+        assert_eq!(WpadClientConfig::default().max_retries, 3);
+        let config = WpadClientConfig::builder().no_retry(true).build();
+        assert_eq!(config.max_retries, 0);
+    }
+
+    #[test]
+    fn wpad_retry_delay_doubles_with_up_to_a_quarter_of_jitter() {
+        for (attempt, base) in [(0, 500), (1, 1_000), (2, 2_000)] {
+            let delay = wpad_retry_delay(500, attempt).as_millis() as u64;
+            assert!(
+                (base..=base + base / 4).contains(&delay),
+                "{attempt}: {delay}"
+            );
+        }
+        assert_eq!(wpad_retry_delay(0, 3), Duration::ZERO);
+    }
+
+    #[test]
+    fn wpad_attempts_follow_exponential_back_off() {
+        assert_eq!(wpad_attempts_within(Duration::ZERO), 1);
+        assert_eq!(wpad_attempts_within(Duration::from_secs(1)), 2);
+        assert_eq!(wpad_attempts_within(Duration::from_secs(3)), 3);
+        assert_eq!(wpad_attempts_within(Duration::from_secs(5)), 4);
+        assert_eq!(wpad_attempts_within(Duration::from_secs(7)), 4);
+    }
+
+    #[tokio::test]
+    async fn async_variant_matches_sync_parsing() {
+        let body = r#"return "PROXY a.example.com:8080; SOCKS5 b.example.com:1080; DIRECT";"#;
+
+        let proxies = detect_proxy_candidates_async(body, None).await;
+        assert_eq!(proxies, detect_proxy_candidates_from_response(body));
+        assert_eq!(proxies, vec!["a.example.com:8080", "b.example.com:1080"]);
+    }
 
     #[test]
     fn parses_proxies_from_variable_assignment() {
@@ -94,6 +1080,93 @@ mod detect_tests {
         assert_eq!(proxies, vec!["proxy-eu.example.net:3128".to_string()]);
     }
 
+    const GUARDED_PAC: &str = r#"
+        function FindProxyForURL(url, host) {
+            if (isPlainHostName(host) || dnsDomainIs(host, ".corp.example.com")) {
+                return "DIRECT";
+            }
+            if (isInNet(host, "10.0.0.0", "255.0.0.0")) {
+                return "DIRECT";
+            }
+            if (shExpMatch(url, "https://*.eu.example.org/*")) {
+                return "PROXY proxy-eu.example.com:8080; DIRECT";
+            }
+            return "PROXY proxy-us.example.com:8080; PROXY proxy-backup.example.com:8080; DIRECT";
+        }
+    "#;
+
+    #[test]
+    fn pac_helpers_resolve_names_and_the_client_address() {
+        let pac = r#"
+            function FindProxyForURL(url, host) {
+                var me = myIpAddress();
+                if (dnsResolve(host) === "127.0.0.1" && isInNet(me, me, "255.255.255.255")) {
+                    return "PROXY local.example.com:8080";
+                }
+                return "PROXY other.example.com:8080";
+            }
+        "#;
+        assert_eq!(
+            detect_proxy_for_url(pac, "http://localhost/").unwrap(),
+            vec!["local.example.com:8080"]
+        );
+    }
+
+    #[test]
+    fn pac_scripts_cannot_allocate_without_bound() {
+        let pac = r#"
+            function FindProxyForURL(url, host) {
+                var chunks = [];
+                for (;;) { chunks.push(new Array(1 << 20).fill(1)); }
+            }
+        "#;
+        assert!(detect_proxy_for_url(pac, "http://example.com/").is_err());
+    }
+
+    #[test]
+    fn evaluates_find_proxy_for_url_guards() {
+        let proxy = |url: &str| detect_proxy_for_url(GUARDED_PAC, url).unwrap();
+        assert!(proxy("http://intranet/").is_empty());
+        assert!(proxy("http://wiki.corp.example.com/").is_empty());
+        assert!(proxy("http://10.1.2.3/").is_empty());
+        assert_eq!(
+            proxy("https://shop.eu.example.org/cart"),
+            vec!["proxy-eu.example.com:8080"]
+        );
+        assert_eq!(
+            proxy("https://example.com/"),
+            vec!["proxy-us.example.com:8080", "proxy-backup.example.com:8080"]
+        );
+    }
+
+    #[test]
+    fn pac_evaluation_reports_script_errors() {
+        let err =
+            detect_proxy_for_url("function FindProxyForURL(url, host) {", "http://a.example/")
+                .unwrap_err();
+        assert!(err.to_string().starts_with("PAC script error"), "{err}");
+
+        let err = detect_proxy_for_url("var x = 1;", "http://a.example/").unwrap_err();
+        assert!(
+            err.to_string().contains("does not define FindProxyForURL"),
+            "{err}"
+        );
+
+        let looping = "function FindProxyForURL(url, host) { while (true) {} }";
+        assert!(detect_proxy_for_url(looping, "http://a.example/").is_err());
+        assert!(detect_proxy_for_url(GUARDED_PAC, "not a url").is_err());
+    }
+
+    #[tokio::test]
+    async fn candidates_prefer_evaluation_and_fall_back_to_the_text() {
+        let evaluated =
+            detect_proxy_candidates_async(GUARDED_PAC, Some("https://a.eu.example.org/")).await;
+        assert_eq!(evaluated, vec!["proxy-eu.example.com:8080"]);
+
+        let direct = detect_proxy_candidates_async(GUARDED_PAC, Some("http://intranet/")).await;
+        assert_eq!(direct, detect_proxy_candidates_from_response(GUARDED_PAC));
+    }
+
     #[test]
     fn ignores_direct_entries() {
         let body = r#"
@@ -103,4 +1176,44 @@ mod detect_tests {
         let proxies = detect_proxy_candidates_from_response(body);
         assert!(proxies.is_empty());
     }
+
+    #[test]
+    fn analyzes_function_based_pac_with_regions_and_direct() {
+        let body = r#"
+            function FindProxyForURL(url, host) {
+                if (shExpMatch(host, "*.eu.example.com")) {
+                    return "PROXY proxy-eu.example.com:8080; PROXY proxy-us.example.com:8080; DIRECT";
+                }
+                return "PROXY proxy-us.example.com:8080; PROXY proxy-backup.example.com:8080; DIRECT";
+            }
+        "#;
+
+        let analysis = analyze_pac_structure(body);
+        assert!(analysis.is_function_based);
+        assert!(analysis.has_direct_fallback);
+        assert_eq!(analysis.proxy_count, 3);
+        assert_eq!(analysis.regions, vec!["eu", "us"]);
+    }
+
+    #[test]
+    fn analyzes_variable_based_pac_without_direct() {
+        let body = r#"
+            var proxies = "PROXY proxy-apac2.example.com:3128; SOCKS5 socks.example.com:1080";
+        "#;
+
+        let analysis = analyze_pac_structure(body);
+        assert!(!analysis.is_function_based);
+        assert!(!analysis.has_direct_fallback);
+        assert_eq!(analysis.proxy_count, 2);
+        assert_eq!(analysis.regions, vec!["apac"]);
+    }
+
+    #[test]
+    fn pac_without_regional_hosts_reports_no_regions() {
+        let analysis =
+            analyze_pac_structure(r#"function FindProxyForURL(u, h) { return "DIRECT"; }"#);
+        assert_eq!(analysis.proxy_count, 0);
+        assert!(analysis.regions.is_empty());
+        assert!(analysis.render().contains("Regions: none detected"));
+    }
 }
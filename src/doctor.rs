@@ -1,11 +1,15 @@
-use crate::{config, db};
+use crate::output::ColorMode;
+use crate::{config, conflicts, db, defaults, export, gnome, output, proxy, systemctl};
 use anyhow::{anyhow, Context, Result};
 use colored::{ColoredString, Colorize};
+use futures::future::BoxFuture;
 use serde::Serialize;
 use serde_json::Value as JsonValue;
 use std::collections::{BTreeMap, HashSet};
 use std::fs;
+use std::net::{IpAddr, Ipv4Addr};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use toml::{map::Map as TomlMap, to_string_pretty, Value as TomlValue};
 
 struct DoctorSummary {
@@ -13,6 +17,65 @@ struct DoctorSummary {
     healthy: bool,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Ok,
+    Warn,
+    Err,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    pub name: String,
+    pub status: CheckStatus,
+    pub message: String,
+}
+
+impl CheckResult {
+    fn from_result(name: &str, result: Result<String>) -> Self {
+        match result {
+            Ok(message) => Self {
+                name: name.to_string(),
+                status: CheckStatus::Ok,
+                message,
+            },
+            Err(err) => Self {
+                name: name.to_string(),
+                status: CheckStatus::Err,
+                message: format!("{err:#}"),
+            },
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let label = match self.status {
+            CheckStatus::Ok => "OK".green(),
+            CheckStatus::Warn => "WARN".yellow(),
+            CheckStatus::Err => "ERR".red(),
+        };
+        format!("{}: {} - {}", self.name.bold(), label, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SystemInfo {
+    pub os: String,
+    pub shell: Option<String>,
+    pub home: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiagnosticReport {
+    pub timestamp: String,
+    pub tool_version: &'static str,
+    pub config: config::AppConfig,
+    pub env_state: db::EnvState,
+    pub ssh_status: config::SshStatus,
+    pub doctor_checks: Vec<CheckResult>,
+    pub system_info: SystemInfo,
+}
+
 pub async fn run() -> Result<()> {
     let summary = evaluate().await?;
 
@@ -27,30 +90,933 @@ pub async fn run() -> Result<()> {
     }
 }
 
-async fn evaluate() -> Result<DoctorSummary> {
-    let mut lines = Vec::new();
-    let mut healthy = true;
+/// A problem `doctor run --fix` knows how to repair.
+pub trait DoctorFix {
+    /// What applying the fix does, shown after "Fix applied:".
+    fn description(&self) -> String;
+    /// Whether the problem is present, so the fix should be applied.
+    fn can_fix(&self) -> bool;
+    /// Boxed so fixes can await the database on the caller's runtime.
+    fn apply_fix(&self) -> BoxFuture<'_, Result<()>>;
+}
+
+struct ConfigFileFix {
+    path: PathBuf,
+}
+
+impl DoctorFix for ConfigFileFix {
+    fn description(&self) -> String {
+        format!("created {}", self.path.display())
+    }
+
+    fn can_fix(&self) -> bool {
+        !self.path.exists()
+    }
+
+    fn apply_fix(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { config::initialize_config() })
+    }
+}
+
+struct HostsFileFix {
+    path: PathBuf,
+}
+
+impl DoctorFix for HostsFileFix {
+    fn description(&self) -> String {
+        format!("created an empty hosts file at {}", self.path.display())
+    }
+
+    fn can_fix(&self) -> bool {
+        !self.path.exists()
+    }
+
+    fn apply_fix(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { config::create_empty_hosts_file(&self.path) })
+    }
+}
+
+struct DatabaseFix {
+    db_path: String,
+    broken: bool,
+}
+
+impl DoctorFix for DatabaseFix {
+    fn description(&self) -> String {
+        format!("recreated the missing tables in {}", self.db_path)
+    }
 
-    match check_config() {
-        Ok(message) => lines.push(format!("{}: {} - {message}", "Config".bold(), "OK".green())),
-        Err(err) => {
-            lines.push(format!("{}: {} - {err}", "Config".bold(), "ERR".red()));
-            healthy = false;
+    fn can_fix(&self) -> bool {
+        self.broken
+    }
+
+    fn apply_fix(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(db::init_db(&self.db_path))
+    }
+}
+
+struct ProfileBlockFix {
+    state: db::EnvState,
+    missing: bool,
+}
+
+impl DoctorFix for ProfileBlockFix {
+    fn description(&self) -> String {
+        "restored the proxy settings block in the shell profiles".to_string()
+    }
+
+    fn can_fix(&self) -> bool {
+        self.missing
+    }
+
+    fn apply_fix(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { proxy::persist_proxy_settings(&self.state) })
+    }
+}
+
+/// Every fix doctor knows about, each already checked against the current
+/// files and database.
+pub async fn collect_fixes() -> Vec<Box<dyn DoctorFix>> {
+    let mut fixes: Vec<Box<dyn DoctorFix>> = Vec::new();
+
+    if let Ok(config_dir) = config::get_config_dir() {
+        fixes.push(Box::new(ConfigFileFix {
+            path: config_dir.join("config.toml"),
+        }));
+    }
+    for path in config::get_local_hosts_file_paths().unwrap_or_default() {
+        fixes.push(Box::new(HostsFileFix { path }));
+    }
+
+    let db_path = db::get_db_path();
+    let broken = db::verify_schema(&db_path).await.is_err();
+    let state = if broken {
+        db::EnvState::default()
+    } else {
+        db::load_env_state(&db_path).await.unwrap_or_default()
+    };
+    fixes.push(Box::new(DatabaseFix { db_path, broken }));
+
+    let has_proxy = [&state.http_proxy, &state.https_proxy, &state.all_proxy]
+        .iter()
+        .any(|value| value.as_deref().is_some_and(|value| !value.is_empty()));
+    let writes_profiles = config::load_config()
+        .map(|config| config.write_shell_profiles.unwrap_or(true))
+        .unwrap_or(true);
+    let missing =
+        has_proxy && writes_profiles && matches!(proxy::profiles_have_managed_block(), Ok(false));
+    fixes.push(Box::new(ProfileBlockFix { state, missing }));
+
+    fixes
+}
+
+/// Apply every fix whose problem is present, then run the usual checks.
+/// Fails when a fix could not be applied or a check still reports an error.
+pub async fn run_with_fixes() -> Result<()> {
+    let mut failed = 0;
+    for fix in collect_fixes().await {
+        if !fix.can_fix() {
+            continue;
+        }
+        match fix.apply_fix().await {
+            Ok(()) => println!("{} {}", "Fix applied:".green(), fix.description()),
+            Err(err) => {
+                failed += 1;
+                println!("{} {}: {err:#}", "Fix failed:".red(), fix.description());
+            }
         }
     }
 
-    match check_database().await {
-        Ok(message) => lines.push(format!(
-            "{}: {} - {message}",
-            "Database".bold(),
-            "OK".green()
-        )),
-        Err(err) => {
-            lines.push(format!("{}: {} - {err}", "Database".bold(), "ERR".red()));
-            healthy = false;
+    run().await?;
+    if failed > 0 {
+        return Err(anyhow!("{failed} fix(es) could not be applied"));
+    }
+    Ok(())
+}
+
+async fn collect_checks() -> Vec<CheckResult> {
+    let mut checks = vec![
+        CheckResult::from_result("Config", check_config()),
+        CheckResult::from_result("Database", check_database().await),
+        proxy_reachable_check().await,
+        profile_check(),
+        check_shell_profiles().await,
+        ssh_permission_check(),
+        credentials_permission_check(),
+        no_proxy_coverage_check(),
+        check_git_proxy().await,
+    ];
+    checks.extend(unknown_key_check());
+    checks.extend(system_proxy_check());
+    checks.extend(gnome_proxy_check().await);
+    if config::get_proxy_scope() == config::ProxyScope::System {
+        checks.push(system_scope_check(Path::new("/")));
+    }
+    checks.extend(env_consistency_checks().await);
+    checks.extend(conflict_checks(conflicts::find_conflicting_tools()));
+    checks
+}
+
+/// Open a TCP connection to the proxy stored in the database, preferring
+/// `https_proxy` over `http_proxy`.
+pub async fn check_proxy_reachable() -> Result<String> {
+    let state = db::load_env_state(&db::get_db_path()).await?;
+    let Some(url) = [state.https_proxy, state.http_proxy]
+        .into_iter()
+        .flatten()
+        .find(|value| !value.trim().is_empty())
+    else {
+        return Ok("no proxy configured".to_string());
+    };
+    let host = proxy::extract_proxy_host(&url).ok_or_else(|| {
+        anyhow!(
+            "cannot find a host in {}",
+            proxy::mask_proxy_credentials(&url)
+        )
+    })?;
+
+    let timeout = Duration::from_secs(defaults::PROXY_REACHABILITY_TIMEOUT_SECONDS);
+    match tokio::time::timeout(timeout, tokio::net::TcpStream::connect(&host)).await {
+        Ok(Ok(_)) => Ok(format!("reachable at {host}")),
+        _ => Err(anyhow!("cannot connect to {host} (proxy may be offline)")),
+    }
+}
+
+// An unreachable proxy is only a warning: the network may be down for now.
+async fn proxy_reachable_check() -> CheckResult {
+    match check_proxy_reachable().await {
+        Ok(message) => CheckResult::from_result("Proxy", Ok(message)),
+        Err(err) => CheckResult {
+            name: "Proxy".to_string(),
+            status: CheckStatus::Warn,
+            message: format!("{err:#}"),
+        },
+    }
+}
+
+/// Compare git's global `http.proxy` with the stored proxy. Only a value
+/// set in git can disagree; none set, or git missing, is fine.
+pub async fn check_git_proxy() -> CheckResult {
+    let name = "Git";
+    let git_proxy = match export::git_proxy(export::GitScope::Global) {
+        Ok(Some(value)) => value,
+        Ok(None) => {
+            return CheckResult::from_result(name, Ok("no http.proxy set in git".to_string()))
+        }
+        Err(err) => return CheckResult::from_result(name, Ok(format!("skipped: {err:#}"))),
+    };
+    let state = db::load_env_state(&db::get_db_path())
+        .await
+        .unwrap_or_default();
+    let stored = export::stored_proxy_values(&state)
+        .ok()
+        .map(|(http, _)| http);
+
+    let show = proxy::mask_proxy_credentials;
+    let warning = match &stored {
+        Some(stored) if *stored == git_proxy => {
+            return CheckResult::from_result(
+                name,
+                Ok(format!("http.proxy matches {}", show(stored))),
+            )
+        }
+        Some(stored) => format!(
+            "git http.proxy is {} but the stored proxy is {}; run `proxyctl-rs export git`",
+            show(&git_proxy),
+            show(stored)
+        ),
+        None => format!(
+            "git http.proxy is {} but no proxy is enabled; run `proxyctl-rs unexport git`",
+            show(&git_proxy)
+        ),
+    };
+    CheckResult {
+        name: name.to_string(),
+        status: CheckStatus::Warn,
+        message: warning,
+    }
+}
+
+// With `manage_gnome_proxy` on, whether GNOME's proxy matches the stored one.
+async fn gnome_proxy_check() -> Option<CheckResult> {
+    if !config::get_manage_gnome_proxy() {
+        return None;
+    }
+    let state = db::load_env_state(&db::get_db_path())
+        .await
+        .unwrap_or_default();
+    Some(check_gnome_proxy_with(&gnome::GSettingsCli, &state))
+}
+
+/// Compare the GNOME proxy read through `backend` with the proxy stored in
+/// `state`, preferring `http_proxy` over `https_proxy`.
+pub fn check_gnome_proxy_with<B>(backend: &B, state: &db::EnvState) -> CheckResult
+where
+    B: gnome::GSettingsBackend + ?Sized,
+{
+    let name = "GNOME proxy";
+    if !backend.is_available() {
+        return CheckResult {
+            name: name.to_string(),
+            status: CheckStatus::Warn,
+            message: "manage_gnome_proxy is set but gsettings is not installed".to_string(),
+        };
+    }
+    let expected = export::stored_proxy_values(state)
+        .ok()
+        .and_then(|(http, _)| proxy::proxy_host_and_port(&http));
+    let expected_ref = expected.as_ref().map(|(host, port)| (host.as_str(), *port));
+    match gnome::proxy_mismatch_with(backend, expected_ref) {
+        Ok(None) => CheckResult::from_result(
+            name,
+            Ok(match &expected {
+                Some((host, port)) => format!("GNOME uses {host}:{port}"),
+                None => "GNOME proxy is off".to_string(),
+            }),
+        ),
+        Ok(Some(mismatch)) => CheckResult {
+            name: name.to_string(),
+            status: CheckStatus::Warn,
+            message: format!("{mismatch}; run `proxyctl-rs on` or `proxyctl-rs off` to resync"),
+        },
+        Err(err) => CheckResult::from_result(name, Err(err)),
+    }
+}
+
+// On macOS, whether `networksetup` runs and knows the configured network
+// service. Elsewhere only a `manage_system_proxy` that can't apply is reported.
+fn system_proxy_check() -> Option<CheckResult> {
+    let name = "System proxy";
+    let network_service = config::get_managed_network_service();
+    if !cfg!(target_os = "macos") {
+        return network_service.map(|_| CheckResult {
+            name: name.to_string(),
+            status: CheckStatus::Warn,
+            message: "manage_system_proxy is set but only applies on macOS".to_string(),
+        });
+    }
+
+    let services = match systemctl::list_macos_network_services() {
+        Ok(services) => services,
+        Err(err) => return Some(CheckResult::from_result(name, Err(err))),
+    };
+    let Some(network_service) = network_service else {
+        return Some(CheckResult::from_result(
+            name,
+            Ok("networksetup is available".to_string()),
+        ));
+    };
+    Some(if services.contains(&network_service) {
+        CheckResult::from_result(name, Ok(format!("networksetup manages {network_service}")))
+    } else {
+        CheckResult {
+            name: name.to_string(),
+            status: CheckStatus::Warn,
+            message: format!(
+                "network service {network_service} not found; set macos_network_service to one of: {}",
+                services.join(", ")
+            ),
+        }
+    })
+}
+
+/// Check that every resolved shell profile has the managed block while a
+/// proxy is stored, and none has it once the proxy is disabled.
+pub async fn check_shell_profiles() -> CheckResult {
+    let result = async {
+        let app_config = config::load_config()?;
+        if !app_config.write_shell_profiles.unwrap_or(true)
+            || config::get_proxy_scope() == config::ProxyScope::System
+        {
+            return Ok("shell profiles are not written in this configuration".to_string());
+        }
+
+        let state = db::load_env_state(&db::get_db_path()).await?;
+        let enabled = [&state.http_proxy, &state.https_proxy, &state.all_proxy]
+            .iter()
+            .any(|value| value.as_deref().is_some_and(|value| !value.is_empty()));
+
+        for profile in proxy::resolve_shell_profiles()? {
+            let has_block = proxy::profile_has_managed_block(&profile)?;
+            if enabled && !has_block {
+                return Err(anyhow!("managed block missing from {}", profile.display()));
+            }
+            if !enabled && has_block {
+                return Err(anyhow!(
+                    "managed block left in {} although the proxy is disabled",
+                    profile.display()
+                ));
+            }
+        }
+
+        Ok(if enabled {
+            "managed block present in every shell profile".to_string()
+        } else {
+            "no managed block while the proxy is disabled".to_string()
+        })
+    }
+    .await;
+    CheckResult::from_result("Profile", result)
+}
+
+fn profile_check() -> CheckResult {
+    let checks = match proxy::check_profile_writability() {
+        Ok(checks) => checks,
+        Err(err) => return CheckResult::from_result("Profiles", Err(err)),
+    };
+
+    let read_only: Vec<String> = checks
+        .iter()
+        .filter(|check| !check.writable)
+        .map(|check| check.path.display().to_string())
+        .collect();
+
+    if read_only.is_empty() {
+        CheckResult {
+            name: "Profiles".to_string(),
+            status: CheckStatus::Ok,
+            message: format!("{} existing shell profile(s) writable", checks.len()),
+        }
+    } else {
+        CheckResult {
+            name: "Profiles".to_string(),
+            status: CheckStatus::Warn,
+            message: format!("not writable: {}", read_only.join(", ")),
+        }
+    }
+}
+
+fn unknown_key_check() -> Option<CheckResult> {
+    let config_file = config::get_config_dir().ok()?.join("config.toml");
+    let warnings = config::validate_config_file(&config_file).ok()?;
+    (!warnings.is_empty()).then(|| CheckResult {
+        name: "Config keys".to_string(),
+        status: CheckStatus::Warn,
+        message: warnings.join("; "),
+    })
+}
+
+/// With `default_proxy_scope = "system"`, every system profile under `root`
+/// must be writable for `proxy on` to succeed.
+pub fn system_scope_check(root: &Path) -> CheckResult {
+    let read_only: Vec<String> = proxy::check_system_writability_in(root)
+        .iter()
+        .filter(|check| !check.writable)
+        .map(|check| check.path.display().to_string())
+        .collect();
+
+    if read_only.is_empty() {
+        CheckResult {
+            name: "System scope".to_string(),
+            status: CheckStatus::Ok,
+            message: "system-wide profiles writable".to_string(),
+        }
+    } else {
+        CheckResult {
+            name: "System scope".to_string(),
+            status: CheckStatus::Warn,
+            message: format!("not writable without sudo: {}", read_only.join(", ")),
+        }
+    }
+}
+
+/// Permission changes made (or, before fixing, planned) by
+/// `doctor fix-ssh-permissions`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FixReport {
+    pub actions: Vec<String>,
+}
+
+const SSH_DIR_MODE: u32 = 0o700;
+const SSH_FILE_MODE: u32 = 0o600;
+const CREDENTIALS_FILE_MODE: u32 = 0o600;
+
+#[cfg(unix)]
+fn permission_mode(path: &Path) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path)
+        .ok()
+        .map(|metadata| metadata.permissions().mode() & 0o777)
+}
+
+#[cfg(not(unix))]
+fn permission_mode(_path: &Path) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn set_permission_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set permissions on {}", path.display()))
+}
+
+#[cfg(not(unix))]
+fn set_permission_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+// The SSH directory, the SSH config and every `*.bak` next to it that group
+// or others can access, with their current and expected modes.
+fn loose_ssh_permissions(ssh_dir: &Path, ssh_config: &Path) -> Result<Vec<(PathBuf, u32, u32)>> {
+    let mut targets = vec![
+        (ssh_dir.to_path_buf(), SSH_DIR_MODE),
+        (ssh_config.to_path_buf(), SSH_FILE_MODE),
+    ];
+    if ssh_dir.is_dir() {
+        let mut backups = Vec::new();
+        for entry in fs::read_dir(ssh_dir)
+            .with_context(|| format!("Failed to read {}", ssh_dir.display()))?
+        {
+            let path = entry?.path();
+            if path.is_file() && path.extension().is_some_and(|ext| ext == "bak") {
+                backups.push(path);
+            }
+        }
+        backups.sort();
+        targets.extend(backups.into_iter().map(|path| (path, SSH_FILE_MODE)));
+    }
+
+    Ok(targets
+        .into_iter()
+        .filter_map(|(path, wanted)| {
+            let current = permission_mode(&path)?;
+            (current & 0o077 != 0).then_some((path, current, wanted))
+        })
+        .collect())
+}
+
+fn describe_permission_fix(path: &Path, current: u32, wanted: u32) -> String {
+    format!("chmod {wanted:o} {} (was {current:o})", path.display())
+}
+
+/// What `fix_ssh_permissions` would change, without touching anything.
+pub fn plan_ssh_permission_fixes(ssh_dir: &Path, ssh_config: &Path) -> Result<FixReport> {
+    let actions = loose_ssh_permissions(ssh_dir, ssh_config)?
+        .iter()
+        .map(|(path, current, wanted)| describe_permission_fix(path, *current, *wanted))
+        .collect();
+    Ok(FixReport { actions })
+}
+
+/// Restrict `ssh_dir` to 0700 and `ssh_config` plus any `*.bak` files in
+/// `ssh_dir` to 0600, leaving paths that are already private alone.
+pub fn fix_ssh_permissions(ssh_dir: &Path, ssh_config: &Path) -> Result<FixReport> {
+    let mut report = FixReport::default();
+    for (path, current, wanted) in loose_ssh_permissions(ssh_dir, ssh_config)? {
+        set_permission_mode(&path, wanted)?;
+        report
+            .actions
+            .push(describe_permission_fix(&path, current, wanted));
+    }
+    Ok(report)
+}
+
+/// ssh refuses configs that group or others can write, so that is an error;
+/// other access to the SSH directory, config or its backups is a warning.
+/// Skipped on platforms without Unix modes.
+pub fn check_ssh_permissions(ssh_dir: &Path, ssh_config: &Path) -> CheckResult {
+    let name = "SSH permissions".to_string();
+    if cfg!(not(unix)) {
+        return CheckResult {
+            name,
+            status: CheckStatus::Ok,
+            message: "permission check skipped (not Unix)".to_string(),
+        };
+    }
+
+    let loose = match loose_ssh_permissions(ssh_dir, ssh_config) {
+        Ok(loose) => loose,
+        Err(err) => return CheckResult::from_result("SSH permissions", Err(err)),
+    };
+    let describe =
+        |(path, current, _): &(PathBuf, u32, u32)| format!("{} ({current:o})", path.display());
+
+    let writable: Vec<String> = loose
+        .iter()
+        .filter(|(path, current, _)| {
+            current & 0o022 != 0 && (path == ssh_dir || path == ssh_config)
+        })
+        .map(describe)
+        .collect();
+    if !writable.is_empty() {
+        return CheckResult {
+            name,
+            status: CheckStatus::Err,
+            message: format!(
+                "writable by group or others, so ssh may ignore the config: {} - run `proxyctl-rs doctor fix-ssh-permissions`",
+                writable.join(", ")
+            ),
+        };
+    }
+
+    if loose.is_empty() {
+        CheckResult {
+            name,
+            status: CheckStatus::Ok,
+            message: "SSH directory and config are private".to_string(),
+        }
+    } else {
+        let paths: Vec<String> = loose.iter().map(describe).collect();
+        CheckResult {
+            name,
+            status: CheckStatus::Warn,
+            message: format!(
+                "accessible by group or others: {} - run `proxyctl-rs doctor fix-ssh-permissions`",
+                paths.join(", ")
+            ),
+        }
+    }
+}
+
+fn ssh_permission_check() -> CheckResult {
+    match config::get_ssh_config_path() {
+        Ok(ssh_config) => match ssh_config.parent() {
+            Some(ssh_dir) => check_ssh_permissions(ssh_dir, &ssh_config),
+            None => CheckResult::from_result(
+                "SSH permissions",
+                Err(anyhow!("SSH config path has no parent directory")),
+            ),
+        },
+        Err(err) => CheckResult::from_result("SSH permissions", Err(err)),
+    }
+}
+
+/// The credentials file holds proxy passwords, so only its owner may read it.
+pub fn check_credentials_permissions(path: &Path) -> CheckResult {
+    let name = "Credentials".to_string();
+    if !path.exists() {
+        return CheckResult {
+            name,
+            status: CheckStatus::Ok,
+            message: format!("no credentials file at {}", path.display()),
+        };
+    }
+
+    match permission_mode(path) {
+        Some(mode) if mode & 0o077 != 0 => CheckResult {
+            name,
+            status: CheckStatus::Warn,
+            message: format!(
+                "{} is accessible by group or others ({mode:o}) - run `chmod {CREDENTIALS_FILE_MODE:o} {}`",
+                path.display(),
+                path.display()
+            ),
+        },
+        _ => CheckResult {
+            name,
+            status: CheckStatus::Ok,
+            message: format!("{} is private", path.display()),
+        },
+    }
+}
+
+fn credentials_permission_check() -> CheckResult {
+    match config::get_credentials_file_path() {
+        Ok(path) => check_credentials_permissions(&path),
+        Err(err) => CheckResult::from_result("Credentials", Err(err)),
+    }
+}
+
+async fn env_consistency_checks() -> Vec<CheckResult> {
+    let inconsistencies = match proxy::get_env_vs_db_status().await {
+        Ok(inconsistencies) => inconsistencies,
+        Err(err) => return vec![CheckResult::from_result("Env", Err(err))],
+    };
+
+    if inconsistencies.is_empty() {
+        return vec![CheckResult {
+            name: "Env".to_string(),
+            status: CheckStatus::Ok,
+            message: "proxy variables match the stored state".to_string(),
+        }];
+    }
+
+    let show = |value: &Option<String>| {
+        value
+            .as_deref()
+            .map(proxy::mask_proxy_credentials)
+            .unwrap_or_else(|| "unset".to_string())
+    };
+    inconsistencies
+        .iter()
+        .map(|item| CheckResult {
+            name: format!("Env [{}]", item.var_name),
+            status: CheckStatus::Warn,
+            message: format!(
+                "environment has {}, database has {}; open a new shell or source your profile",
+                show(&item.env_value),
+                show(&item.db_value)
+            ),
+        })
+        .collect()
+}
+
+/// Print only the env-vs-database checks; mismatches are warnings.
+pub async fn print_env_consistency() {
+    for check in env_consistency_checks().await {
+        println!("{}", check.render());
+    }
+}
+
+fn conflict_checks(warnings: Vec<conflicts::ConflictWarning>) -> Vec<CheckResult> {
+    if warnings.is_empty() {
+        return vec![CheckResult {
+            name: "Conflicts".to_string(),
+            status: CheckStatus::Ok,
+            message: "no conflicting proxy tools found".to_string(),
+        }];
+    }
+
+    warnings
+        .into_iter()
+        .map(|warning| {
+            let message = if warning.path.as_os_str().is_empty() {
+                warning.description
+            } else {
+                format!("{} ({})", warning.description, warning.path.display())
+            };
+            CheckResult {
+                name: format!("Conflicts [{}]", warning.tool),
+                status: CheckStatus::Warn,
+                message,
+            }
+        })
+        .collect()
+}
+
+/// A gap or mistake in the `no_proxy` list found by `check_no_proxy_coverage`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NoproxyCoverageWarning {
+    /// A loopback name or address that should always bypass the proxy.
+    MissingLocal(String),
+    /// A corporate domain from the hosts file, with one host that uses it.
+    MissingDomain { domain: String, host: String },
+    /// An entry most tools will not understand.
+    InvalidEntry { entry: String, reason: String },
+}
+
+impl NoproxyCoverageWarning {
+    pub fn message(&self) -> String {
+        match self {
+            Self::MissingLocal(entry) => format!("{entry} is not excluded"),
+            Self::MissingDomain { domain, host } => {
+                format!("corporate domain {domain} (used by {host}) is not excluded")
+            }
+            Self::InvalidEntry { entry, reason } => format!("'{entry}': {reason}"),
+        }
+    }
+}
+
+const LOCAL_NO_PROXY_ENTRIES: [&str; 3] = ["localhost", "127.0.0.1", "::1"];
+const CORPORATE_DOMAIN_LABELS: [&str; 3] = ["corp", "internal", "intranet"];
+
+/// Check `no_proxy` for missing loopback entries, corporate domains used in
+/// the configured hosts files, and malformed entries.
+pub fn check_no_proxy_coverage(no_proxy: &str) -> Vec<NoproxyCoverageWarning> {
+    let hosts = config::get_hosts_file_paths()
+        .and_then(|paths| config::read_host_patterns(&paths))
+        .unwrap_or_default();
+    check_no_proxy_coverage_with(no_proxy, &hosts)
+}
+
+/// Like [`check_no_proxy_coverage`], against the given host patterns.
+pub fn check_no_proxy_coverage_with(
+    no_proxy: &str,
+    host_patterns: &[String],
+) -> Vec<NoproxyCoverageWarning> {
+    let entries: Vec<&str> = no_proxy
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .collect();
+    let mut warnings = Vec::new();
+
+    for local in LOCAL_NO_PROXY_ENTRIES {
+        if !entries
+            .iter()
+            .any(|entry| no_proxy_entry_covers(entry, local))
+        {
+            warnings.push(NoproxyCoverageWarning::MissingLocal(local.to_string()));
+        }
+    }
+
+    let mut domains: Vec<String> = Vec::new();
+    for host in host_patterns {
+        let Some(domain) = corporate_domain(host) else {
+            continue;
+        };
+        if domains.contains(&domain) {
+            continue;
+        }
+        if !entries
+            .iter()
+            .any(|entry| no_proxy_entry_covers(entry, &domain))
+        {
+            warnings.push(NoproxyCoverageWarning::MissingDomain {
+                domain: domain.clone(),
+                host: host.clone(),
+            });
+        }
+        domains.push(domain);
+    }
+
+    for entry in &entries {
+        if let Some(reason) = invalid_no_proxy_entry(entry) {
+            warnings.push(NoproxyCoverageWarning::InvalidEntry {
+                entry: entry.to_string(),
+                reason,
+            });
+        }
+    }
+
+    warnings
+}
+
+// `*.corp.example.com` or `db1.corp.example.com` -> `corp.example.com`.
+fn corporate_domain(pattern: &str) -> Option<String> {
+    let pattern = pattern.to_ascii_lowercase();
+    let labels: Vec<&str> = pattern.split('.').collect();
+    let start = labels
+        .iter()
+        .position(|label| CORPORATE_DOMAIN_LABELS.contains(label))?;
+    let domain = labels[start..].join(".");
+    (labels.len() - start > 1 && !domain.contains(['*', '?'])).then_some(domain)
+}
+
+fn no_proxy_entry_covers(entry: &str, target: &str) -> bool {
+    if entry == "*" {
+        return true;
+    }
+    let unbracketed = |value: &str| {
+        value
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string()
+    };
+    if let (Some((network, prefix)), Ok(ip)) = (entry.split_once('/'), target.parse::<IpAddr>()) {
+        return cidr_contains(network, prefix, ip);
+    }
+    let entry = unbracketed(entry).to_ascii_lowercase();
+    let domain = entry.trim_start_matches('*').trim_start_matches('.');
+    let target = unbracketed(target).to_ascii_lowercase();
+    target == domain || target.ends_with(&format!(".{domain}"))
+}
+
+fn cidr_contains(network: &str, prefix: &str, ip: IpAddr) -> bool {
+    let (Ok(network), Ok(prefix)) = (network.parse::<IpAddr>(), prefix.parse::<u32>()) else {
+        return false;
+    };
+    match (network, ip) {
+        (IpAddr::V4(network), IpAddr::V4(ip)) if prefix <= 32 => {
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(network) & mask == u32::from(ip) & mask
+        }
+        (IpAddr::V6(network), IpAddr::V6(ip)) if prefix <= 128 => {
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(network) & mask == u128::from(ip) & mask
+        }
+        _ => false,
+    }
+}
+
+fn invalid_no_proxy_entry(entry: &str) -> Option<String> {
+    if entry.contains("://") {
+        return Some("drop the URL scheme; no_proxy takes host names".to_string());
+    }
+    if let Some((network, prefix)) = entry.split_once('/') {
+        let Ok(network) = network.parse::<IpAddr>() else {
+            return Some("CIDR entries need an IP address before the '/'".to_string());
+        };
+        if let Ok(mask) = prefix.parse::<Ipv4Addr>() {
+            return Some(format!(
+                "subnet masks are not supported; use {network}/{}",
+                u32::from(mask).leading_ones()
+            ));
         }
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        return match prefix.parse::<u32>() {
+            Ok(bits) if bits <= max => None,
+            _ => Some(format!("prefix length must be between 0 and {max}")),
+        };
     }
 
+    let numeric = entry
+        .chars()
+        .all(|c| c.is_ascii_digit() || c == '.' || c == '*');
+    if !numeric || !entry.contains('.') {
+        return None;
+    }
+    if entry.contains('*') {
+        return Some(
+            "IP wildcards are not supported; use CIDR notation like 10.0.0.0/8".to_string(),
+        );
+    }
+    match entry.parse::<Ipv4Addr>() {
+        Ok(ip) if ip.octets()[3] == 0 => Some(format!(
+            "looks like a network address; add a prefix length like {ip}/24"
+        )),
+        Ok(_) => None,
+        Err(_) => Some("incomplete IP address; use CIDR notation like 10.0.0.0/8".to_string()),
+    }
+}
+
+fn no_proxy_coverage_warnings() -> Result<Vec<NoproxyCoverageWarning>> {
+    Ok(check_no_proxy_coverage(&config::get_no_proxy()?))
+}
+
+fn no_proxy_coverage_check() -> CheckResult {
+    let name = "No-proxy coverage";
+    match no_proxy_coverage_warnings() {
+        Ok(warnings) if warnings.is_empty() => CheckResult {
+            name: name.to_string(),
+            status: CheckStatus::Ok,
+            message: "no_proxy covers local addresses".to_string(),
+        },
+        Ok(warnings) => CheckResult {
+            name: name.to_string(),
+            status: CheckStatus::Warn,
+            message: warnings
+                .iter()
+                .map(NoproxyCoverageWarning::message)
+                .collect::<Vec<_>>()
+                .join("; "),
+        },
+        Err(err) => CheckResult::from_result(name, Err(err)),
+    }
+}
+
+/// Print one line per `no_proxy` coverage problem, or an OK line.
+pub fn print_no_proxy_coverage() -> Result<()> {
+    let warnings = no_proxy_coverage_warnings()?;
+    if warnings.is_empty() {
+        println!("{}", no_proxy_coverage_check().render());
+    }
+    for warning in &warnings {
+        let check = CheckResult {
+            name: "No-proxy coverage".to_string(),
+            status: CheckStatus::Warn,
+            message: warning.message(),
+        };
+        println!("{}", check.render());
+    }
+    Ok(())
+}
+
+/// Print only the conflicting-tools checks; warnings never fail the command.
+pub fn print_conflicting_tools() {
+    for check in conflict_checks(conflicts::find_conflicting_tools()) {
+        println!("{}", check.render());
+    }
+}
+
+async fn evaluate() -> Result<DoctorSummary> {
+    let checks = collect_checks().await;
+    let healthy = checks.iter().all(|check| check.status != CheckStatus::Err);
+    let mut lines: Vec<String> = checks.iter().map(CheckResult::render).collect();
+
     if healthy {
         lines.push(
             format!("{}: {} - all checks passed", "Summary".bold(), "OK".green())
@@ -68,6 +1034,57 @@ async fn evaluate() -> Result<DoctorSummary> {
     Ok(DoctorSummary { lines, healthy })
 }
 
+pub async fn generate_report() -> Result<DiagnosticReport> {
+    let db_path = db::get_db_path();
+    let mut env_state = db::load_env_state(&db_path).await.unwrap_or_default();
+    for value in [
+        &mut env_state.http_proxy,
+        &mut env_state.https_proxy,
+        &mut env_state.ftp_proxy,
+        &mut env_state.all_proxy,
+        &mut env_state.proxy_rsync,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        *value = proxy::mask_proxy_credentials(value);
+    }
+
+    Ok(DiagnosticReport {
+        timestamp: chrono::Utc::now().to_rfc3339(),
+        tool_version: env!("CARGO_PKG_VERSION"),
+        config: config::load_config().unwrap_or_default(),
+        env_state,
+        ssh_status: config::get_ssh_status()?,
+        doctor_checks: collect_checks().await,
+        system_info: SystemInfo {
+            os: std::env::consts::OS.to_string(),
+            shell: std::env::var("SHELL").ok(),
+            home: dirs::home_dir().map(|home| home.display().to_string()),
+        },
+    })
+}
+
+pub async fn write_report(output: Option<&Path>) -> Result<()> {
+    let report = generate_report().await?;
+
+    match output {
+        Some(path) => {
+            let file = fs::File::create(path)
+                .with_context(|| format!("creating report file {}", path.display()))?;
+            serde_json::to_writer_pretty(file, &report)?;
+            output::info(format!("Diagnostic report written to {}", path.display()));
+        }
+        None => {
+            let mut stdout = std::io::stdout();
+            serde_json::to_writer_pretty(&mut stdout, &report)?;
+            println!();
+        }
+    }
+
+    Ok(())
+}
+
 fn check_config() -> Result<String> {
     let config_dir = config::get_config_dir().context("finding config directory")?;
     let config_file = config_dir.join("config.toml");
@@ -75,9 +1092,9 @@ fn check_config() -> Result<String> {
     config::load_config()
         .with_context(|| format!("loading configuration from {}", config_file.display()))?;
 
-    let hosts_path = config::get_hosts_file_path().context("resolving hosts file path")?;
-    if !hosts_path.exists() {
-        return Err(anyhow!("expected hosts file at {}", hosts_path.display()));
+    let hosts_paths = config::get_hosts_file_paths().context("resolving hosts file paths")?;
+    if let Some(missing) = hosts_paths.iter().find(|path| !path.exists()) {
+        return Err(anyhow!("expected hosts file at {}", missing.display()));
     }
 
     Ok(format!(
@@ -100,7 +1117,9 @@ async fn check_database() -> Result<String> {
     Ok(format!("database reachable at {}", file_path.display()))
 }
 
-pub fn print_config() -> Result<()> {
+/// Print the effective configuration, annotating where each value came
+/// from. With `only_changed`, values left at their defaults are omitted.
+pub fn print_config(only_changed: bool, color: ColorMode) -> Result<()> {
     let config_dir = config::get_config_dir()?;
     let config_file = config_dir.join("config.toml");
     let current = load_config_or_default(&config_file)?;
@@ -108,13 +1127,80 @@ pub fn print_config() -> Result<()> {
 
     let merged = merge_with_defaults(&default, &current)?;
     let configured_paths = gather_configured_paths(&config_file)?;
-    let annotated = annotate_config_toml(&default, &merged, &configured_paths)?;
+    let options =
+        config::describe_config_options_for(&current, config::config_schema_version(&config_file)?);
+    let rendered = effective_config_value(&merged, &options, &configured_paths, only_changed)?;
+    let annotated = annotate_config_toml(&default, &rendered, &options, &configured_paths, color)?;
 
-    println!("{}\n{}", "Configuration".bold(), annotated);
+    println!("{}\n{}", color.paint("Configuration".bold()), annotated);
 
     Ok(())
 }
 
+// The merged config as TOML, with env-supplied values filled in and, when
+// `only_changed` is set, every value still at its default removed.
+fn effective_config_value(
+    merged: &config::AppConfig,
+    options: &[config::ConfigOptionDescriptor],
+    configured_paths: &HashSet<Vec<String>>,
+    only_changed: bool,
+) -> Result<TomlValue> {
+    let TomlValue::Table(mut table) = TomlValue::try_from(merged)? else {
+        return Err(anyhow!("configuration did not serialize to a TOML table"));
+    };
+
+    for option in options {
+        if option.source == config::ConfigSource::EnvVar && !table.contains_key(option.key) {
+            if let Some(value) = option.env_var.and_then(|var| std::env::var(var).ok()) {
+                table.insert(option.key.to_string(), TomlValue::String(value));
+            }
+        }
+    }
+
+    if let Some(TomlValue::String(proxy)) = table.get_mut("default_proxy") {
+        *proxy = proxy::mask_proxy_credentials(proxy);
+    }
+
+    if only_changed {
+        table.retain(|key, value| {
+            if source_for(&[key.to_string()], options, configured_paths)
+                == config::ConfigSource::Default
+            {
+                return false;
+            }
+            if let TomlValue::Table(nested) = value {
+                nested.retain(|child, _| {
+                    configured_paths.contains(&vec![key.to_string(), child.to_string()])
+                });
+            }
+            true
+        });
+    }
+
+    Ok(TomlValue::Table(table))
+}
+
+// Top-level keys use the option descriptors; keys inside tables count as
+// coming from the file only when the file sets them explicitly.
+fn source_for(
+    path: &[String],
+    options: &[config::ConfigOptionDescriptor],
+    configured_paths: &HashSet<Vec<String>>,
+) -> config::ConfigSource {
+    if path.len() > 1 {
+        return if configured_paths.contains(path) {
+            config::ConfigSource::File
+        } else {
+            config::ConfigSource::Default
+        };
+    }
+    options
+        .iter()
+        .find(|option| path.first().is_some_and(|key| key == option.key))
+        .map(|option| option.source)
+        .unwrap_or_default()
+}
+
 fn gather_configured_paths(config_file: &Path) -> Result<HashSet<Vec<String>>> {
     if !config_file.exists() {
         return Ok(HashSet::new());
@@ -210,16 +1296,22 @@ fn deep_merge(target: &mut JsonValue, source: &JsonValue) {
 
 fn annotate_config_toml(
     default: &config::AppConfig,
-    current: &config::AppConfig,
+    current: &TomlValue,
+    options: &[config::ConfigOptionDescriptor],
     configured_paths: &HashSet<Vec<String>>,
+    color: ColorMode,
 ) -> Result<String> {
     let annotations = build_annotation_map(default, current)?;
-    highlight_toml_with_annotations(current, &annotations, configured_paths)
+    highlight_toml_with_annotations(current, &annotations, options, configured_paths, color)
 }
 
-fn build_annotation_map<T>(default: &T, current: &T) -> Result<BTreeMap<Vec<String>, ValueSnapshot>>
+fn build_annotation_map<D, C>(
+    default: &D,
+    current: &C,
+) -> Result<BTreeMap<Vec<String>, ValueSnapshot>>
 where
-    T: Serialize,
+    D: Serialize,
+    C: Serialize,
 {
     let default_json = serde_json::to_value(default)?;
     let current_json = serde_json::to_value(current)?;
@@ -289,9 +1381,11 @@ struct RenderedLine {
 }
 
 fn highlight_toml_with_annotations(
-    current: &config::AppConfig,
+    current: &TomlValue,
     annotations: &BTreeMap<Vec<String>, ValueSnapshot>,
+    options: &[config::ConfigOptionDescriptor],
     configured_paths: &HashSet<Vec<String>>,
+    color: ColorMode,
 ) -> Result<String> {
     let toml_string = to_string_pretty(current)?;
     let mut result = String::new();
@@ -310,7 +1404,7 @@ fn highlight_toml_with_annotations(
         if let Some(path) = parse_table_path(trimmed) {
             table_path = path;
             result.push_str(indent);
-            result.push_str(&trimmed.blue().bold().to_string());
+            result.push_str(&color.paint(trimmed.blue().bold()));
             result.push('\n');
             continue;
         }
@@ -320,12 +1414,14 @@ fn highlight_toml_with_annotations(
             let value_text = value_part.trim();
             let mut full_path = table_path.clone();
             full_path.push(key.to_string());
+            let source = source_for(&full_path, options, configured_paths);
             let rendered = render_line(
                 indent,
                 key,
                 value_text,
                 annotations.get(&full_path),
-                configured_paths.contains(&full_path),
+                source,
+                color,
             );
             result.push_str(&rendered.text);
             if let Some(deferred) = rendered.deferred {
@@ -345,7 +1441,7 @@ fn highlight_toml_with_annotations(
                     if !pending.configured {
                         closing_repr = closing_repr.dimmed();
                     }
-                    line_buf.push_str(&closing_repr.to_string());
+                    line_buf.push_str(&color.paint(closing_repr));
                     line_buf.push_str(&pending.comment);
                 } else {
                     line_buf.push_str(trimmed);
@@ -356,7 +1452,7 @@ fn highlight_toml_with_annotations(
                     if !pending.configured {
                         body_repr = body_repr.dimmed();
                     }
-                    line_buf.push_str(&body_repr.to_string());
+                    line_buf.push_str(&color.paint(body_repr));
                 } else {
                     line_buf.push_str(trimmed);
                 }
@@ -376,15 +1472,17 @@ fn render_line(
     key: &str,
     value_text: &str,
     annotation: Option<&ValueSnapshot>,
-    is_configured: bool,
+    source: config::ConfigSource,
+    color: ColorMode,
 ) -> RenderedLine {
+    let is_configured = source != config::ConfigSource::Default;
     let mut line = String::new();
     line.push_str(indent);
     let mut key_repr = key.bold();
     if !is_configured {
         key_repr = key_repr.dimmed();
     }
-    line.push_str(&key_repr.to_string());
+    line.push_str(&color.paint(key_repr));
     line.push_str(" = ");
 
     let mut deferred = None;
@@ -395,7 +1493,7 @@ fn render_line(
         if !is_configured {
             value_repr = value_repr.dimmed();
         }
-        line.push_str(&value_repr.to_string());
+        line.push_str(&color.paint(value_repr));
 
         let type_sample = select_type_sample(&snapshot.default, &snapshot.current);
         let type_label = format!("({})", describe_type(type_sample));
@@ -407,6 +1505,10 @@ fn render_line(
 
         let mut comment_parts: Vec<ColoredString> = vec![type_colored];
 
+        if is_configured {
+            comment_parts.push(format!("(from {source})").bright_black());
+        }
+
         if show_default_note(snapshot) {
             let default_display = format!("[{}]", format_value(&snapshot.default));
             comment_parts.push(colorize_secondary(&default_display, kind));
@@ -415,11 +1517,11 @@ fn render_line(
         if !comment_parts.is_empty() {
             let mut comment = String::new();
             comment.push_str("  ");
-            comment.push_str(&"#".bright_black().to_string());
+            comment.push_str(&color.paint("#".bright_black()));
 
             for part in comment_parts {
                 comment.push(' ');
-                comment.push_str(&part.to_string());
+                comment.push_str(&color.paint(part));
             }
 
             if let Some(closing) = multiline_closing(kind, value_text) {
@@ -439,7 +1541,7 @@ fn render_line(
         if !is_configured {
             value_repr = value_repr.dimmed();
         }
-        line.push_str(&value_repr.to_string());
+        line.push_str(&color.paint(value_repr));
     }
 
     line.push('\n');
@@ -0,0 +1,113 @@
+use colored::Colorize;
+
+/// Whether a command should only preview its changes (`--dry-run`) instead
+/// of writing files, the environment or the database.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DryRun(pub bool);
+
+/// One proposed change to a line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffLine {
+    Added(String),
+    Removed(String),
+}
+
+/// Proposed line changes, grouped by the file (or the environment) they
+/// apply to, rendered as a `+`/`-` preview.
+#[derive(Debug, Clone, Default)]
+pub struct DiffWriter {
+    sections: Vec<(String, Vec<DiffLine>)>,
+}
+
+impl DiffWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, target: &str, line: impl Into<String>) {
+        self.section(target).push(DiffLine::Added(line.into()));
+    }
+
+    pub fn remove(&mut self, target: &str, line: impl Into<String>) {
+        self.section(target).push(DiffLine::Removed(line.into()));
+    }
+
+    /// Record the lines that differ between the `old` and `new` content of
+    /// `target`, in file order.
+    pub fn diff(&mut self, target: &str, old: &str, new: &str) {
+        let old: Vec<&str> = old.lines().collect();
+        let new: Vec<&str> = new.lines().collect();
+
+        // common[i][j]: length of the longest common subsequence of old[i..] and new[j..].
+        let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+        for i in (0..old.len()).rev() {
+            for j in (0..new.len()).rev() {
+                common[i][j] = if old[i] == new[j] {
+                    common[i + 1][j + 1] + 1
+                } else {
+                    common[i + 1][j].max(common[i][j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while i < old.len() || j < new.len() {
+            if i < old.len() && j < new.len() && old[i] == new[j] {
+                i += 1;
+                j += 1;
+            } else if j == new.len() || (i < old.len() && common[i + 1][j] >= common[i][j + 1]) {
+                self.remove(target, old[i]);
+                i += 1;
+            } else {
+                self.add(target, new[j]);
+                j += 1;
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sections.is_empty()
+    }
+
+    /// The changes recorded for `target`.
+    pub fn lines(&self, target: &str) -> &[DiffLine] {
+        self.sections
+            .iter()
+            .find(|(name, _)| name == target)
+            .map_or(&[], |(_, lines)| lines.as_slice())
+    }
+
+    /// Each target as a bold header followed by its `+` (green) and `-`
+    /// (red) lines.
+    pub fn render(&self) -> String {
+        if self.is_empty() {
+            return "No changes".to_string();
+        }
+        let mut output = Vec::new();
+        for (target, lines) in &self.sections {
+            output.push(target.bold().to_string());
+            for line in lines {
+                output.push(match line {
+                    DiffLine::Added(text) => format!("+{text}").green().to_string(),
+                    DiffLine::Removed(text) => format!("-{text}").red().to_string(),
+                });
+            }
+        }
+        output.join("\n")
+    }
+
+    pub fn print(&self) {
+        println!("{}", self.render());
+    }
+
+    fn section(&mut self, target: &str) -> &mut Vec<DiffLine> {
+        let index = match self.sections.iter().position(|(name, _)| name == target) {
+            Some(index) => index,
+            None => {
+                self.sections.push((target.to_string(), Vec::new()));
+                self.sections.len() - 1
+            }
+        };
+        &mut self.sections[index].1
+    }
+}
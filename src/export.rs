@@ -0,0 +1,521 @@
+use crate::db;
+use crate::dry_run::{DiffWriter, DryRun};
+use crate::proxy;
+use anyhow::{anyhow, Context, Result};
+use serde_json::{Map, Value};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// `.npmrc` keys npm reads its HTTP and HTTPS proxy from.
+const NPM_PROXY_KEYS: [&str; 2] = ["proxy", "https-proxy"];
+
+/// The user's `~/.npmrc`.
+pub fn default_npmrc_path() -> Result<PathBuf> {
+    dirs::home_dir()
+        .map(|home| home.join(".npmrc"))
+        .ok_or_else(|| anyhow!("Could not find home directory"))
+}
+
+/// The stored HTTP and HTTPS proxy, each falling back to the other when
+/// only one is set.
+pub fn stored_proxy_values(state: &db::EnvState) -> Result<(String, String)> {
+    let non_empty = |value: &Option<String>| value.clone().filter(|value| !value.is_empty());
+    let http = non_empty(&state.http_proxy);
+    let https = non_empty(&state.https_proxy);
+    match (http, https) {
+        (Some(http), Some(https)) => Ok((http, https)),
+        (Some(proxy), None) | (None, Some(proxy)) => Ok((proxy.clone(), proxy)),
+        (None, None) => Err(anyhow!(
+            "no proxy is enabled; run `proxyctl-rs on` before exporting it"
+        )),
+    }
+}
+
+/// `content` with `proxy` and `https-proxy` set. The first line for each
+/// key is replaced in place and repeats are dropped; keys not yet present
+/// are appended. Every other line is kept as it was.
+pub fn set_npmrc_proxy(content: &str, proxy: &str, https_proxy: &str) -> String {
+    let values = [proxy, https_proxy];
+    let mut written = [false; 2];
+    let mut lines = Vec::new();
+    for line in content.lines() {
+        match npm_proxy_key_index(line) {
+            Some(index) if written[index] => {}
+            Some(index) => {
+                written[index] = true;
+                lines.push(format!("{}={}", NPM_PROXY_KEYS[index], values[index]));
+            }
+            None => lines.push(line.to_string()),
+        }
+    }
+    for (index, key) in NPM_PROXY_KEYS.iter().enumerate() {
+        if !written[index] {
+            lines.push(format!("{key}={}", values[index]));
+        }
+    }
+    join_lines(lines)
+}
+
+/// `content` without any `proxy` or `https-proxy` line.
+pub fn remove_npmrc_proxy(content: &str) -> String {
+    let lines: Vec<String> = content
+        .lines()
+        .filter(|line| npm_proxy_key_index(line).is_none())
+        .map(str::to_string)
+        .collect();
+    join_lines(lines)
+}
+
+/// Write the stored proxy to the `.npmrc` at `path`, creating the file if needed.
+pub async fn export_npm(path: &Path, dry_run: DryRun) -> Result<()> {
+    let state = db::load_env_state(&db::get_db_path()).await?;
+    let (proxy, https_proxy) = stored_proxy_values(&state)?;
+    let current = read_config(path)?;
+    update_config(
+        path,
+        &current,
+        &set_npmrc_proxy(&current, &proxy, &https_proxy),
+        dry_run,
+    )
+}
+
+/// Remove the proxy keys from the `.npmrc` at `path`, if it exists.
+pub fn unexport_npm(path: &Path, dry_run: DryRun) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let current = read_config(path)?;
+    update_config(path, &current, &remove_npmrc_proxy(&current), dry_run)
+}
+
+/// pip's per-user config file: `pip/pip.conf` under `~/.config` (or
+/// `$XDG_CONFIG_HOME`) on Linux and `~/Library/Application Support` on macOS.
+pub fn default_pip_conf_path() -> Result<PathBuf> {
+    let file_name = if cfg!(windows) { "pip.ini" } else { "pip.conf" };
+    dirs::config_dir()
+        .map(|dir| dir.join("pip").join(file_name))
+        .ok_or_else(|| anyhow!("Could not find config directory"))
+}
+
+/// `content` with `proxy` set in its `[global]` section. The first `proxy`
+/// line is replaced in place and repeats are dropped; without one the key
+/// goes at the end of `[global]`, and without `[global]` the section is
+/// appended. Comments and other sections are kept as they were.
+pub fn set_pip_proxy(content: &str, proxy: &str) -> String {
+    let proxy_line = format!("proxy = {proxy}");
+    let mut lines = Vec::new();
+    let mut in_global = false;
+    // Where a missing `proxy` key goes: after the last non-blank [global] line.
+    let mut global_end = None;
+    let mut written = false;
+    for line in content.lines() {
+        if let Some(section) = ini_section(line) {
+            in_global = section == "global";
+            lines.push(line.to_string());
+            if in_global {
+                global_end = Some(lines.len());
+            }
+            continue;
+        }
+        if in_global && ini_key(line) == Some("proxy") {
+            if !written {
+                written = true;
+                lines.push(proxy_line.clone());
+                global_end = Some(lines.len());
+            }
+            continue;
+        }
+        lines.push(line.to_string());
+        if in_global && !line.trim().is_empty() {
+            global_end = Some(lines.len());
+        }
+    }
+    if !written {
+        match global_end {
+            Some(index) => lines.insert(index, proxy_line),
+            None => {
+                if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+                    lines.push(String::new());
+                }
+                lines.push("[global]".to_string());
+                lines.push(proxy_line);
+            }
+        }
+    }
+    join_lines(lines)
+}
+
+/// `content` without the `proxy` key of its `[global]` section.
+pub fn remove_pip_proxy(content: &str) -> String {
+    let mut in_global = false;
+    let lines: Vec<String> = content
+        .lines()
+        .filter(|line| {
+            if let Some(section) = ini_section(line) {
+                in_global = section == "global";
+            }
+            !(in_global && ini_key(line) == Some("proxy"))
+        })
+        .map(str::to_string)
+        .collect();
+    join_lines(lines)
+}
+
+/// Write the stored proxy to the pip config at `path`, preferring
+/// `https_proxy`, creating the file if needed.
+pub async fn export_pip(path: &Path, dry_run: DryRun) -> Result<()> {
+    let state = db::load_env_state(&db::get_db_path()).await?;
+    let (_, proxy) = stored_proxy_values(&state)?;
+    let current = read_config(path)?;
+    update_config(path, &current, &set_pip_proxy(&current, &proxy), dry_run)
+}
+
+/// Remove the `[global]` proxy key from the pip config at `path`, if it exists.
+pub fn unexport_pip(path: &Path, dry_run: DryRun) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let current = read_config(path)?;
+    update_config(path, &current, &remove_pip_proxy(&current), dry_run)
+}
+
+/// Docker's daemon config: `/etc/docker/daemon.json`, or
+/// `~/.docker/daemon.json` for a rootless daemon.
+pub fn default_docker_daemon_path(rootless: bool) -> Result<PathBuf> {
+    if !rootless {
+        return Ok(PathBuf::from("/etc/docker/daemon.json"));
+    }
+    dirs::home_dir()
+        .map(|home| home.join(".docker").join("daemon.json"))
+        .ok_or_else(|| anyhow!("Could not find home directory"))
+}
+
+/// Keys of the `proxies.default` object in `daemon.json`.
+const DOCKER_PROXY_KEYS: [&str; 3] = ["httpProxy", "httpsProxy", "noProxy"];
+
+/// `content` (a `daemon.json`) with the stored proxy under
+/// `proxies.default`. Other keys, inside `proxies` or not, are kept.
+pub fn set_docker_proxy(content: &str, state: &db::EnvState) -> Result<String> {
+    let (http, https) = stored_proxy_values(state)?;
+    let no_proxy = state.no_proxy.clone().filter(|value| !value.is_empty());
+    let mut config = parse_daemon_json(content)?;
+    let default = config
+        .entry("proxies")
+        .or_insert_with(|| Value::Object(Map::new()))
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("\"proxies\" in daemon.json is not an object"))?
+        .entry("default")
+        .or_insert_with(|| Value::Object(Map::new()))
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("\"proxies.default\" in daemon.json is not an object"))?;
+    default.insert("httpProxy".to_string(), Value::String(http));
+    default.insert("httpsProxy".to_string(), Value::String(https));
+    match no_proxy {
+        Some(no_proxy) => default.insert("noProxy".to_string(), Value::String(no_proxy)),
+        None => default.shift_remove("noProxy"),
+    };
+    render_daemon_json(&config)
+}
+
+/// `content` (a `daemon.json`) without the keys [`set_docker_proxy`] writes;
+/// `proxies.default` and `proxies` are dropped once empty. Other keys keep
+/// their order, and `content` is returned as is when there was nothing to
+/// remove.
+pub fn remove_docker_proxy(content: &str) -> Result<String> {
+    let mut config = parse_daemon_json(content)?;
+    let mut removed = false;
+    if let Some(Value::Object(proxies)) = config.get_mut("proxies") {
+        if let Some(Value::Object(default)) = proxies.get_mut("default") {
+            for key in DOCKER_PROXY_KEYS {
+                removed |= default.shift_remove(key).is_some();
+            }
+            if removed && default.is_empty() {
+                proxies.shift_remove("default");
+            }
+        }
+        if removed && proxies.is_empty() {
+            config.shift_remove("proxies");
+        }
+    }
+    if !removed {
+        return Ok(content.to_string());
+    }
+    render_daemon_json(&config)
+}
+
+/// Write the stored proxy to the Docker daemon config at `path`, creating
+/// the file if needed.
+pub async fn export_docker(path: &Path, dry_run: DryRun) -> Result<()> {
+    let state = db::load_env_state(&db::get_db_path()).await?;
+    let current = read_config(path)?;
+    let updated = set_docker_proxy(&current, &state)
+        .with_context(|| format!("could not update {}", path.display()))?;
+    update_config(path, &current, &updated, dry_run)
+}
+
+/// Remove the proxy from the Docker daemon config at `path`, if it exists.
+pub fn unexport_docker(path: &Path, dry_run: DryRun) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let current = read_config(path)?;
+    let updated = remove_docker_proxy(&current)
+        .with_context(|| format!("could not update {}", path.display()))?;
+    update_config(path, &current, &updated, dry_run)
+}
+
+fn parse_daemon_json(content: &str) -> Result<Map<String, Value>> {
+    if content.trim().is_empty() {
+        return Ok(Map::new());
+    }
+    match serde_json::from_str(content)? {
+        Value::Object(config) => Ok(config),
+        _ => Err(anyhow!("daemon.json must contain a JSON object")),
+    }
+}
+
+fn render_daemon_json(config: &Map<String, Value>) -> Result<String> {
+    let mut content = serde_json::to_string_pretty(config)?;
+    content.push('\n');
+    Ok(content)
+}
+
+/// Which git config file `export git` changes, as git's `--global`,
+/// `--local` and `--system` options.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum GitScope {
+    /// The user's `~/.gitconfig` (or `$GIT_CONFIG_GLOBAL`).
+    #[default]
+    Global,
+    /// The repository in the current directory.
+    Local,
+    /// The system-wide git config.
+    System,
+}
+
+impl GitScope {
+    fn flag(self) -> &'static str {
+        match self {
+            GitScope::Global => "--global",
+            GitScope::Local => "--local",
+            GitScope::System => "--system",
+        }
+    }
+}
+
+/// git config keys holding the HTTP and HTTPS proxy.
+const GIT_PROXY_KEYS: [&str; 2] = ["http.proxy", "https.proxy"];
+
+/// The `http.proxy` set in git's `scope` config, if any.
+pub fn git_proxy(scope: GitScope) -> Result<Option<String>> {
+    git_value(scope, GIT_PROXY_KEYS[0])
+}
+
+/// Set git's `http.proxy` and `https.proxy` in `scope` to the stored proxy.
+pub async fn export_git(scope: GitScope, dry_run: DryRun) -> Result<()> {
+    let state = db::load_env_state(&db::get_db_path()).await?;
+    let (http, https) = stored_proxy_values(&state)?;
+    let values = [http, https];
+    if dry_run.0 {
+        let mut diff = DiffWriter::new();
+        let target = format!("git config {}", scope.flag());
+        for (key, value) in GIT_PROXY_KEYS.iter().zip(&values) {
+            let current = git_value(scope, key)?;
+            if current.as_ref() != Some(value) {
+                if let Some(current) = current {
+                    let current = proxy::mask_proxy_credentials(&current);
+                    diff.remove(&target, format!("{key}={current}"));
+                }
+                let value = proxy::mask_proxy_credentials(value);
+                diff.add(&target, format!("{key}={value}"));
+            }
+        }
+        diff.print();
+        return Ok(());
+    }
+    for (key, value) in GIT_PROXY_KEYS.iter().zip(&values) {
+        set_git_value(scope, key, value)?;
+    }
+    Ok(())
+}
+
+/// Unset git's `http.proxy` and `https.proxy` in `scope`.
+pub fn unexport_git(scope: GitScope, dry_run: DryRun) -> Result<()> {
+    if dry_run.0 {
+        let mut diff = DiffWriter::new();
+        let target = format!("git config {}", scope.flag());
+        for key in GIT_PROXY_KEYS {
+            if let Some(current) = git_value(scope, key)? {
+                let current = proxy::mask_proxy_credentials(&current);
+                diff.remove(&target, format!("{key}={current}"));
+            }
+        }
+        diff.print();
+        return Ok(());
+    }
+    for key in GIT_PROXY_KEYS {
+        let output = git_config(scope, &["--unset-all", key])?;
+        // Exit code 5: the key was not set.
+        if !output.status.success() && output.status.code() != Some(5) {
+            return Err(git_error(key, &output));
+        }
+    }
+    Ok(())
+}
+
+// Stands in for a proxy URL with credentials on git's command line, where
+// every user could read them; the real value is then written into the file
+// git put the placeholder in.
+const GIT_VALUE_PLACEHOLDER: &str = "proxyctl-rs-pending";
+
+fn set_git_value(scope: GitScope, key: &str, value: &str) -> Result<()> {
+    let has_credentials = proxy::strip_proxy_credentials(value) != value;
+    let argument = if has_credentials {
+        GIT_VALUE_PLACEHOLDER
+    } else {
+        value
+    };
+    let output = git_config(scope, &[key, argument])?;
+    if !output.status.success() {
+        return Err(git_error(key, &output));
+    }
+    if !has_credentials {
+        return Ok(());
+    }
+
+    let path = git_value_origin(scope, key)?;
+    let content =
+        fs::read_to_string(&path).with_context(|| format!("could not read {}", path.display()))?;
+    let placeholder = format!("= {GIT_VALUE_PLACEHOLDER}");
+    if !content.contains(&placeholder) {
+        return Err(anyhow!("could not find {key} in {}", path.display()));
+    }
+    let quoted = value.replace('\\', "\\\\").replace('"', "\\\"");
+    let updated = content.replacen(&placeholder, &format!("= \"{quoted}\""), 1);
+    fs::write(&path, updated).with_context(|| format!("could not write {}", path.display()))
+}
+
+// The config file `key` was read from in `scope`.
+fn git_value_origin(scope: GitScope, key: &str) -> Result<PathBuf> {
+    let output = git_config(scope, &["--show-origin", "--get", key])?;
+    if !output.status.success() {
+        return Err(git_error(key, &output));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    stdout
+        .split_once('\t')
+        .and_then(|(origin, _)| origin.strip_prefix("file:"))
+        // git quotes paths with unusual characters; those are not unquoted here.
+        .filter(|path| !path.is_empty() && !path.starts_with('"'))
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow!("could not find the git config file holding {key}"))
+}
+
+fn git_value(scope: GitScope, key: &str) -> Result<Option<String>> {
+    let output = git_config(scope, &["--get", key])?;
+    if output.status.success() {
+        let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        Ok(Some(value).filter(|value| !value.is_empty()))
+    } else if output.status.code() == Some(1) {
+        // Exit code 1: the key is not set.
+        Ok(None)
+    } else {
+        Err(git_error(key, &output))
+    }
+}
+
+fn git_config(scope: GitScope, args: &[&str]) -> Result<Output> {
+    Command::new("git")
+        .arg("config")
+        .arg(scope.flag())
+        .args(args)
+        .output()
+        .map_err(|err| match err.kind() {
+            ErrorKind::NotFound => anyhow!("git is not installed or not in PATH"),
+            _ => anyhow!("could not run git config: {err}"),
+        })
+}
+
+fn git_error(key: &str, output: &Output) -> anyhow::Error {
+    anyhow!(
+        "git config {key} failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    )
+}
+
+// The name of the section a `[name]` line starts.
+fn ini_section(line: &str) -> Option<&str> {
+    line.trim()
+        .strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .map(str::trim)
+}
+
+// The key a `key = value` or `key: value` line sets; comments and indented
+// continuation lines set nothing.
+fn ini_key(line: &str) -> Option<&str> {
+    if line.starts_with(char::is_whitespace) || line.starts_with(['#', ';']) {
+        return None;
+    }
+    let end = line.find(['=', ':'])?;
+    Some(line[..end].trim())
+}
+
+// Index into NPM_PROXY_KEYS of the key a `key=value` line sets; comments
+// (`#` or `;`) set nothing.
+fn npm_proxy_key_index(line: &str) -> Option<usize> {
+    let line = line.trim_start();
+    if line.starts_with('#') || line.starts_with(';') {
+        return None;
+    }
+    let (key, _) = line.split_once('=')?;
+    NPM_PROXY_KEYS.iter().position(|known| *known == key.trim())
+}
+
+fn join_lines(lines: Vec<String>) -> String {
+    if lines.is_empty() {
+        return String::new();
+    }
+    let mut content = lines.join("\n");
+    content.push('\n');
+    content
+}
+
+// Characters around values in `.npmrc`, pip and `daemon.json` files.
+fn is_value_separator(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '=' | '"' | '\'' | ',')
+}
+
+fn read_config(path: &Path) -> Result<String> {
+    if path.exists() {
+        Ok(fs::read_to_string(path)?)
+    } else {
+        Ok(String::new())
+    }
+}
+
+fn update_config(path: &Path, current: &str, updated: &str, dry_run: DryRun) -> Result<()> {
+    if dry_run.0 {
+        // Mask every proxy URL in either version, not just the stored ones.
+        let tokens = current
+            .split(is_value_separator)
+            .chain(updated.split(is_value_separator));
+        let mask = proxy::credential_masker(tokens.map(str::to_string).collect());
+        let mut diff = DiffWriter::new();
+        diff.diff(&path.display().to_string(), &mask(current), &mask(updated));
+        diff.print();
+        return Ok(());
+    }
+    if current != updated {
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, updated).with_context(|| format!("could not write {}", path.display()))?;
+    }
+    Ok(())
+}
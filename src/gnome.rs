@@ -0,0 +1,149 @@
+use anyhow::{anyhow, Context, Result};
+use std::process::Command;
+
+const PROXY_SCHEMA: &str = "org.gnome.system.proxy";
+const HTTP_SCHEMA: &str = "org.gnome.system.proxy.http";
+const HTTPS_SCHEMA: &str = "org.gnome.system.proxy.https";
+
+/// Reads and writes GSettings keys. Values use GVariant text, as on the
+/// `gsettings` command line (`'manual'`, `8080`).
+pub trait GSettingsBackend {
+    /// Whether settings can be read and written at all.
+    fn is_available(&self) -> bool;
+    fn get(&self, schema: &str, key: &str) -> Result<String>;
+    fn set(&self, schema: &str, key: &str, value: &str) -> Result<()>;
+}
+
+/// GSettings through the `gsettings` command.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct GSettingsCli;
+
+impl GSettingsBackend for GSettingsCli {
+    fn is_available(&self) -> bool {
+        Command::new("gsettings")
+            .arg("--version")
+            .output()
+            .is_ok_and(|output| output.status.success())
+    }
+
+    fn get(&self, schema: &str, key: &str) -> Result<String> {
+        let output = Command::new("gsettings")
+            .args(["get", schema, key])
+            .output()
+            .context("running gsettings get")?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "gsettings get {schema} {key} failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    fn set(&self, schema: &str, key: &str, value: &str) -> Result<()> {
+        let output = Command::new("gsettings")
+            .args(["set", schema, key, value])
+            .output()
+            .context("running gsettings set")?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "gsettings set {schema} {key} failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))
+        }
+    }
+}
+
+/// Point GNOME's HTTP and HTTPS proxy at `host:port` and switch the proxy
+/// mode to manual. Returns `false`, changing nothing, when gsettings is
+/// not available.
+pub fn set_proxy(host: &str, port: u16) -> Result<bool> {
+    set_proxy_with(&GSettingsCli, host, port)
+}
+
+/// Like [`set_proxy`], through `backend`.
+pub fn set_proxy_with<B>(backend: &B, host: &str, port: u16) -> Result<bool>
+where
+    B: GSettingsBackend + ?Sized,
+{
+    if !backend.is_available() {
+        return Ok(false);
+    }
+    for schema in [HTTP_SCHEMA, HTTPS_SCHEMA] {
+        backend.set(schema, "host", &gvariant_string(host))?;
+        backend.set(schema, "port", &port.to_string())?;
+    }
+    backend.set(PROXY_SCHEMA, "mode", &gvariant_string("manual"))?;
+    Ok(true)
+}
+
+/// Switch GNOME's proxy mode to none. Returns `false` when gsettings is not
+/// available.
+pub fn disable_proxy() -> Result<bool> {
+    disable_proxy_with(&GSettingsCli)
+}
+
+/// Like [`disable_proxy`], through `backend`.
+pub fn disable_proxy_with<B: GSettingsBackend + ?Sized>(backend: &B) -> Result<bool> {
+    if !backend.is_available() {
+        return Ok(false);
+    }
+    backend.set(PROXY_SCHEMA, "mode", &gvariant_string("none"))?;
+    Ok(true)
+}
+
+/// GNOME's HTTP proxy as `(host, port)` when the mode is manual, `None`
+/// for any other mode.
+pub fn current_proxy_with<B>(backend: &B) -> Result<Option<(String, u16)>>
+where
+    B: GSettingsBackend + ?Sized,
+{
+    if parse_gvariant_string(&backend.get(PROXY_SCHEMA, "mode")?) != "manual" {
+        return Ok(None);
+    }
+    let host = parse_gvariant_string(&backend.get(HTTP_SCHEMA, "host")?);
+    let port = backend.get(HTTP_SCHEMA, "port")?;
+    let port = port
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("unexpected GNOME proxy port: {port}"))?;
+    Ok(Some((host, port)))
+}
+
+/// How GNOME's proxy differs from `expected` (the stored proxy as
+/// `(host, port)`, or `None` when it is disabled); `None` when they agree.
+pub fn proxy_mismatch_with<B>(backend: &B, expected: Option<(&str, u16)>) -> Result<Option<String>>
+where
+    B: GSettingsBackend + ?Sized,
+{
+    let current = current_proxy_with(backend)?;
+    let current = current.as_ref().map(|(host, port)| (host.as_str(), *port));
+    Ok(match (current, expected) {
+        (current, expected) if current == expected => None,
+        (Some((host, port)), Some((expected_host, expected_port))) => Some(format!(
+            "GNOME uses {host}:{port} but the stored proxy is {expected_host}:{expected_port}"
+        )),
+        (Some((host, port)), None) => Some(format!(
+            "GNOME still uses {host}:{port} although no proxy is enabled"
+        )),
+        (None, Some(_)) => Some("GNOME proxy mode is not manual".to_string()),
+        (None, None) => None,
+    })
+}
+
+// A GVariant string literal, as `gsettings set` expects.
+fn gvariant_string(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+// The text of a GVariant string literal printed by `gsettings get`.
+fn parse_gvariant_string(value: &str) -> String {
+    let value = value.trim();
+    let inner = value
+        .strip_prefix('\'')
+        .and_then(|rest| rest.strip_suffix('\''))
+        .unwrap_or(value);
+    inner.replace("\\'", "'").replace("\\\\", "\\")
+}
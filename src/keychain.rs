@@ -0,0 +1,143 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const SERVICE_NAME: &str = "proxyctl-rs";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProxyCredentials {
+    pub username: String,
+    pub password: String,
+    /// RFC 3339 time after which the credentials must be replaced.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+}
+
+impl ProxyCredentials {
+    /// Whether `expires_at` has passed at `now`. An unparseable timestamp
+    /// counts as expired so that the entry gets replaced.
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        match &self.expires_at {
+            Some(value) => DateTime::parse_from_rfc3339(value)
+                .map(|expires_at| expires_at <= now)
+                .unwrap_or(true),
+            None => false,
+        }
+    }
+}
+
+/// Storage backend for proxy credentials, keyed by a profile name.
+pub trait CredentialStore {
+    fn load(&self, profile: &str) -> Result<Option<ProxyCredentials>>;
+    fn store(&self, profile: &str, credentials: &ProxyCredentials) -> Result<()>;
+}
+
+/// Reads through to the wrapped store but discards writes, so dry runs and
+/// previews never change the keychain.
+pub struct ReadOnlyStore<'a, S: ?Sized>(pub &'a S);
+
+impl<S: CredentialStore + ?Sized> CredentialStore for ReadOnlyStore<'_, S> {
+    fn load(&self, profile: &str) -> Result<Option<ProxyCredentials>> {
+        self.0.load(profile)
+    }
+
+    fn store(&self, _profile: &str, _credentials: &ProxyCredentials) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Load the credentials stored for `profile`. When there are none or they
+/// have expired, `prompt` supplies new ones, which are written back to `store`.
+pub fn load_proxy_credentials<S, F>(store: &S, profile: &str, prompt: F) -> Result<ProxyCredentials>
+where
+    S: CredentialStore + ?Sized,
+    F: FnOnce(&str) -> Result<ProxyCredentials>,
+{
+    match store.load(profile)? {
+        Some(credentials) if !credentials.is_expired(Utc::now()) => Ok(credentials),
+        _ => {
+            let credentials = prompt(profile)?;
+            store.store(profile, &credentials)?;
+            Ok(credentials)
+        }
+    }
+}
+
+/// Credential store backed by the platform keychain: `security` on macOS and
+/// `secret-tool` (libsecret) elsewhere.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemKeychain;
+
+impl CredentialStore for SystemKeychain {
+    fn load(&self, profile: &str) -> Result<Option<ProxyCredentials>> {
+        let output = if cfg!(target_os = "macos") {
+            Command::new("security")
+                .args(["find-generic-password", "-s", SERVICE_NAME, "-a", profile])
+                .arg("-w")
+                .output()
+        } else {
+            Command::new("secret-tool")
+                .args(["lookup", "service", SERVICE_NAME, "profile", profile])
+                .output()
+        }
+        .context("running keychain lookup")?;
+
+        if !output.status.success() {
+            return Ok(None);
+        }
+
+        let secret = String::from_utf8_lossy(&output.stdout);
+        let secret = secret.trim();
+        if secret.is_empty() {
+            return Ok(None);
+        }
+
+        let credentials = serde_json::from_str(secret)
+            .with_context(|| format!("parsing keychain entry for profile '{profile}'"))?;
+        Ok(Some(credentials))
+    }
+
+    fn store(&self, profile: &str, credentials: &ProxyCredentials) -> Result<()> {
+        let secret = serde_json::to_string(credentials)?;
+
+        // The secret goes through stdin so that it never shows up in `ps`.
+        let (mut command, input) = if cfg!(target_os = "macos") {
+            // `security -i` reads the command itself from stdin; `-X` takes
+            // the password as hex, which needs no quoting.
+            let hex: String = secret.bytes().map(|byte| format!("{byte:02x}")).collect();
+            let account = profile.replace('\\', "\\\\").replace('"', "\\\"");
+            let mut command = Command::new("security");
+            command.arg("-i");
+            (
+                command,
+                format!("add-generic-password -U -s {SERVICE_NAME} -a \"{account}\" -X {hex}\n"),
+            )
+        } else {
+            let mut command = Command::new("secret-tool");
+            command
+                .args(["store", "--label", &format!("{SERVICE_NAME} ({profile})")])
+                .args(["service", SERVICE_NAME, "profile", profile]);
+            (command, secret)
+        };
+
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .context("running keychain store")?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(input.as_bytes())?;
+        }
+        let status = child.wait()?;
+
+        if status.success() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "failed to store credentials for profile '{profile}' in the system keychain"
+            ))
+        }
+    }
+}
@@ -1,9 +1,17 @@
+pub mod completions;
 pub mod config;
+pub mod conflicts;
 pub mod db;
 pub mod defaults;
 pub mod detect;
 pub mod doctor;
+pub mod dry_run;
+pub mod export;
+pub mod gnome;
+pub mod keychain;
+pub mod output;
 pub mod proxy;
+pub mod systemctl;
 pub use config::collect_configured_hosts;
 pub use config::get_ssh_status;
 pub use config::SshStatus;
@@ -1,31 +1,55 @@
 use anyhow::Result;
-use clap::{Parser, Subcommand};
+use clap::{Args, CommandFactory, Parser, Subcommand};
 use colored::Colorize;
+use std::io::IsTerminal;
+use std::path::PathBuf;
 
-mod config;
-mod db;
-mod defaults;
-mod detect;
-mod doctor;
-mod proxy;
+use proxyctl_rs::dry_run::DryRun;
+use proxyctl_rs::output::Verbosity;
+use proxyctl_rs::{completions, config, db, detect, doctor, export, keychain, output, proxy};
+
+// Print through `output::log` at a `Verbosity`, formatting like `println!`.
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => {
+        output::log($level, format_args!($($arg)*))
+    };
+}
 
 #[derive(Parser)]
 #[command(name = "proxyctl-rs")]
 #[command(about = "A CLI tool for managing proxy configurations")]
 #[command(version, about, long_about = None)]
 struct Cli {
+    /// Suppress informational output; errors still go to stderr
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Print extra detail, such as each variable and profile being written
+    #[arg(short, long, global = true)]
+    verbose: bool,
+    /// Don't show a spinner while waiting on the network
+    #[arg(long, global = true)]
+    no_progress: bool,
+    /// Print the file and environment changes as `+`/`-` lines instead of making them
+    #[arg(short = 'n', long, global = true)]
+    dry_run: bool,
+    /// Apply the overrides of this named profile from config.toml
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+    JsonPretty,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Enable proxy configuration and add SSH hosts
-    On {
-        /// Proxy server URL (optional, will detect if not provided)
-        #[arg(short, long)]
-        proxy: Option<String>,
-    },
+    On(Box<OnArgs>),
     /// Disable proxy configuration and remove SSH hosts
     Off,
     /// Manage proxy configuration without touching SSH
@@ -34,22 +58,203 @@ enum Commands {
         action: ProxyCommands,
     },
     /// Detect and display the best regional proxy
-    Detect,
+    Detect {
+        /// Also apply the detected proxy (shell profiles and SSH hosts)
+        #[arg(long)]
+        set: bool,
+        /// With --set, skip updating the SSH config
+        #[arg(long, requires = "set", conflicts_with = "ssh_only")]
+        no_ssh: bool,
+        /// With --set, only update the SSH config and leave proxy env vars untouched
+        #[arg(long, requires = "set")]
+        ssh_only: bool,
+        /// Also show the structure of the WPAD file (proxies, regions, DIRECT fallback)
+        #[arg(long)]
+        validate: bool,
+        /// Keep the WPAD file's proxy order instead of picking the fastest to connect
+        #[arg(long)]
+        no_latency_test: bool,
+        /// URL to run the PAC file's FindProxyForURL for (default http://example.com/)
+        #[arg(long, value_name = "URL")]
+        for_url: Option<String>,
+        /// Fetch the WPAD file even if a cached copy is younger than wpad_cache_ttl_seconds
+        #[arg(long)]
+        refresh: bool,
+        /// List every proxy from the WPAD file with its connect latency, fastest first
+        #[arg(long, conflicts_with = "no_latency_test")]
+        all: bool,
+        /// Fail at once when the WPAD server can't be reached instead of retrying
+        #[arg(long)]
+        no_retry: bool,
+        /// Give up on the WPAD request after this many milliseconds (overrides wpad_timeout_ms)
+        #[arg(long, value_name = "MS")]
+        timeout: Option<u64>,
+    },
     /// Manage SSH configuration for proxy hosts
     Ssh {
         #[command(subcommand)]
         action: SshCommands,
     },
+    /// Inspect the hosts file against the SSH config
+    Hosts {
+        #[command(subcommand)]
+        action: HostsCommands,
+    },
     /// Show current status information
     Status {
         #[command(subcommand)]
         action: Option<StatusCommands>,
+        /// Output format; json and json-pretty apply to status, status proxy and status ssh
+        #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+        /// Exit with code 0 when a proxy is active and 1 otherwise
+        #[arg(long, group = "status_check")]
+        check: bool,
+        /// Exit with code 0 when all tracked SSH hosts are configured and 1 otherwise
+        #[arg(long, group = "status_check")]
+        check_ssh: bool,
+        /// Exit with code 0 when every shell profile has the managed block and 1 otherwise
+        #[arg(long, group = "status_check")]
+        check_profile: bool,
     },
     /// Run diagnostics or inspect configuration state
     Doctor {
         #[command(subcommand)]
         action: Option<DoctorCommands>,
     },
+    /// Audit or change config.toml
+    Config {
+        #[command(subcommand)]
+        action: ConfigCommands,
+    },
+    /// Write the current proxy to another tool's config file
+    Export {
+        #[command(subcommand)]
+        target: ExportCommands,
+    },
+    /// Remove the proxy written by `export` from another tool's config file
+    Unexport {
+        #[command(subcommand)]
+        target: ExportCommands,
+    },
+    /// Manage named profiles that override default_proxy, no_proxy and WPAD settings
+    Profile {
+        #[command(subcommand)]
+        action: ProfileCommands,
+    },
+    /// Export or import the current proxy state and history
+    Db {
+        #[command(subcommand)]
+        action: DbCommands,
+    },
+    /// Convert config.toml to the v2 layout with [proxy], [ssh], [shell], [detection] and [no_proxy] tables
+    MigrateConfig {
+        /// Keep a copy of the original file as config.toml.v1.bak
+        #[arg(long)]
+        backup: bool,
+    },
+    /// Generate shell completion scripts
+    Completions {
+        /// Shell to generate completions for
+        shell: clap_complete::Shell,
+        /// Prefix the script with instructions for installing it
+        #[arg(long)]
+        print_install_instructions: bool,
+    },
+}
+
+#[derive(Args, Default)]
+struct OnArgs {
+    /// Proxy server URL (optional, will detect if not provided)
+    #[arg(short, long)]
+    proxy: Option<String>,
+    /// Read the proxy URL and NO_PROXY entries from a .env file
+    #[arg(long, value_name = "PATH")]
+    env_file: Option<PathBuf>,
+    /// Prompt for a password and embed USERNAME credentials in the proxy URL
+    #[arg(long, value_name = "USERNAME")]
+    authenticate: Option<String>,
+    /// Save the prompted credentials to the system keychain (keyed by proxy host)
+    #[arg(long, requires = "authenticate")]
+    store: bool,
+    /// Load the proxy credentials from keychain PROFILE, prompting for new ones if expired
+    #[arg(long, value_name = "PROFILE", conflicts_with = "authenticate")]
+    rotate_credentials: Option<String>,
+    /// Skip WPAD detection and use default_proxy from config
+    #[arg(long)]
+    no_detect: bool,
+    /// Don't fetch WPAD; use the cached WPAD response, then default_proxy
+    #[arg(long, conflicts_with_all = ["no_detect", "wait_for_wpad"])]
+    no_wpad: bool,
+    /// Fetch the WPAD file even if a cached copy is younger than wpad_cache_ttl_seconds
+    #[arg(long, conflicts_with_all = ["no_detect", "no_wpad"])]
+    refresh: bool,
+    /// Fail at once when the WPAD server can't be reached instead of retrying
+    #[arg(long, conflicts_with_all = ["no_detect", "no_wpad"])]
+    no_retry: bool,
+    /// Keep the WPAD file's proxy order instead of picking the fastest to connect
+    #[arg(long, conflicts_with_all = ["no_detect", "no_wpad"])]
+    no_latency_test: bool,
+    /// Milliseconds allowed to connect to the WPAD server (overrides wpad_connect_timeout_ms)
+    #[arg(long, value_name = "MS")]
+    timeout_connect: Option<u64>,
+    /// Milliseconds allowed between reads of the WPAD response (overrides wpad_read_timeout_ms)
+    #[arg(long, value_name = "MS")]
+    timeout_read: Option<u64>,
+    /// Retry WPAD discovery with back-off for up to SECONDS before falling back to default_proxy
+    #[arg(long, value_name = "SECONDS", conflicts_with_all = ["proxy", "no_detect"])]
+    wait_for_wpad: Option<u64>,
+    /// Persist the resolved proxy URL as default_proxy in config
+    #[arg(long)]
+    save_default: bool,
+    /// After enabling, record the working proxy, WPAD URL and time in config
+    #[arg(long)]
+    update_config: bool,
+    /// Apply the proxy even if the URL fails validation
+    #[arg(long)]
+    force: bool,
+    /// Skip the confirmation prompt for --force
+    #[arg(short, long, requires = "force")]
+    yes: bool,
+    /// Note to store with this change in the proxy history
+    #[arg(long, value_name = "TEXT")]
+    comment: Option<String>,
+    /// Add the directly reachable local subnets (from the routing table) to no_proxy
+    #[arg(long)]
+    cidr_exclusions: bool,
+    /// Label this proxy session, shown in status and history
+    #[arg(long, value_name = "NAME")]
+    tag: Option<String>,
+    /// Only enable the proxy if a HEAD request to URL succeeds through it
+    #[arg(long, value_name = "URL")]
+    test_url: Option<String>,
+    /// Don't write shell profiles; only this process and the stored state change
+    #[arg(long)]
+    no_profile: bool,
+    /// Write the proxy exports to the user's shell profiles or system-wide files under /etc
+    #[arg(long, value_enum)]
+    scope: Option<config::ProxyScope>,
+    /// Only set the proxy variables that are not already set in this environment
+    #[arg(long, conflicts_with = "inherit_env")]
+    keep_existing: bool,
+    /// Take over the proxy already set in this shell's environment variables
+    #[arg(long, visible_alias = "from-env", conflicts_with_all = ["proxy", "env_file"])]
+    inherit_env: bool,
+    /// Also look for a proxy in environment variable NAME (repeatable)
+    #[arg(long, value_name = "NAME")]
+    extra_var: Vec<String>,
+    /// Print the changes as a shell script instead of applying them
+    #[arg(long, conflicts_with_all = ["save_default", "update_config", "test_url"])]
+    print_command: bool,
+    /// Show the proxy, variables, no_proxy, profiles and SSH hosts that would be configured, without changing anything
+    #[arg(
+        long,
+        conflicts_with_all = ["print_command", "save_default", "update_config", "test_url", "validate_pac"]
+    )]
+    preview: bool,
+    /// Fetch the WPAD file, show its structure and ask before enabling
+    #[arg(long, conflicts_with_all = ["proxy", "no_detect", "no_wpad", "print_command"])]
+    validate_pac: bool,
 }
 
 #[derive(Subcommand)]
@@ -59,29 +264,225 @@ enum SshCommands {
         /// Path to hosts file (optional, uses config default)
         #[arg(long)]
         hosts_file: Option<String>,
+        /// Text to add to the comment written above each ProxyCommand
+        #[arg(long, value_name = "TEXT")]
+        comment: Option<String>,
+        /// Skip the URLs listed in remote_hosts_files
+        #[arg(long, conflicts_with = "hosts_file")]
+        no_remote: bool,
+        /// Fetch remote_hosts_files URLs even if their cached copy is recent
+        #[arg(long, conflicts_with_all = ["hosts_file", "no_remote"])]
+        refresh_remote: bool,
     },
     /// Remove proxy hosts from SSH config
     Remove,
+    /// Show every Host block in the SSH config and its ProxyCommand
+    List {
+        /// Print the blocks as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Delete all but the most recent dated SSH config backups
+    BackupRotate {
+        /// Number of backups to keep (defaults to ssh_backup_count, or 5)
+        #[arg(long, value_name = "N")]
+        keep: Option<usize>,
+    },
 }
 
 #[derive(Subcommand)]
-enum ProxyCommands {
-    /// Enable proxy configuration only
-    On {
-        /// Proxy server URL (optional, will detect if not provided)
+enum HostsCommands {
+    /// Report which hosts are configured, missing, or using a stale proxy
+    Check {
+        /// Expected proxy server URL (optional, will detect if not provided)
         #[arg(short, long)]
         proxy: Option<String>,
     },
+}
+
+#[derive(Subcommand)]
+enum ProxyCommands {
+    /// Enable proxy configuration only
+    On(Box<OnArgs>),
     /// Disable proxy configuration only
-    Off,
+    Off {
+        /// Also warn about proxy variables proxyctl-rs did not set
+        #[arg(long)]
+        reset_env: bool,
+        /// With --reset-env, unset those variables too
+        #[arg(long, requires = "reset_env")]
+        force: bool,
+    },
+    /// Remove default_proxy from config
+    ClearDefault,
+    /// Show previous proxy enable/disable events
+    History {
+        /// Only show events from the last DURATION (e.g. 30m, 2h, 1d) or after an
+        /// ISO date or time (e.g. 2024-05-01, 2024-05-01T09:00:00Z)
+        #[arg(long, value_name = "DURATION|DATE")]
+        since: Option<String>,
+        /// Show at most the N most recent events
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
+    },
+    /// Set the comment on the most recent history entry
+    Tag {
+        /// Comment to store
+        comment: String,
+    },
+    /// Change the tag of the current proxy session
+    Retag {
+        /// New session tag
+        name: String,
+    },
+    /// Delete the cached WPAD files that later runs and --no-wpad reuse
+    FlushWpadCache,
 }
 
 #[derive(Subcommand, Clone)]
 enum DoctorCommands {
     /// Run diagnostics for configuration and database
-    Run,
+    Run {
+        /// Repair missing files, database tables and profile blocks first
+        #[arg(long)]
+        fix: bool,
+    },
     /// Display the current and default configuration values
-    Config,
+    Config {
+        /// Only show values set in the config file or environment
+        #[arg(long)]
+        only_changed: bool,
+        /// Never color the output, even on a terminal
+        #[arg(long)]
+        plain: bool,
+    },
+    /// Write a JSON diagnostic report for bug reports
+    Report {
+        /// File to write the report to (defaults to stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Look for other proxy tools or settings that may override proxyctl-rs
+    CheckConflictingTools,
+    /// Compare proxy variables in this shell with the stored state
+    CheckEnvConsistency,
+    /// Check that no_proxy covers localhost, loopback addresses and corporate domains
+    CheckNoProxyCoverage,
+    /// Make the SSH directory 0700 and the SSH config and its backups 0600
+    FixSshPermissions {
+        /// Apply the changes without asking for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Suggest config changes; exits 1 when a setting leaves proxyctl-rs unable to find a proxy
+    Audit,
+    /// Print one option's raw value for scripts; --or-default falls back to the built-in default
+    Get {
+        /// Option name; nested options use dots, as in proxy_settings.enable_http_proxy
+        key: String,
+        /// Print the built-in default when the option is unset
+        #[arg(long)]
+        or_default: bool,
+        /// Print nothing and exit 1 when the option is unset
+        #[arg(short, long)]
+        quiet: bool,
+    },
+    /// Set one option, e.g. `proxy_settings.enable_ftp_proxy false` or `no_proxy localhost,.corp`
+    Set {
+        /// Option name; nested options use dots, as in proxy_settings.enable_http_proxy
+        key: String,
+        /// New value: true/false, a number, text, a comma-separated list, or null to unset
+        value: String,
+        /// Add the comma-separated entries to a list option instead of replacing it
+        #[arg(long)]
+        append: bool,
+    },
+    /// Overwrite config.toml with the built-in defaults; without --confirm, only show the changes
+    Reset {
+        /// Write the defaults without asking
+        #[arg(long, visible_alias = "yes")]
+        confirm: bool,
+        /// Keep the credentials_file setting
+        #[arg(long)]
+        keep_credentials: bool,
+        /// Keep the [profiles] table
+        #[arg(long)]
+        keep_profiles: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ExportCommands {
+    /// The proxy and https-proxy keys of ~/.npmrc
+    Npm {
+        /// .npmrc to update instead of ~/.npmrc, such as a project's own
+        #[arg(long, value_name = "PATH")]
+        file: Option<PathBuf>,
+    },
+    /// The proxy key of the [global] section in pip's pip.conf
+    Pip {
+        /// pip config file to update instead of the per-user pip.conf
+        #[arg(long, value_name = "PATH")]
+        file: Option<PathBuf>,
+    },
+    /// The proxies.default object of Docker's daemon.json (/etc/docker/daemon.json)
+    Docker {
+        /// Update ~/.docker/daemon.json for a rootless daemon instead
+        #[arg(long)]
+        rootless: bool,
+        /// daemon.json to update instead
+        #[arg(long, value_name = "PATH", conflicts_with = "rootless")]
+        file: Option<PathBuf>,
+    },
+    /// git's http.proxy and https.proxy settings
+    Git {
+        /// Which git config to change
+        #[arg(long, value_enum, default_value_t = export::GitScope::Global)]
+        scope: export::GitScope,
+    },
+}
+
+#[derive(Subcommand)]
+enum DbCommands {
+    /// Write the current state and history as JSON, or the history as CSV
+    Export {
+        /// File format
+        #[arg(long, value_enum, default_value_t = db::ExportFormat::Json)]
+        format: db::ExportFormat,
+        /// File to write instead of stdout
+        #[arg(long, short, value_name = "PATH")]
+        output: Option<PathBuf>,
+        /// Keep proxy passwords in the export instead of masking them
+        #[arg(long)]
+        include_secrets: bool,
+    },
+    /// Replace the state and history with a file written by `db export`
+    Import {
+        /// JSON export, or a CSV export to restore only the history
+        file: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum ProfileCommands {
+    /// List the profiles in config.toml, marking the one selected with --profile
+    List,
+    /// Add a profile that uses PROXY as its default_proxy, or change its proxy
+    Add {
+        /// Profile name, as passed to --profile
+        name: String,
+        /// Proxy URL used as default_proxy while the profile is selected
+        proxy: String,
+    },
+    /// Delete a profile from config.toml
+    Delete {
+        /// Profile name
+        name: String,
+    },
 }
 
 #[derive(Subcommand, Clone)]
@@ -90,99 +491,1037 @@ enum StatusCommands {
     Proxy,
     /// Show only SSH status details
     Ssh,
+    /// Print KEY=VALUE per proxy variable from the stored state; exits 1 when no proxy is set
+    Machine {
+        /// Leave out variables that are not set
+        #[arg(long)]
+        omit_unset: bool,
+    },
+    /// Show proxy history from the last DURATION, oldest first
+    Since {
+        /// How far back to look, e.g. 30m, 2h or 1d
+        duration: String,
+    },
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // Load environment variables from .env file if it exists
-    let _ = dotenvy::dotenv();
-
-    // Initialize config directory and files
-    config::initialize_config()?;
-    db::init_db(&db::get_db_path()).await?;
+    let dotenv_path = dotenvy::dotenv().ok();
 
     let cli = Cli::parse();
 
+    // Initialize config directory and files. `config reset` replaces a
+    // config.toml that no longer parses, so it must not fail on one.
+    if let Err(err) = config::initialize_config() {
+        if !matches!(
+            cli.command,
+            Commands::Config {
+                action: ConfigCommands::Reset { .. }
+            }
+        ) {
+            return Err(err);
+        }
+    }
+    // A dry run must not create or migrate the database either.
+    if !cli.dry_run {
+        db::init_db(&db::get_db_path()).await?;
+    }
+
+    output::init(cli.quiet, cli.verbose);
+    output::set_progress(!cli.no_progress);
+    if let Some(path) = &dotenv_path {
+        log!(
+            Verbosity::Verbose,
+            "Loaded environment from {}",
+            path.display()
+        );
+    }
+    log!(
+        Verbosity::Verbose,
+        "Config file: {}",
+        config::get_config_dir()?.join("config.toml").display()
+    );
+    config::set_active_profile(cli.profile.as_deref());
+    if let Some(name) = &cli.profile {
+        config::load_config()?;
+        log!(Verbosity::Verbose, "Using profile {name}");
+    }
+    let dry_run = DryRun(cli.dry_run);
+    if dry_run.0 && rejects_dry_run(&cli.command) {
+        return Err(anyhow::anyhow!(
+            "--dry-run is not supported for this command"
+        ));
+    }
+
     match cli.command {
-        Commands::On { proxy } => {
-            let resolved = configure_proxy(proxy.as_deref()).await?;
-            let hosts_file = config::get_hosts_file_path()?.to_string_lossy().to_string();
-            config::add_ssh_hosts(&hosts_file, &resolved.proxy_host)?;
-            println!("Proxy enabled and SSH hosts added");
+        Commands::On(args) if args.print_command => print_proxy_commands(&args, true).await?,
+        Commands::On(args) if args.preview => print_proxy_preview(&args, true).await?,
+        Commands::On(args) => {
+            let resolved = configure_proxy(&args, dry_run).await?;
+            if !dry_run.0 {
+                config::refresh_remote_hosts(false).await?;
+            }
+            config::add_ssh_hosts_from_files(
+                &config::get_hosts_file_paths()?,
+                &resolved,
+                &config::SshAddOptions {
+                    dry_run,
+                    ..config::SshAddOptions::default()
+                },
+            )?;
+            report_done(dry_run, "Proxy enabled and SSH hosts added");
         }
         Commands::Off => {
-            proxy::disable_proxy().await?;
-            config::remove_ssh_hosts()?;
-            println!("Proxy disabled and SSH hosts removed");
+            proxy::disable_proxy(dry_run).await?;
+            config::remove_ssh_hosts(dry_run)?;
+            report_done(dry_run, "Proxy disabled and SSH hosts removed");
         }
         Commands::Proxy { action } => match action {
-            ProxyCommands::On { proxy } => {
-                configure_proxy(proxy.as_deref()).await?;
-                println!("Proxy enabled");
+            ProxyCommands::On(args) if args.print_command => {
+                print_proxy_commands(&args, false).await?
+            }
+            ProxyCommands::On(args) if args.preview => print_proxy_preview(&args, false).await?,
+            ProxyCommands::On(args) => {
+                configure_proxy(&args, dry_run).await?;
+                report_done(dry_run, "Proxy enabled");
+            }
+            ProxyCommands::Off { reset_env, force } => {
+                proxy::disable_proxy(dry_run).await?;
+                let force = force && !dry_run.0;
+                if reset_env {
+                    let found = proxy::reset_proxy_env(force);
+                    if force {
+                        log!(
+                            Verbosity::Normal,
+                            "Unset {} other proxy variable(s)",
+                            found.len()
+                        );
+                    } else if !found.is_empty() {
+                        log!(
+                            Verbosity::Normal,
+                            "Run with --reset-env --force to unset them"
+                        );
+                    }
+                }
+                report_done(dry_run, "Proxy disabled");
             }
-            ProxyCommands::Off => {
-                proxy::disable_proxy().await?;
-                println!("Proxy disabled");
+            ProxyCommands::ClearDefault => {
+                config::set_default_proxy(None)?;
+                log!(Verbosity::Normal, "Cleared default_proxy from config");
+            }
+            ProxyCommands::History { since, limit } => {
+                let limit = limit.unwrap_or(db::HISTORY_MAX_ROWS);
+                let entries = match since {
+                    Some(since) => {
+                        let since = history_since(&since)?;
+                        let mut entries =
+                            db::load_history_since(&db::get_db_path(), Some(since)).await?;
+                        entries.truncate(limit);
+                        entries
+                    }
+                    None => db::load_proxy_history(&db::get_db_path(), limit).await?,
+                };
+                println!("{}", format_history(&entries));
+            }
+            ProxyCommands::Tag { comment } => {
+                if !db::tag_latest_history(&db::get_db_path(), &comment).await? {
+                    return Err(anyhow::anyhow!("No proxy history to tag yet"));
+                }
+                log!(Verbosity::Normal, "Tagged latest history entry: {comment}");
+            }
+            ProxyCommands::Retag { name } => {
+                proxy::retag_session(&name).await?;
+                log!(Verbosity::Normal, "Session tagged \"{name}\"");
+            }
+            ProxyCommands::FlushWpadCache => {
+                if detect::flush_wpad_cache().await? {
+                    log!(Verbosity::Normal, "WPAD cache removed");
+                } else {
+                    log!(Verbosity::Normal, "No WPAD cache to remove");
+                }
             }
         },
-        Commands::Detect => {
-            let proxy = detect::detect_best_proxy().await?;
-            println!("Best regional proxy: {proxy}");
+        Commands::Detect {
+            set,
+            no_ssh,
+            ssh_only,
+            validate,
+            no_latency_test,
+            for_url,
+            refresh,
+            all,
+            no_retry,
+            timeout,
+        } => {
+            let wpad_client = detect::WpadClientConfig::builder()
+                .skip_latency_test(no_latency_test)
+                .refresh(refresh)
+                .no_retry(no_retry)
+                .timeout_ms(timeout)
+                .build();
+            if validate {
+                let analysis = detect::analyze_wpad(&wpad_client).await?;
+                println!("{}", analysis.render());
+            }
+            let detected = if all {
+                let (_, candidates) =
+                    detect::detect_scored_candidates(&wpad_client, for_url.as_deref()).await?;
+                for (rank, candidate) in candidates.iter().enumerate() {
+                    println!("{:>2}. {}", rank + 1, candidate.render());
+                }
+                if !set {
+                    return Ok(());
+                }
+                detect::best_candidate(&candidates)?
+            } else {
+                detect::detect_best_proxy(&wpad_client, for_url.as_deref()).await?
+            };
+            if !set {
+                println!("Best regional proxy: {detected}");
+                return Ok(());
+            }
+
+            let resolved = if ssh_only {
+                proxy::resolve_proxy(Some(&detected), &proxy::ResolveOptions::default()).await?
+            } else {
+                configure_proxy(
+                    &OnArgs {
+                        proxy: Some(detected),
+                        ..OnArgs::default()
+                    },
+                    dry_run,
+                )
+                .await?
+            };
+
+            if !no_ssh {
+                if !dry_run.0 {
+                    config::refresh_remote_hosts(false).await?;
+                }
+                config::add_ssh_hosts_from_files(
+                    &config::get_hosts_file_paths()?,
+                    &resolved,
+                    &config::SshAddOptions {
+                        dry_run,
+                        ..config::SshAddOptions::default()
+                    },
+                )?;
+            }
+            report_done(
+                dry_run,
+                &format!("Best proxy detected and set: {}", resolved.proxy_url),
+            );
         }
         Commands::Ssh { action } => match action {
-            SshCommands::Add { hosts_file } => {
-                let resolved = proxy::resolve_proxy(None).await?;
-                let file = hosts_file.unwrap_or_else(|| {
-                    config::get_hosts_file_path()
-                        .map(|p| p.to_string_lossy().to_string())
-                        .unwrap_or_else(|_| "default_hosts.example.txt".to_string())
-                });
-                config::add_ssh_hosts(&file, &resolved.proxy_host)?;
-                println!("SSH hosts added from {file}");
+            SshCommands::Add {
+                hosts_file,
+                comment,
+                no_remote,
+                refresh_remote,
+            } => {
+                let resolved =
+                    proxy::resolve_proxy(None, &proxy::ResolveOptions::default()).await?;
+                let files = match hosts_file {
+                    Some(file) => vec![PathBuf::from(file)],
+                    None if no_remote => config::get_local_hosts_file_paths()?,
+                    None => {
+                        if !dry_run.0 {
+                            config::refresh_remote_hosts(refresh_remote).await?;
+                        }
+                        config::get_hosts_file_paths()
+                            .unwrap_or_else(|_| vec![PathBuf::from("default_hosts.example.txt")])
+                    }
+                };
+                let options = config::SshAddOptions { comment, dry_run };
+                config::add_ssh_hosts_from_files(&files, &resolved, &options)?;
+                let names: Vec<String> = files
+                    .iter()
+                    .map(|file| file.display().to_string())
+                    .collect();
+                report_done(
+                    dry_run,
+                    &format!("SSH hosts added from {}", names.join(", ")),
+                );
             }
             SshCommands::Remove => {
-                config::remove_ssh_hosts()?;
-                println!("SSH hosts removed");
+                config::remove_ssh_hosts(dry_run)?;
+                report_done(dry_run, "SSH hosts removed");
+            }
+            SshCommands::List { json } => {
+                let blocks = config::list_ssh_host_blocks(&config::get_ssh_config_path()?)?;
+                if json {
+                    println!("{}", serde_json::to_string_pretty(&blocks)?);
+                } else {
+                    println!("{}", format_ssh_host_blocks(&blocks));
+                }
+            }
+            SshCommands::BackupRotate { keep } => {
+                let ssh_config_path = config::get_ssh_config_path()?;
+                let ssh_dir = ssh_config_path
+                    .parent()
+                    .ok_or_else(|| anyhow::anyhow!("SSH config path has no parent directory"))?;
+                let keep = keep.unwrap_or_else(config::get_ssh_backup_count);
+                let summary = if dry_run.0 {
+                    config::plan_ssh_backup_rotation(ssh_dir, keep)?
+                } else {
+                    config::rotate_ssh_backups(ssh_dir, keep)?
+                };
+                let verb = if dry_run.0 { "Would delete" } else { "Deleted" };
+                for path in &summary.deleted {
+                    log!(Verbosity::Normal, "{verb} {}", path.display());
+                }
+                log!(
+                    Verbosity::Normal,
+                    "{verb} {} backup(s), keeping {}",
+                    summary.deleted.len(),
+                    summary.kept.len()
+                );
             }
         },
-        Commands::Status { action } => match action {
+        Commands::Hosts { action } => match action {
+            HostsCommands::Check { proxy } => {
+                let resolved =
+                    proxy::resolve_proxy(proxy.as_deref(), &proxy::ResolveOptions::default())
+                        .await?;
+                let audit = config::audit_ssh_hosts(
+                    &config::get_hosts_file_paths()?,
+                    &config::get_ssh_config_path()?,
+                    &resolved.proxy_host,
+                )?;
+                println!("{}", format_hosts_audit(&audit));
+                if !audit.is_clean() {
+                    return Err(anyhow::anyhow!("hosts check found issues"));
+                }
+            }
+        },
+        Commands::Status {
+            check,
+            check_ssh,
+            check_profile,
+            ..
+        } if check || check_ssh || check_profile => {
+            let (label, passed) = if check {
+                ("proxy active", proxy::is_proxy_active().await?)
+            } else if check_ssh {
+                (
+                    "ssh configured",
+                    config::get_ssh_status()?.is_fully_configured(),
+                )
+            } else {
+                ("profile managed", proxy::profiles_have_managed_block()?)
+            };
+
+            log!(
+                Verbosity::Verbose,
+                "{label}: {}",
+                if passed { "yes" } else { "no" }
+            );
+            std::process::exit(if passed { 0 } else { 1 });
+        }
+        Commands::Status { action, format, .. } if format != OutputFormat::Text => {
+            let (proxy, ssh) = match action {
+                Some(StatusCommands::Proxy) => (true, false),
+                Some(StatusCommands::Ssh) => (false, true),
+                None => (true, true),
+                Some(_) => {
+                    return Err(anyhow::anyhow!(
+                        "--format json is only supported by status, status proxy and status ssh"
+                    ))
+                }
+            };
+            print_status_json(format, proxy, ssh).await?;
+        }
+        Commands::Status { action, .. } => match action {
             Some(StatusCommands::Proxy) => {
                 print_proxy_status().await?;
             }
             Some(StatusCommands::Ssh) => {
                 print_ssh_status()?;
             }
+            Some(StatusCommands::Machine { omit_unset }) => {
+                let lines = proxy::get_machine_status(omit_unset).await?;
+                if !lines.is_empty() {
+                    println!("{lines}");
+                }
+                let active = proxy::is_proxy_active().await?;
+                std::process::exit(if active { 0 } else { 1 });
+            }
+            Some(StatusCommands::Since { duration }) => {
+                let since = history_cutoff(&duration)?;
+                let mut entries = db::load_history_since(&db::get_db_path(), Some(since)).await?;
+                entries.reverse();
+                println!("{}", format_history_timeline(&entries, &duration));
+            }
             None => {
                 print_proxy_status().await?;
                 println!();
                 print_ssh_status()?;
             }
         },
-        Commands::Doctor { action } => match action.unwrap_or(DoctorCommands::Run) {
-            DoctorCommands::Run => {
+        Commands::Doctor { action } => match action.unwrap_or(DoctorCommands::Run { fix: false }) {
+            DoctorCommands::Run { fix: true } => {
+                doctor::run_with_fixes().await?;
+            }
+            DoctorCommands::Run { fix: false } => {
                 doctor::run().await?;
             }
-            DoctorCommands::Config => {
-                doctor::print_config()?;
+            DoctorCommands::Config {
+                only_changed,
+                plain,
+            } => {
+                let color = if plain {
+                    output::ColorMode::Never
+                } else {
+                    output::ColorMode::Auto
+                };
+                doctor::print_config(only_changed, color)?;
+            }
+            DoctorCommands::Report { output } => {
+                doctor::write_report(output.as_deref()).await?;
+            }
+            DoctorCommands::CheckConflictingTools => {
+                doctor::print_conflicting_tools();
+            }
+            DoctorCommands::CheckEnvConsistency => {
+                doctor::print_env_consistency().await;
+            }
+            DoctorCommands::CheckNoProxyCoverage => {
+                doctor::print_no_proxy_coverage()?;
+            }
+            DoctorCommands::FixSshPermissions { yes } => {
+                fix_ssh_permissions(yes)?;
+            }
+        },
+        Commands::Config { action } => match action {
+            ConfigCommands::Audit => {
+                let suggestions = config::audit_config(&config::load_config()?);
+                println!("{}", format_audit(&suggestions));
+                if suggestions
+                    .iter()
+                    .any(|suggestion| suggestion.severity == config::Severity::Error)
+                {
+                    return Err(anyhow::anyhow!("config audit found errors"));
+                }
+            }
+            ConfigCommands::Get {
+                key,
+                or_default,
+                quiet,
+            } => {
+                let config = config::load_config()?;
+                let value = if or_default {
+                    config::get_config_value_or_default(&config, &key)?
+                } else {
+                    config::get_config_value(&config, &key)?
+                };
+                match raw_config_value(&value) {
+                    Some(text) => println!("{text}"),
+                    None if quiet => std::process::exit(1),
+                    None => println!(),
+                }
+            }
+            ConfigCommands::Set { key, value, append } => {
+                let updated =
+                    config::set_config_value(&config::load_base_config()?, &key, &value, append)?;
+                config::save_config(&updated)?;
+                log!(
+                    Verbosity::Normal,
+                    "Set {key} = {}",
+                    config::get_config_value(&updated, &key)?
+                );
+            }
+            ConfigCommands::Reset {
+                confirm: confirmed,
+                keep_credentials,
+                keep_profiles,
+            } => {
+                let preview = config::ResetOptions {
+                    keep_credentials,
+                    keep_profiles,
+                    dry_run: DryRun(true),
+                };
+                if !confirmed || dry_run.0 {
+                    config::reset_config_file(&preview)?;
+                }
+                if dry_run.0 {
+                    report_done(dry_run, "");
+                } else if confirmed
+                    || (std::io::stdin().is_terminal()
+                        && confirm("Reset config.toml to the defaults? [y/N]")?)
+                {
+                    config::reset_config_file(&config::ResetOptions {
+                        dry_run: DryRun(false),
+                        ..preview
+                    })?;
+                    log!(Verbosity::Normal, "Reset config.toml to the defaults");
+                } else {
+                    log!(
+                        Verbosity::Normal,
+                        "Nothing was changed; run `proxyctl-rs config reset --confirm` to reset"
+                    );
+                }
             }
         },
+        Commands::Completions {
+            shell,
+            print_install_instructions,
+        } => {
+            completions::generate(
+                shell,
+                &mut Cli::command(),
+                print_install_instructions,
+                &mut std::io::stdout(),
+            )?;
+        }
+        Commands::Export { target } => {
+            let destination = export_proxy(target, dry_run).await?;
+            report_done(dry_run, &format!("Exported the proxy to {destination}"));
+        }
+        Commands::Unexport { target } => {
+            let destination = unexport_proxy(target, dry_run)?;
+            report_done(dry_run, &format!("Removed the proxy from {destination}"));
+        }
+        Commands::Profile { action } => match action {
+            ProfileCommands::List => {
+                println!("{}", format_profiles(&config::list_profiles()?));
+            }
+            ProfileCommands::Add { name, proxy } => {
+                config::add_profile(&name, &proxy)?;
+                log!(Verbosity::Normal, "Saved profile {name}");
+            }
+            ProfileCommands::Delete { name } => {
+                config::delete_profile(&name)?;
+                log!(Verbosity::Normal, "Deleted profile {name}");
+            }
+        },
+        Commands::Db { action } => match action {
+            DbCommands::Export {
+                format,
+                output,
+                include_secrets,
+            } => {
+                let mut dump = db::load_dump(&db::get_db_path()).await?;
+                if !include_secrets {
+                    dump = dump.masked(proxy::mask_proxy_credentials);
+                }
+                let text = db::render_dump(&dump, format)?;
+                match output {
+                    Some(path) => {
+                        db::write_private_file(&path, &text)?;
+                        log!(
+                            Verbosity::Normal,
+                            "Exported {} history entries to {}",
+                            dump.history.len(),
+                            path.display()
+                        );
+                    }
+                    None => print!("{text}"),
+                }
+            }
+            DbCommands::Import { file } => {
+                let text = std::fs::read_to_string(&file)?;
+                let count = if text.trim_start().starts_with('{') {
+                    let dump: db::DbDump = serde_json::from_str(&text)?;
+                    db::import_dump(&db::get_db_path(), Some(&dump.current_state), &dump.history)
+                        .await?;
+                    dump.history.len()
+                } else {
+                    let history = db::parse_history_csv(&text)?;
+                    db::import_dump(&db::get_db_path(), None, &history).await?;
+                    log!(
+                        Verbosity::Normal,
+                        "CSV exports hold no current state; it was left unchanged"
+                    );
+                    history.len()
+                };
+                log!(
+                    Verbosity::Normal,
+                    "Imported {count} history entries from {}",
+                    file.display()
+                );
+            }
+        },
+        Commands::MigrateConfig { backup } => {
+            if let Some(path) = config::migrate_config_file(backup)? {
+                log!(
+                    Verbosity::Normal,
+                    "Backed up previous config to {}",
+                    path.display()
+                );
+            }
+            log!(
+                Verbosity::Normal,
+                "Config migrated to schema v{}",
+                config::CONFIG_SCHEMA_V2
+            );
+        }
     }
 
     Ok(())
 }
 
-async fn configure_proxy(proxy: Option<&str>) -> Result<proxy::ResolvedProxy> {
-    let resolved = proxy::resolve_proxy(proxy).await?;
-    proxy::set_proxy(&resolved.proxy_url).await?;
+// Commands that change state but have no dry-run preview.
+fn rejects_dry_run(command: &Commands) -> bool {
+    matches!(
+        command,
+        Commands::Proxy {
+            action: ProxyCommands::ClearDefault
+                | ProxyCommands::Tag { .. }
+                | ProxyCommands::Retag { .. }
+                | ProxyCommands::FlushWpadCache,
+        } | Commands::Config {
+            action: ConfigCommands::Set { .. },
+        } | Commands::Profile {
+            action: ProfileCommands::Add { .. } | ProfileCommands::Delete { .. },
+        } | Commands::Db {
+            action: DbCommands::Export {
+                output: Some(_),
+                ..
+            } | DbCommands::Import { .. },
+        } | Commands::MigrateConfig { .. }
+            | Commands::Doctor {
+                action: Some(
+                    DoctorCommands::FixSshPermissions { .. }
+                        | DoctorCommands::Run { fix: true }
+                        | DoctorCommands::Report { output: Some(_) }
+                ),
+            }
+    )
+}
+
+// Write the stored proxy to the tool `target` names; returns the file (or
+// tool) changed.
+async fn export_proxy(target: ExportCommands, dry_run: DryRun) -> Result<String> {
+    let path = match target {
+        ExportCommands::Npm { file } => {
+            let path = file.map_or_else(export::default_npmrc_path, Ok)?;
+            export::export_npm(&path, dry_run).await?;
+            path
+        }
+        ExportCommands::Pip { file } => {
+            let path = file.map_or_else(export::default_pip_conf_path, Ok)?;
+            export::export_pip(&path, dry_run).await?;
+            path
+        }
+        ExportCommands::Docker { rootless, file } => {
+            let path = file.map_or_else(|| export::default_docker_daemon_path(rootless), Ok)?;
+            export::export_docker(&path, dry_run).await?;
+            path
+        }
+        ExportCommands::Git { scope } => {
+            export::export_git(scope, dry_run).await?;
+            return Ok("git".to_string());
+        }
+    };
+    Ok(path.display().to_string())
+}
+
+// Undo `export_proxy` for `target`.
+fn unexport_proxy(target: ExportCommands, dry_run: DryRun) -> Result<String> {
+    let path = match target {
+        ExportCommands::Npm { file } => {
+            let path = file.map_or_else(export::default_npmrc_path, Ok)?;
+            export::unexport_npm(&path, dry_run)?;
+            path
+        }
+        ExportCommands::Pip { file } => {
+            let path = file.map_or_else(export::default_pip_conf_path, Ok)?;
+            export::unexport_pip(&path, dry_run)?;
+            path
+        }
+        ExportCommands::Docker { rootless, file } => {
+            let path = file.map_or_else(|| export::default_docker_daemon_path(rootless), Ok)?;
+            export::unexport_docker(&path, dry_run)?;
+            path
+        }
+        ExportCommands::Git { scope } => {
+            export::unexport_git(scope, dry_run)?;
+            return Ok("git".to_string());
+        }
+    };
+    Ok(path.display().to_string())
+}
+
+// One line per profile with its proxy; `*` marks the one selected with --profile.
+fn format_profiles(
+    profiles: &std::collections::BTreeMap<String, config::ProfileOverride>,
+) -> String {
+    if profiles.is_empty() {
+        return "No profiles configured".to_string();
+    }
+    let active = config::get_active_profile();
+    profiles
+        .iter()
+        .map(|(name, profile)| {
+            let marker = if active.as_deref() == Some(name.as_str()) {
+                "*"
+            } else {
+                " "
+            };
+            let proxy = profile
+                .default_proxy
+                .as_deref()
+                .map(proxy::mask_proxy_credentials)
+                .unwrap_or_else(|| "(base default_proxy)".to_string());
+            format!("{marker} {}: {proxy}", name.bold())
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+// The success message, or a reminder that a dry run changed nothing.
+fn report_done(dry_run: DryRun, message: &str) {
+    if dry_run.0 {
+        log!(Verbosity::Normal, "Dry run: nothing was changed");
+    } else {
+        log!(Verbosity::Normal, "{message}");
+    }
+}
+
+/// A resolved proxy (with any credentials applied) and how to set it.
+struct PreparedProxy {
+    resolved: proxy::ResolvedProxy,
+    options: proxy::SetProxyOptions,
+    /// The resolved URL without any credentials.
+    default_url: String,
+    /// Credentials for `--store` to save in the keychain, keyed by proxy
+    /// host, once the proxy has been set.
+    store_credentials: Option<(String, keychain::ProxyCredentials)>,
+}
+
+fn wpad_client_config(args: &OnArgs, read_only: bool) -> detect::WpadClientConfig {
+    detect::WpadClientConfig::builder()
+        .read_only(read_only)
+        .no_retry(args.no_retry)
+        .skip_latency_test(args.no_latency_test)
+        .connect_timeout_ms(args.timeout_connect)
+        .read_timeout_ms(args.timeout_read)
+        .refresh(args.refresh)
+        .build()
+}
+
+// With `read_only` (dry runs, --preview, --print-command) nothing is written:
+// not the WPAD caches and not the keychain.
+async fn prepare_proxy(args: &OnArgs, read_only: bool) -> Result<PreparedProxy> {
+    let mut options = proxy::SetProxyOptions {
+        force: args.force,
+        comment: args.comment.clone(),
+        tag: args.tag.clone(),
+        cidr_exclusions: args.cidr_exclusions,
+        no_profile: args.no_profile,
+        scope: args.scope,
+        proxy_settings: if args.keep_existing {
+            Some(proxy::keep_existing_proxy_settings(
+                config::get_proxy_settings()?,
+            ))
+        } else {
+            None
+        },
+        ..proxy::SetProxyOptions::default()
+    };
+    let mut proxy = args.proxy.clone();
+
+    if args.inherit_env {
+        let (key, inherited) = proxy::proxy_from_env_explicit(&args.extra_var)?;
+        log!(
+            Verbosity::Normal,
+            "Inherited {} from {key}",
+            proxy::mask_proxy_credentials(&inherited.proxy_url)
+        );
+        proxy = Some(inherited.proxy_url);
+    }
+
+    if let Some(path) = &args.env_file {
+        let env_file = proxy::read_env_file(path)?;
+        if proxy.is_none() {
+            proxy = env_file.proxy_url;
+        }
+        options.extra_no_proxy = env_file.no_proxy;
+    }
+
+    let resolve_options = proxy::ResolveOptions {
+        mode: if args.no_detect {
+            Some(config::ResolveMode::DefaultOnly)
+        } else {
+            args.no_wpad.then_some(config::ResolveMode::NoWpad)
+        },
+        wpad_wait: args.wait_for_wpad.map(std::time::Duration::from_secs),
+        extra_vars: args.extra_var.clone(),
+        wpad_client: Some(wpad_client_config(args, read_only)),
+        force: args.force,
+    };
+    let mut resolved = proxy::resolve_proxy(proxy.as_deref(), &resolve_options).await?;
+    let mut store_credentials = None;
+    // What --save-default and --update-config write; any credentials, whether
+    // given in --proxy, inherited or embedded below, stay out of config.toml.
+    let default_url = proxy::strip_proxy_credentials(&resolved.proxy_url);
+
+    if let Some(username) = &args.authenticate {
+        let password = rpassword::prompt_password(format!("Proxy password for {username}: "))?;
+        let authenticated = proxy::with_credentials(&resolved.proxy_url, username, &password)?;
+        if !args.force {
+            config::validate_proxy_url(&authenticated)?;
+        }
+
+        if args.store && read_only {
+            log!(
+                Verbosity::Normal,
+                "Would store credentials for {} in keychain",
+                resolved.proxy_host
+            );
+        } else if args.store {
+            let credentials = keychain::ProxyCredentials {
+                username: username.clone(),
+                password,
+                expires_at: None,
+            };
+            store_credentials = Some((resolved.proxy_host.clone(), credentials));
+        }
+
+        resolved.proxy_url = authenticated;
+    }
+
+    if let Some(profile) = &args.rotate_credentials {
+        let credentials = if read_only {
+            keychain::load_proxy_credentials(
+                &keychain::ReadOnlyStore(&keychain::SystemKeychain),
+                profile,
+                prompt_credentials,
+            )?
+        } else {
+            keychain::load_proxy_credentials(
+                &keychain::SystemKeychain,
+                profile,
+                prompt_credentials,
+            )?
+        };
+        let authenticated = proxy::with_credentials(
+            &resolved.proxy_url,
+            &credentials.username,
+            &credentials.password,
+        )?;
+        if !args.force {
+            config::validate_proxy_url(&authenticated)?;
+        }
+        log!(
+            Verbosity::Normal,
+            "Using credentials for {} from keychain profile '{profile}'",
+            credentials.username
+        );
+        resolved.proxy_url = authenticated;
+    }
+
+    Ok(PreparedProxy {
+        resolved,
+        options,
+        default_url,
+        store_credentials,
+    })
+}
+
+async fn configure_proxy(args: &OnArgs, dry_run: DryRun) -> Result<proxy::ResolvedProxy> {
+    if args.validate_pac {
+        let analysis = detect::analyze_wpad(&wpad_client_config(args, dry_run.0)).await?;
+        println!("{}", analysis.render());
+        if !confirm("Enable the proxy from this PAC file? [y/N]")? {
+            return Err(anyhow::anyhow!("Aborted; proxy settings left unchanged"));
+        }
+    }
+
+    let PreparedProxy {
+        resolved,
+        mut options,
+        default_url,
+        store_credentials,
+    } = prepare_proxy(args, dry_run.0).await?;
+    options.dry_run = dry_run;
+
+    if args.force
+        && !args.yes
+        && !confirm("This proxy URL may be invalid or unreachable. Continue? [y/N]")?
+    {
+        return Err(anyhow::anyhow!("Aborted; proxy settings left unchanged"));
+    }
+
+    let test_url = match &args.test_url {
+        Some(url) => Some(url.clone()),
+        None => config::load_config()?.pre_flight_test_url,
+    };
+    if let Some(test_url) = test_url {
+        proxy::preflight_check(&resolved.proxy_url, &test_url).await?;
+    }
+
+    proxy::set_proxy(&resolved.proxy_url, &options).await?;
+    if dry_run.0 {
+        return Ok(resolved);
+    }
+
+    if let Some((host, credentials)) = store_credentials {
+        use keychain::CredentialStore;
+        keychain::SystemKeychain.store(&host, &credentials)?;
+        log!(
+            Verbosity::Normal,
+            "Stored credentials for {host} in keychain"
+        );
+    }
+
+    if args.save_default {
+        config::set_default_proxy(Some(&default_url))?;
+        log!(
+            Verbosity::Normal,
+            "Saved {default_url} as default_proxy in config"
+        );
+    }
+
+    if args.update_config {
+        let timestamp =
+            config::record_successful_proxy(&default_url, resolved.wpad_url.as_deref())?;
+        let mut updated = vec![format!("default_proxy = '{default_url}'")];
+        if let Some(wpad_url) = &resolved.wpad_url {
+            updated.push(format!("wpad_url = '{wpad_url}'"));
+        }
+        updated.push(format!("last_successful_proxy_at = '{timestamp}'"));
+        log!(Verbosity::Normal, "Updated config: {}", updated.join(", "));
+    }
+
     Ok(resolved)
 }
 
+fn plan_operations(prepared: &PreparedProxy, with_ssh: bool) -> Result<Vec<proxy::ProxyOperation>> {
+    let mut operations = proxy::plan_proxy_setup(&prepared.resolved.proxy_url, &prepared.options)?;
+    if with_ssh {
+        operations.extend(proxy::plan_ssh_setup(
+            &config::get_hosts_file_paths()?,
+            &prepared.resolved,
+        )?);
+    }
+    Ok(operations)
+}
+
+async fn print_proxy_commands(args: &OnArgs, with_ssh: bool) -> Result<()> {
+    // Informational messages would end up in the script, so keep stdout for it alone.
+    output::init(true, false);
+    let prepared = prepare_proxy(args, true).await?;
+    let operations = plan_operations(&prepared, with_ssh)?;
+    print!("{}", proxy::render_operations_script(&operations));
+    Ok(())
+}
+
+async fn print_proxy_preview(args: &OnArgs, with_ssh: bool) -> Result<()> {
+    let prepared = prepare_proxy(args, true).await?;
+    let operations = plan_operations(&prepared, with_ssh)?;
+    println!(
+        "{}",
+        format_proxy_preview(&prepared.resolved, &operations, with_ssh)
+    );
+    Ok(())
+}
+
+fn format_proxy_preview(
+    resolved: &proxy::ResolvedProxy,
+    operations: &[proxy::ProxyOperation],
+    with_ssh: bool,
+) -> String {
+    let mut variables = Vec::new();
+    let mut no_proxy = None;
+    let mut profiles = Vec::new();
+    let mut ssh_hosts = Vec::new();
+    for operation in operations {
+        match operation {
+            proxy::ProxyOperation::SetEnvVar(key, value) => {
+                if key.eq_ignore_ascii_case("no_proxy") {
+                    no_proxy = Some(value.clone());
+                }
+                variables.push(key.clone());
+            }
+            proxy::ProxyOperation::WriteProfile(path, _) => {
+                profiles.push(path.display().to_string())
+            }
+            proxy::ProxyOperation::AddSshProxyCommand(_, host_line, _) => {
+                // `Host a b` or `Match host=a,b` -> `a b`
+                ssh_hosts.push(config::ssh_block_patterns(host_line).join(" "));
+            }
+        }
+    }
+
+    let none = |values: Vec<String>| {
+        if values.is_empty() {
+            vec!["none".to_string()]
+        } else {
+            values
+        }
+    };
+    let rows: Vec<(&str, Vec<String>)> = vec![
+        (
+            "Proxy",
+            vec![proxy::mask_proxy_credentials(&resolved.proxy_url)],
+        ),
+        (
+            "WPAD URL",
+            vec![resolved.wpad_url.clone().unwrap_or_else(|| "-".to_string())],
+        ),
+        ("Variables", vec![variables.join(", ")]),
+        (
+            "no_proxy",
+            vec![no_proxy.unwrap_or_else(|| "disabled".to_string())],
+        ),
+        ("Profiles", none(profiles)),
+        (
+            "SSH hosts",
+            if with_ssh {
+                none(ssh_hosts)
+            } else {
+                vec!["unchanged by proxy on".to_string()]
+            },
+        ),
+    ];
+
+    let width = rows
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0)
+        .max("SETTING".len());
+    let mut lines = vec![format!("{:<width$}  {}", "SETTING", "VALUE")
+        .bold()
+        .to_string()];
+    for (name, values) in rows {
+        for (index, value) in values.iter().enumerate() {
+            let name = if index == 0 { name } else { "" };
+            lines.push(format!("{name:<width$}  {value}"));
+        }
+    }
+    lines.push("Preview only; nothing was changed".to_string());
+    lines.join("\n")
+}
+
 async fn print_proxy_status() -> Result<()> {
     let status = proxy::get_status().await?;
     println!("{status}");
     Ok(())
 }
 
+async fn print_status_json(format: OutputFormat, proxy: bool, ssh: bool) -> Result<()> {
+    let mut report = serde_json::Map::new();
+    if proxy {
+        let status = proxy::get_status_report().await?;
+        report.insert("proxy".to_string(), serde_json::to_value(status)?);
+    }
+    if ssh {
+        let status = config::get_ssh_status()?;
+        report.insert("ssh".to_string(), serde_json::to_value(status)?);
+    }
+    let report = serde_json::Value::Object(report);
+    let rendered = if format == OutputFormat::JsonPretty {
+        serde_json::to_string_pretty(&report)?
+    } else {
+        serde_json::to_string(&report)?
+    };
+    println!("{rendered}");
+    Ok(())
+}
+
 fn print_ssh_status() -> Result<()> {
     let status = config::get_ssh_status()?;
     println!("{}", format_ssh_status(&status));
@@ -214,15 +1553,17 @@ fn format_ssh_status(status: &config::SshStatus) -> String {
             " (missing)"
         }
     ));
-    lines.push(format!(
-        "Hosts file: {}{}",
-        status.hosts_path.display(),
-        if status.hosts_file_exists {
-            ""
-        } else {
-            " (missing)"
-        }
-    ));
+    for hosts_path in &status.hosts_paths {
+        lines.push(format!(
+            "Hosts file: {}{}",
+            hosts_path.display(),
+            if hosts_path.exists() {
+                ""
+            } else {
+                " (missing)"
+            }
+        ));
+    }
 
     if status.hosts_file_exists {
         if status.hosts.is_empty() {
@@ -254,3 +1595,303 @@ fn format_ssh_status(status: &config::SshStatus) -> String {
 
     lines.join("\n")
 }
+
+fn format_hosts_audit(audit: &config::HostsAudit) -> String {
+    let mut lines = Vec::new();
+
+    for host in &audit.correct {
+        lines.push(format!("  {} {host}", "✓".green()));
+    }
+    for host in &audit.missing_proxy {
+        lines.push(format!(
+            "  {} {host} {}",
+            "✗".red(),
+            "(missing ProxyCommand)".red()
+        ));
+    }
+    for (host, current, expected) in &audit.stale_proxy {
+        lines.push(format!(
+            "  {} {host} {}",
+            "!".yellow(),
+            format!("(stale proxy {current}, expected {expected})").yellow()
+        ));
+    }
+
+    let summary = if audit.is_clean() {
+        "All hosts configured".green().bold().to_string()
+    } else {
+        format!(
+            "{} missing, {} stale",
+            audit.missing_proxy.len(),
+            audit.stale_proxy.len()
+        )
+        .yellow()
+        .bold()
+        .to_string()
+    };
+
+    lines.insert(0, format!("{}: {}", "Hosts Check".bold(), summary));
+    lines.join("\n")
+}
+
+fn format_history(entries: &[db::HistoryEntry]) -> String {
+    if entries.is_empty() {
+        return "No proxy history recorded".to_string();
+    }
+
+    let mut lines = vec![format!(
+        "{:<5} {:<25} {:<6} {:<40} {:<20} {}",
+        "ID", "WHEN", "ACTION", "PROXY", "TAG", "COMMENT"
+    )
+    .bold()
+    .to_string()];
+    for entry in entries {
+        lines.push(format!(
+            "{:<5} {:<25} {:<6} {:<40} {:<20} {}",
+            entry.id,
+            entry.activated_at,
+            entry.action,
+            entry
+                .proxy_url
+                .as_deref()
+                .map_or_else(|| "-".to_string(), proxy::mask_proxy_credentials),
+            entry.tag.as_deref().unwrap_or("-"),
+            entry.comment.as_deref().unwrap_or("")
+        ));
+    }
+    lines.join("\n")
+}
+
+/// The earliest history timestamp within `duration` of now.
+fn history_cutoff(duration: &str) -> Result<chrono::DateTime<chrono::Utc>> {
+    let duration = chrono::Duration::from_std(db::parse_duration(duration)?)?;
+    Ok(chrono::Utc::now()
+        .checked_sub_signed(duration)
+        .unwrap_or(chrono::DateTime::<chrono::Utc>::MIN_UTC))
+}
+
+/// The cutoff for `proxy history --since`: an RFC 3339 time, a date (its
+/// start, in UTC) or a duration before now.
+fn history_since(since: &str) -> Result<chrono::DateTime<chrono::Utc>> {
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(since) {
+        return Ok(time.with_timezone(&chrono::Utc));
+    }
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(since, "%Y-%m-%d") {
+        return Ok(date.and_time(chrono::NaiveTime::MIN).and_utc());
+    }
+    history_cutoff(since).map_err(|_| {
+        anyhow::anyhow!(
+            "invalid --since '{since}': expected a duration like 2h or an ISO date like 2024-05-01"
+        )
+    })
+}
+
+fn format_history_timeline(entries: &[db::HistoryEntry], duration: &str) -> String {
+    if entries.is_empty() {
+        return format!("No proxy history in the last {duration}");
+    }
+
+    let mut lines = vec![format!(
+        "{:<35} | {:<6} | {:<40} | {}",
+        "TIMESTAMP", "ACTION", "PROXY_URL", "COMMENT"
+    )
+    .bold()
+    .to_string()];
+    for entry in entries {
+        lines.push(format!(
+            "{:<35} | {:<6} | {:<40} | {}",
+            entry.activated_at,
+            entry.action,
+            entry
+                .proxy_url
+                .as_deref()
+                .map_or_else(|| "-".to_string(), proxy::mask_proxy_credentials),
+            entry.comment.as_deref().unwrap_or("")
+        ));
+    }
+    lines.join("\n")
+}
+
+/// A config value as plain text for scripts: strings unquoted, lists
+/// comma-separated, and `None` when unset.
+fn raw_config_value(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::Null => None,
+        serde_json::Value::String(text) => Some(text.clone()),
+        serde_json::Value::Array(items) => Some(
+            items
+                .iter()
+                .filter_map(raw_config_value)
+                .collect::<Vec<_>>()
+                .join(","),
+        ),
+        other => Some(other.to_string()),
+    }
+}
+
+fn format_audit(suggestions: &[config::AuditSuggestion]) -> String {
+    if suggestions.is_empty() {
+        return "No suggestions; config follows the recommendations".to_string();
+    }
+
+    let mut lines = Vec::new();
+    for suggestion in suggestions {
+        let label = match suggestion.severity {
+            config::Severity::Info => "INFO".cyan(),
+            config::Severity::Warning => "WARN".yellow(),
+            config::Severity::Error => "ERR".red(),
+        };
+        lines.push(format!(
+            "{label}: {} - {}",
+            suggestion.field.bold(),
+            suggestion.message
+        ));
+        lines.push(format!("  suggestion: {}", suggestion.suggestion));
+    }
+    lines.join("\n")
+}
+
+fn format_ssh_host_blocks(blocks: &[config::SshHostBlock]) -> String {
+    if blocks.is_empty() {
+        return "No Host blocks found in SSH config".to_string();
+    }
+
+    let rows: Vec<(String, String, &str)> = blocks
+        .iter()
+        .map(|block| {
+            let command = block
+                .proxy_command
+                .as_deref()
+                .map(|command| {
+                    command
+                        .split_whitespace()
+                        .map(proxy::mask_proxy_credentials)
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                })
+                .unwrap_or_else(|| "-".to_string());
+            let managed = if block.managed_by_proxyctl {
+                "yes"
+            } else {
+                "no"
+            };
+            (block.patterns.join(" "), command, managed)
+        })
+        .collect();
+
+    let host_width = rows
+        .iter()
+        .map(|(hosts, _, _)| hosts.len())
+        .max()
+        .unwrap_or(0)
+        .max("HOST".len());
+    let command_width = rows
+        .iter()
+        .map(|(_, command, _)| command.len())
+        .max()
+        .unwrap_or(0)
+        .max("PROXYCOMMAND".len());
+
+    let mut lines = vec![format!(
+        "{:<host_width$}  {:<command_width$}  {}",
+        "HOST", "PROXYCOMMAND", "MANAGED"
+    )
+    .bold()
+    .to_string()];
+    for (hosts, command, managed) in rows {
+        lines.push(format!(
+            "{hosts:<host_width$}  {command:<command_width$}  {managed}"
+        ));
+    }
+    lines.join("\n")
+}
+
+fn prompt_credentials(profile: &str) -> Result<keychain::ProxyCredentials> {
+    use std::io::Write;
+
+    log!(
+        Verbosity::Normal,
+        "Credentials for keychain profile '{profile}' are missing or expired"
+    );
+    print!("Proxy username: ");
+    std::io::stdout().flush()?;
+    let mut username = String::new();
+    std::io::stdin().read_line(&mut username)?;
+    let username = username.trim().to_string();
+    if username.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No username given; keychain profile '{profile}' left unchanged"
+        ));
+    }
+
+    let password = rpassword::prompt_password(format!("Proxy password for {username}: "))?;
+
+    // Ask again for a lifetime so that rotated credentials keep expiring.
+    print!("Replace these credentials after (e.g. 90d; blank for never): ");
+    std::io::stdout().flush()?;
+    let mut lifetime = String::new();
+    std::io::stdin().read_line(&mut lifetime)?;
+    let expires_at = match lifetime.trim() {
+        "" => None,
+        lifetime => {
+            let lifetime = chrono::Duration::from_std(db::parse_duration(lifetime)?)?;
+            Some((chrono::Utc::now() + lifetime).to_rfc3339())
+        }
+    };
+
+    Ok(keychain::ProxyCredentials {
+        username,
+        password,
+        expires_at,
+    })
+}
+
+fn fix_ssh_permissions(yes: bool) -> Result<()> {
+    let ssh_config = config::get_ssh_config_path()?;
+    let ssh_dir = ssh_config
+        .parent()
+        .ok_or_else(|| anyhow::anyhow!("SSH config path has no parent directory"))?;
+
+    let plan = doctor::plan_ssh_permission_fixes(ssh_dir, &ssh_config)?;
+    if plan.actions.is_empty() {
+        log!(
+            Verbosity::Normal,
+            "SSH permissions are already private; nothing to fix"
+        );
+        return Ok(());
+    }
+
+    for action in &plan.actions {
+        println!("Would run: {action}");
+    }
+    if !yes && !confirm("Apply these permission changes? [y/N]")? {
+        return Err(anyhow::anyhow!("Aborted; permissions left unchanged"));
+    }
+
+    let report = doctor::fix_ssh_permissions(ssh_dir, &ssh_config)?;
+    for action in &report.actions {
+        log!(Verbosity::Normal, "Ran: {action}");
+    }
+
+    let check = doctor::check_ssh_permissions(ssh_dir, &ssh_config);
+    println!("{}", check.render());
+    if check.status == doctor::CheckStatus::Ok {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("SSH permissions are still not private"))
+    }
+}
+
+fn confirm(prompt: &str) -> Result<bool> {
+    use std::io::Write;
+
+    print!("{} ", prompt.yellow());
+    std::io::stdout().flush()?;
+
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(
+        answer.trim().to_ascii_lowercase().as_str(),
+        "y" | "yes"
+    ))
+}
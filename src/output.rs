@@ -0,0 +1,198 @@
+use colored::Colorize;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::fmt::Display;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How much informational output commands print, from the global
+/// `--quiet` / `--verbose` flags. Errors and warnings go to stderr regardless.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only command results, such as `status` or JSON bodies.
+    Quiet,
+    /// Results plus progress messages like "Proxy enabled".
+    #[default]
+    Normal,
+    /// Also the files, variables and URLs each step touches.
+    Verbose,
+}
+
+impl Verbosity {
+    pub fn from_flags(quiet: bool, verbose: bool) -> Self {
+        if quiet {
+            Verbosity::Quiet
+        } else if verbose {
+            Verbosity::Verbose
+        } else {
+            Verbosity::Normal
+        }
+    }
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+/// Apply the global `--quiet` / `--verbose` flags before dispatching a command.
+pub fn init(quiet: bool, verbose: bool) {
+    set_verbosity(Verbosity::from_flags(quiet, verbose));
+}
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Normal,
+        _ => Verbosity::Verbose,
+    }
+}
+
+pub fn is_quiet() -> bool {
+    verbosity() == Verbosity::Quiet
+}
+
+pub fn is_verbose() -> bool {
+    verbosity() == Verbosity::Verbose
+}
+
+/// Print `message` when the verbosity is at least `level`.
+pub fn log(level: Verbosity, message: impl Display) {
+    if level > Verbosity::Quiet && verbosity() >= level {
+        match active_spinner() {
+            Some(bar) => bar.suspend(|| println!("{message}")),
+            None => println!("{message}"),
+        }
+    }
+}
+
+/// Print an informational message unless `--quiet` is set.
+pub fn info(message: impl Display) {
+    log(Verbosity::Normal, message);
+}
+
+/// Print extra detail only when `--verbose` is set.
+pub fn detail(message: impl Display) {
+    log(Verbosity::Verbose, message);
+}
+
+/// Print a warning to stderr, whatever the verbosity.
+pub fn warn(message: impl Display) {
+    let line = format!("{}: {message}", "Warning".yellow().bold());
+    match active_spinner() {
+        Some(bar) => bar.suspend(|| eprintln!("{line}")),
+        None => eprintln!("{line}"),
+    }
+}
+
+/// Whether styled output may contain ANSI color codes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Color only when stdout is a terminal and neither `NO_COLOR` nor
+    /// `CLICOLOR=0` is set (`CLICOLOR_FORCE` overrides both).
+    #[default]
+    Auto,
+    /// Never color, e.g. for `--plain`.
+    Never,
+}
+
+impl ColorMode {
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorMode::Auto => colored::control::SHOULD_COLORIZE.should_colorize(),
+            ColorMode::Never => false,
+        }
+    }
+
+    /// `styled` when colors are enabled, otherwise its plain text.
+    pub fn paint(self, styled: colored::ColoredString) -> String {
+        if self.enabled() {
+            styled.to_string()
+        } else {
+            styled.input.clone()
+        }
+    }
+}
+
+static PROGRESS: AtomicBool = AtomicBool::new(true);
+static SPINNER: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// Apply the global `--no-progress` flag.
+pub fn set_progress(enabled: bool) {
+    PROGRESS.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether spinners are drawn: not with `--no-progress` or `--quiet`, and
+/// only when stderr is a terminal.
+pub fn progress_enabled() -> bool {
+    PROGRESS.load(Ordering::Relaxed) && !is_quiet() && std::io::stderr().is_terminal()
+}
+
+/// A spinner on stderr showing `message` until the returned guard is
+/// dropped. While one is already showing, its message is swapped for
+/// `message` and restored when the guard drops, so nested steps share a
+/// single line.
+pub fn spinner(message: impl Into<String>) -> Spinner {
+    let message = message.into();
+    if !progress_enabled() {
+        return Spinner::default();
+    }
+    let mut active = SPINNER.lock().unwrap_or_else(|err| err.into_inner());
+    if let Some(bar) = active.as_ref() {
+        let previous = bar.message();
+        bar.set_message(message);
+        return Spinner {
+            bar: Some(bar.clone()),
+            owned: false,
+            previous: Some(previous),
+        };
+    }
+    let bar = ProgressBar::new_spinner().with_message(message);
+    if let Ok(style) = ProgressStyle::with_template("{spinner} {msg}") {
+        bar.set_style(style);
+    }
+    bar.enable_steady_tick(Duration::from_millis(100));
+    *active = Some(bar.clone());
+    Spinner {
+        bar: Some(bar),
+        owned: true,
+        previous: None,
+    }
+}
+
+/// Guard returned by [`spinner`]; clears the spinner when dropped.
+#[derive(Default)]
+pub struct Spinner {
+    bar: Option<ProgressBar>,
+    owned: bool,
+    previous: Option<String>,
+}
+
+impl Spinner {
+    pub fn is_active(&self) -> bool {
+        self.bar.is_some()
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        let Some(bar) = self.bar.take() else {
+            return;
+        };
+        if self.owned {
+            bar.finish_and_clear();
+            SPINNER.lock().unwrap_or_else(|err| err.into_inner()).take();
+        } else if let Some(previous) = self.previous.take() {
+            bar.set_message(previous);
+        }
+    }
+}
+
+fn active_spinner() -> Option<ProgressBar> {
+    SPINNER
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .clone()
+}
@@ -1,243 +1,1311 @@
-use crate::config;
+use crate::config::{self, validate_proxy_url};
 use crate::db;
 use crate::defaults;
 use crate::detect;
+use crate::dry_run::{DiffWriter, DryRun};
+use crate::gnome;
+use crate::output;
+use crate::systemctl;
 use anyhow::{anyhow, Result};
 use colored::Colorize;
-use std::collections::HashSet;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
 
-pub async fn set_proxy(proxy_url: &str) -> Result<()> {
-    let proxy_settings = config::get_proxy_settings()?;
+/// Per-invocation adjustments applied on top of the loaded configuration.
+#[derive(Debug, Clone, Default)]
+pub struct SetProxyOptions {
+    /// Additional `no_proxy` entries merged after the configured list.
+    pub extra_no_proxy: Vec<String>,
+    /// Apply the proxy even when the URL fails validation.
+    pub force: bool,
+    /// Note stored with the history entry for this change.
+    pub comment: Option<String>,
+    /// Only update this process and the database; leave shell profiles alone.
+    pub no_profile: bool,
+    /// Overrides `default_proxy_scope` from the config when set.
+    pub scope: Option<config::ProxyScope>,
+    /// Human-readable label for this proxy session.
+    pub tag: Option<String>,
+    /// Add the directly reachable local subnets to `no_proxy`; defaults to
+    /// `auto_local_cidr_no_proxy` from the config.
+    pub cidr_exclusions: bool,
+    /// Overrides `proxy_settings` from the config when set.
+    pub proxy_settings: Option<config::ProxySettings>,
+    /// Print the changes instead of applying them or saving the state.
+    pub dry_run: DryRun,
+}
 
-    let no_proxy_value = if proxy_settings.enable_no_proxy {
-        let value = if let Some(custom_no_proxy) = config::get_custom_no_proxy()? {
-            custom_no_proxy.join(",")
-        } else {
-            defaults::default_no_proxy()
+/// One step of enabling a proxy, planned by [`plan_proxy_setup`] and then
+/// either applied or printed with [`render_operations_script`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProxyOperation {
+    /// Set an environment variable to a value.
+    SetEnvVar(String, String),
+    /// Replace the managed block in a profile with these lines (removing it when empty).
+    WriteProfile(PathBuf, String),
+    /// Put a ProxyCommand line (with indentation) under a `Host` line of an SSH config.
+    AddSshProxyCommand(PathBuf, String, String),
+}
+
+pub async fn set_proxy(proxy_url: &str, options: &SetProxyOptions) -> Result<()> {
+    let operations = plan_proxy_setup(proxy_url, options)?;
+    if options.dry_run.0 {
+        preview_proxy_operations(&operations)?.print();
+        return Ok(());
+    }
+    if operations
+        .iter()
+        .any(|operation| matches!(operation, ProxyOperation::WriteProfile(..)))
+        && options.scope.unwrap_or_else(config::get_proxy_scope) == config::ProxyScope::User
+    {
+        warn_about_skipped_profiles()?;
+    }
+    apply_proxy_operations(&operations)?;
+
+    let mut state = db::EnvState {
+        session_tag: options.tag.clone(),
+        active_profile: config::get_active_profile(),
+        activated_at: Some(chrono::Utc::now().timestamp()),
+        ..db::EnvState::default()
+    };
+    for operation in &operations {
+        let ProxyOperation::SetEnvVar(key, value) = operation else {
+            continue;
+        };
+        let slot = match key.to_ascii_lowercase().as_str() {
+            "http_proxy" => &mut state.http_proxy,
+            "https_proxy" => &mut state.https_proxy,
+            "ftp_proxy" => &mut state.ftp_proxy,
+            "all_proxy" => &mut state.all_proxy,
+            "proxy_rsync" => &mut state.proxy_rsync,
+            "no_proxy" => &mut state.no_proxy,
+            _ => continue,
         };
-        Some(value)
+        *slot = Some(value.clone());
+    }
+    save_env_state(&state).await?;
+
+    let masked_url = mask_proxy_credentials(proxy_url);
+    db::record_tagged_history(
+        &db::get_db_path(),
+        "on",
+        Some(&masked_url),
+        options.comment.as_deref(),
+        options.tag.as_deref(),
+    )
+    .await?;
+
+    // After the state is saved, so a failure here cannot leave the profiles
+    // written but the state and history unrecorded.
+    if let Some(network_service) = config::get_managed_network_service() {
+        warn_on_error(set_system_proxy(proxy_url, &network_service));
+    }
+    if config::get_manage_gnome_proxy() {
+        warn_on_error(set_gnome_proxy(proxy_url));
+    }
+
+    Ok(())
+}
+
+/// Work out the environment variables and profile writes `set_proxy` would
+/// make for `proxy_url`, without changing anything.
+pub fn plan_proxy_setup(proxy_url: &str, options: &SetProxyOptions) -> Result<Vec<ProxyOperation>> {
+    if !options.force {
+        // WPAD and env values are often bare `host:port`, so assume http:// for those.
+        if proxy_url.contains("://") {
+            validate_proxy_url(proxy_url)?;
+        } else {
+            validate_proxy_url(&format!("http://{}", proxy_url.trim()))?;
+        }
+    }
+
+    let proxy_settings = match &options.proxy_settings {
+        Some(proxy_settings) => proxy_settings.clone(),
+        None => config::get_proxy_settings()?,
+    };
+    proxy_settings.validate()?;
+    let app_config = config::load_config()?;
+
+    let no_proxy_value = if proxy_settings.enable_no_proxy {
+        let value = config::get_no_proxy()?;
+        let mut extra = options.extra_no_proxy.clone();
+        if options.cidr_exclusions || app_config.auto_local_cidr_no_proxy.unwrap_or(false) {
+            extra.extend(defaults::local_subnet_entries()?);
+        }
+        Some(merge_no_proxy(&value, &extra))
     } else {
         None
     };
 
-    if proxy_settings.enable_http_proxy {
-        set_env_vars(&HTTP_PROXY_KEYS, proxy_url);
-    }
-    if proxy_settings.enable_https_proxy {
-        set_env_vars(&HTTPS_PROXY_KEYS, proxy_url);
+    let exports = gather_proxy_exports(&proxy_settings, proxy_url, no_proxy_value.as_deref());
+    let mut operations: Vec<ProxyOperation> = exports
+        .iter()
+        .map(|(key, value)| ProxyOperation::SetEnvVar(key.to_string(), value.to_string()))
+        .collect();
+
+    let write_profiles = app_config.write_shell_profiles.unwrap_or(true);
+    if write_profiles && !options.no_profile {
+        let profile_writes = match options.scope.unwrap_or_else(config::get_proxy_scope) {
+            config::ProxyScope::User => plan_profile_writes(resolve_shell_profiles()?, &exports),
+            config::ProxyScope::System => plan_system_writes_in(Path::new("/"), &exports)?,
+        };
+        operations.extend(profile_writes);
     }
-    if proxy_settings.enable_ftp_proxy {
-        set_env_vars(&FTP_PROXY_KEYS, proxy_url);
+
+    Ok(operations)
+}
+
+/// The SSH ProxyCommand changes `config::add_ssh_hosts` would make.
+pub fn plan_ssh_setup(
+    hosts_files: &[PathBuf],
+    proxy: &ResolvedProxy,
+) -> Result<Vec<ProxyOperation>> {
+    Ok(
+        config::plan_ssh_proxy_commands(hosts_files, proxy, &config::SshAddOptions::default())?
+            .into_iter()
+            .map(|(path, host, line)| ProxyOperation::AddSshProxyCommand(path, host, line))
+            .collect(),
+    )
+}
+
+fn apply_proxy_operations(operations: &[ProxyOperation]) -> Result<()> {
+    for operation in operations {
+        match operation {
+            ProxyOperation::SetEnvVar(key, value) => set_env_vars(&[key.as_str()], value),
+            ProxyOperation::WriteProfile(profile, content) if content.is_empty() => {
+                remove_managed_block(profile)?
+            }
+            ProxyOperation::WriteProfile(profile, content) => {
+                let lines: Vec<String> = content.lines().map(str::to_string).collect();
+                write_managed_block(profile, &lines)?
+            }
+            // SSH changes are written by `config::add_ssh_hosts`, which plans the same edits.
+            ProxyOperation::AddSshProxyCommand(..) => {}
+        }
     }
-    if proxy_settings.enable_all_proxy {
-        set_env_vars(&ALL_PROXY_KEYS, proxy_url);
+    Ok(())
+}
+
+// The environment target in dry-run previews.
+const ENVIRONMENT_TARGET: &str = "environment";
+
+// The line changes `apply_proxy_operations` would make, with credentials masked.
+fn preview_proxy_operations(operations: &[ProxyOperation]) -> Result<DiffWriter> {
+    let mask = credential_masker(operation_values(operations));
+    let mut diff = DiffWriter::new();
+    for operation in operations {
+        match operation {
+            ProxyOperation::SetEnvVar(key, value) => {
+                let current = env::var(key).ok();
+                if current.as_deref() == Some(value.as_str()) {
+                    continue;
+                }
+                if let Some(current) = current {
+                    let current = mask_proxy_credentials(&current);
+                    diff.remove(ENVIRONMENT_TARGET, format!("{key}={current}"));
+                }
+                diff.add(ENVIRONMENT_TARGET, format!("{key}={}", mask(value)));
+            }
+            ProxyOperation::WriteProfile(profile, content) => {
+                let existing = read_profile(profile)?;
+                let updated = if content.is_empty() {
+                    strip_managed_block(&existing).0
+                } else {
+                    let lines: Vec<String> = content.lines().map(str::to_string).collect();
+                    render_managed_block(profile, &existing, &lines)
+                };
+                diff.diff(
+                    &profile.display().to_string(),
+                    &mask(&existing),
+                    &mask(&updated),
+                );
+            }
+            // `config::add_ssh_hosts` previews its own changes.
+            ProxyOperation::AddSshProxyCommand(..) => {}
+        }
     }
-    if proxy_settings.enable_proxy_rsync {
-        set_env_vars(&PROXY_RSYNC_KEYS, proxy_url);
+    Ok(diff)
+}
+
+fn operation_values(operations: &[ProxyOperation]) -> Vec<String> {
+    operations
+        .iter()
+        .filter_map(|operation| match operation {
+            ProxyOperation::SetEnvVar(_, value) => Some(value.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Masks every one of `values` that carries credentials wherever it appears
+/// in a text, for config files that embed proxy URLs in their own syntax.
+pub fn credential_masker(values: Vec<String>) -> impl Fn(&str) -> String {
+    let secrets: Vec<(String, String)> = values
+        .into_iter()
+        .filter_map(|value| {
+            let masked = mask_proxy_credentials(&value);
+            (masked != value).then_some((value, masked))
+        })
+        .collect();
+    move |text: &str| {
+        secrets
+            .iter()
+            .fold(text.to_string(), |text, (secret, masked)| {
+                text.replace(secret.as_str(), masked)
+            })
     }
-    if let Some(ref no_proxy_str) = no_proxy_value {
-        set_env_vars(&NO_PROXY_KEYS, no_proxy_str);
+}
+
+/// Render `operations` as a POSIX shell script that makes the same changes.
+/// Proxy credentials are masked.
+pub fn render_operations_script(operations: &[ProxyOperation]) -> String {
+    let mask = credential_masker(operation_values(operations));
+
+    let mut script = vec![
+        "#!/bin/sh".to_string(),
+        "# Commands equivalent to `proxyctl-rs on`; nothing has been changed.".to_string(),
+        "set -e".to_string(),
+    ];
+
+    for operation in operations {
+        match operation {
+            ProxyOperation::SetEnvVar(key, value) => {
+                script.push(format!("export {key}={}", shell_quote(&mask(value))));
+            }
+            ProxyOperation::WriteProfile(profile, content) => {
+                let (start, end) = ShellSyntax::for_profile(profile).managed_markers();
+                let path = shell_quote(&profile.display().to_string());
+                let tmp = shell_quote(&format!("{}.proxyctl-rs.tmp", profile.display()));
+                script.push(String::new());
+                script.push(format!(
+                    "# Replace the proxyctl-rs block in {}",
+                    profile.display()
+                ));
+                if let Some(parent) = profile.parent() {
+                    script.push(format!(
+                        "mkdir -p {}",
+                        shell_quote(&parent.display().to_string())
+                    ));
+                }
+                script.push(format!("touch {path}"));
+                script.push(format!("sed '/^{start}$/,/^{end}$/d' {path} > {tmp}"));
+                if !content.is_empty() {
+                    // Separate the block from existing content by one blank line.
+                    script.push(format!("if [ -s {tmp} ]; then"));
+                    script.push(format!("  [ -z \"$(tail -c 1 {tmp})\" ] || echo >> {tmp}"));
+                    script.push(format!("  [ -z \"$(tail -n 1 {tmp})\" ] || echo >> {tmp}"));
+                    script.push("fi".to_string());
+                    script.push(format!("cat >> {tmp} <<'PROXYCTL_RS_EOF'"));
+                    script.push(start.to_string());
+                    script.extend(content.lines().map(&mask));
+                    script.push(end.to_string());
+                    script.push("PROXYCTL_RS_EOF".to_string());
+                }
+                script.push(format!("mv {tmp} {path}"));
+            }
+            ProxyOperation::AddSshProxyCommand(ssh_config, host, line) => {
+                let path = shell_quote(&ssh_config.display().to_string());
+                let tmp = shell_quote(&format!("{}.proxyctl-rs.tmp", ssh_config.display()));
+                script.push(String::new());
+                script.push(format!(
+                    "# Set the ProxyCommand for `{}` in {}",
+                    host.trim(),
+                    ssh_config.display()
+                ));
+                // The first pass notes whether the block already has a ProxyCommand
+                // (replaced in place); otherwise it goes right after the Host line.
+                script.push(format!(
+                    "awk -v host={} -v cmd={} '",
+                    shell_quote(host),
+                    shell_quote(&mask(line))
+                ));
+                script.extend(
+                    [
+                        "  /^[ \t]*([Hh][Oo][Ss][Tt]|[Mm][Aa][Tt][Cc][Hh])[ \t]/ { in_block = ($0 == host) }",
+                        "  NR == FNR { if (in_block && tolower($1) ~ /^proxy(command|jump)$/) has = 1; next }",
+                        "  FNR == 1 { in_block = ($0 == host) }",
+                        "  in_block && tolower($1) ~ /^proxy(command|jump)$/ { if (!done) print cmd; done = 1; next }",
+                        "  { print }",
+                        "  $0 == host && !has { print cmd }",
+                    ]
+                    .map(str::to_string),
+                );
+                script.push(format!("' {path} {path} > {tmp}"));
+                script.push(format!("mv {tmp} {path}"));
+            }
+        }
     }
 
-    persist_proxy_settings(&proxy_settings, proxy_url, no_proxy_value.as_deref())?;
+    script.join("\n") + "\n"
+}
 
-    let mut state = db::EnvState::default();
-    if proxy_settings.enable_http_proxy {
-        state.http_proxy = Some(proxy_url.to_string());
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+pub async fn disable_proxy(dry_run: DryRun) -> Result<()> {
+    if dry_run.0 {
+        preview_disable()?.print();
+        return Ok(());
     }
-    if proxy_settings.enable_https_proxy {
-        state.https_proxy = Some(proxy_url.to_string());
+    disable_proxy_with(clear_env_vars).await
+}
+
+// The line changes `disable_proxy` would make, with credentials masked.
+fn preview_disable() -> Result<DiffWriter> {
+    let mut values = Vec::new();
+    let mut diff = DiffWriter::new();
+    for keys in MANAGED_KEY_GROUPS {
+        for key in keys {
+            if let Ok(value) = env::var(key) {
+                let masked = mask_proxy_credentials(&value);
+                diff.remove(ENVIRONMENT_TARGET, format!("{key}={masked}"));
+                values.push(value);
+            }
+        }
     }
-    if proxy_settings.enable_ftp_proxy {
-        state.ftp_proxy = Some(proxy_url.to_string());
+
+    let mask = credential_masker(values);
+    let mut profiles = resolve_shell_profiles()?;
+    profiles.extend(system_profiles_in(Path::new("/")));
+    for profile in profiles {
+        let existing = fs::read_to_string(&profile).unwrap_or_default();
+        let (updated, changed) = strip_managed_block(&existing);
+        if changed {
+            diff.diff(
+                &profile.display().to_string(),
+                &mask(&existing),
+                &mask(&updated),
+            );
+        }
     }
-    if proxy_settings.enable_all_proxy {
-        state.all_proxy = Some(proxy_url.to_string());
+    Ok(diff)
+}
+
+async fn disable_proxy_with(clear: fn(&[&str])) -> Result<()> {
+    for keys in MANAGED_KEY_GROUPS {
+        clear(keys);
     }
-    if proxy_settings.enable_proxy_rsync {
-        state.proxy_rsync = Some(proxy_url.to_string());
+    // Checked before any profile or the database is touched, so a failure
+    // leaves everything as it was.
+    if !managed_env_cleared() {
+        return Err(anyhow!(
+            "proxy environment variables are still set after clearing; profiles and database state left unchanged"
+        ));
     }
-    if let Some(no_proxy_str) = no_proxy_value {
-        state.no_proxy = Some(no_proxy_str);
+
+    remove_persisted_settings()?;
+    save_env_state(&db::EnvState::default()).await?;
+    db::record_history(&db::get_db_path(), "off", None, None).await?;
+
+    if let Some(network_service) = config::get_managed_network_service() {
+        warn_on_error(disable_system_proxy(&network_service));
+    }
+    if config::get_manage_gnome_proxy() {
+        warn_on_error(disable_gnome_proxy());
     }
-    save_env_state(&state).await?;
 
     Ok(())
 }
 
-pub async fn disable_proxy() -> Result<()> {
-    clear_env_vars(&HTTP_PROXY_KEYS);
-    clear_env_vars(&HTTPS_PROXY_KEYS);
-    clear_env_vars(&FTP_PROXY_KEYS);
-    clear_env_vars(&ALL_PROXY_KEYS);
-    clear_env_vars(&PROXY_RSYNC_KEYS);
-    clear_env_vars(&NO_PROXY_KEYS);
+// `manage_system_proxy` only has an effect on macOS; elsewhere it warns.
+fn set_system_proxy(proxy_url: &str, network_service: &str) -> Result<()> {
+    if !cfg!(target_os = "macos") {
+        output::warn("manage_system_proxy only applies on macOS; skipping networksetup");
+        return Ok(());
+    }
+    output::detail(format!(
+        "Setting the system proxy of {network_service} with networksetup"
+    ));
+    systemctl::set_macos_proxy(proxy_url, network_service)
+}
 
-    remove_persisted_settings()?;
-    save_env_state(&db::EnvState::default()).await?;
+fn disable_system_proxy(network_service: &str) -> Result<()> {
+    if !cfg!(target_os = "macos") {
+        return Ok(());
+    }
+    output::detail(format!(
+        "Turning off the system proxy of {network_service} with networksetup"
+    ));
+    systemctl::disable_macos_proxy(network_service)
+}
 
+fn disable_gnome_proxy() -> Result<()> {
+    if !gnome::disable_proxy()? {
+        output::detail("gsettings not found; leaving the GNOME proxy alone");
+    }
     Ok(())
 }
 
-pub async fn get_status() -> Result<String> {
+// The system and GNOME proxies are extras on top of the shell profiles, so a
+// failing networksetup or gsettings is reported without failing the command.
+fn warn_on_error(result: Result<()>) {
+    if let Err(err) = result {
+        output::warn(format!("{err:#}"));
+    }
+}
+
+fn set_gnome_proxy(proxy_url: &str) -> Result<()> {
+    let (host, port) = proxy_host_and_port(proxy_url).ok_or_else(|| {
+        anyhow!(
+            "cannot find a host and port in {}",
+            mask_proxy_credentials(proxy_url)
+        )
+    })?;
+    if gnome::set_proxy(&host, port)? {
+        output::detail(format!(
+            "Set the GNOME proxy to {host}:{port} with gsettings"
+        ));
+    } else {
+        output::detail("gsettings not found; leaving the GNOME proxy alone");
+    }
+    Ok(())
+}
+
+/// `proxy_settings` with every variable group that already has a non-empty
+/// value in the environment switched off, so `on --keep-existing` leaves
+/// proxies set by login scripts alone.
+pub fn keep_existing_proxy_settings(
+    mut proxy_settings: config::ProxySettings,
+) -> config::ProxySettings {
+    let groups = [
+        (&HTTP_PROXY_KEYS, &mut proxy_settings.enable_http_proxy),
+        (&HTTPS_PROXY_KEYS, &mut proxy_settings.enable_https_proxy),
+        (&FTP_PROXY_KEYS, &mut proxy_settings.enable_ftp_proxy),
+        (&ALL_PROXY_KEYS, &mut proxy_settings.enable_all_proxy),
+        (&PROXY_RSYNC_KEYS, &mut proxy_settings.enable_proxy_rsync),
+        (&NO_PROXY_KEYS, &mut proxy_settings.enable_no_proxy),
+    ];
+    for (keys, enabled) in groups {
+        if !*enabled {
+            continue;
+        }
+        if let Some(value) = get_env_value(keys) {
+            output::info(format!(
+                "Skipping {} — already set to {}",
+                keys[1],
+                mask_proxy_credentials(&value)
+            ));
+            *enabled = false;
+        }
+    }
+    proxy_settings
+}
+
+/// Every non-empty environment variable whose name contains "proxy" in any
+/// case, sorted by name; catches variables set by other tools or login scripts.
+/// Names mentioning proxyctl itself are not proxy settings and are skipped.
+pub fn find_all_proxy_env_vars() -> Vec<(String, String)> {
+    let mut vars: Vec<(String, String)> = env::vars()
+        .filter(|(key, value)| {
+            let key = key.to_ascii_lowercase();
+            key.contains("proxy") && !key.contains("proxyctl") && !value.is_empty()
+        })
+        .collect();
+    vars.sort();
+    vars
+}
+
+/// Warn about proxy variables still set after `disable_proxy`, unsetting
+/// them when `force` is true. Returns the variables found.
+pub fn reset_proxy_env(force: bool) -> Vec<(String, String)> {
+    let found = find_all_proxy_env_vars();
+    for (key, value) in &found {
+        output::warn(format!(
+            "{key}={} is set outside proxyctl-rs",
+            mask_proxy_credentials(value)
+        ));
+        if force {
+            env::remove_var(key);
+        }
+    }
+    found
+}
+
+/// Returns true when none of `keys` hold a non-empty value in the process env.
+pub fn verify_env_cleared(keys: &[&str]) -> bool {
+    get_env_value(keys).is_none()
+}
+
+#[cfg(unix)]
+fn managed_env_cleared() -> bool {
+    MANAGED_KEY_GROUPS
+        .iter()
+        .all(|keys| verify_env_cleared(keys))
+}
+
+#[cfg(not(unix))]
+fn managed_env_cleared() -> bool {
+    output::warn("skipping proxy variable clearing verification on this platform");
+    true
+}
+
+/// The proxy half of `status`, with credentials masked. Disabled or unset
+/// variables are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ProxyStatusReport {
+    pub http_proxy: Option<String>,
+    pub https_proxy: Option<String>,
+    pub ftp_proxy: Option<String>,
+    pub all_proxy: Option<String>,
+    pub proxy_rsync: Option<String>,
+    pub no_proxy: Option<String>,
+    pub session: Option<String>,
+    pub profile: Option<String>,
+    /// When the proxy was enabled, as a Unix timestamp.
+    pub activated_at: Option<i64>,
+}
+
+pub async fn get_status_report() -> Result<ProxyStatusReport> {
     let proxy_settings = config::get_proxy_settings()?;
     let state = load_env_state()
         .await
         .unwrap_or_else(|_| db::EnvState::default());
 
-    let mut status_lines = Vec::new();
+    let value = |enabled: bool, state_value: &Option<String>, keys: &[&str]| {
+        enabled
+            .then(|| status_value(state_value.as_deref(), keys))
+            .flatten()
+    };
 
-    if proxy_settings.enable_http_proxy {
-        status_lines.push(render_status_line(
-            "HTTP Proxy",
-            state.http_proxy.as_deref(),
+    Ok(ProxyStatusReport {
+        http_proxy: value(
+            proxy_settings.enable_http_proxy,
+            &state.http_proxy,
             &HTTP_PROXY_KEYS,
-        ));
-    }
-    if proxy_settings.enable_https_proxy {
-        status_lines.push(render_status_line(
-            "HTTPS Proxy",
-            state.https_proxy.as_deref(),
+        ),
+        https_proxy: value(
+            proxy_settings.enable_https_proxy,
+            &state.https_proxy,
             &HTTPS_PROXY_KEYS,
-        ));
-    }
-    if proxy_settings.enable_ftp_proxy {
-        status_lines.push(render_status_line(
-            "FTP Proxy",
-            state.ftp_proxy.as_deref(),
+        ),
+        ftp_proxy: value(
+            proxy_settings.enable_ftp_proxy,
+            &state.ftp_proxy,
             &FTP_PROXY_KEYS,
-        ));
-    }
-    if proxy_settings.enable_all_proxy {
-        status_lines.push(render_status_line(
-            "All Proxy",
-            state.all_proxy.as_deref(),
+        ),
+        all_proxy: value(
+            proxy_settings.enable_all_proxy,
+            &state.all_proxy,
             &ALL_PROXY_KEYS,
-        ));
-    }
-    if proxy_settings.enable_proxy_rsync {
-        status_lines.push(render_status_line(
-            "Proxy Rsync",
-            state.proxy_rsync.as_deref(),
+        ),
+        proxy_rsync: value(
+            proxy_settings.enable_proxy_rsync,
+            &state.proxy_rsync,
             &PROXY_RSYNC_KEYS,
-        ));
-    }
-    if proxy_settings.enable_no_proxy {
-        status_lines.push(render_status_line(
-            "No Proxy",
-            state.no_proxy.as_deref(),
+        ),
+        no_proxy: value(
+            proxy_settings.enable_no_proxy,
+            &state.no_proxy,
             &NO_PROXY_KEYS,
+        ),
+        session: state.session_tag,
+        profile: state.active_profile,
+        activated_at: state.activated_at,
+    })
+}
+
+pub async fn get_status() -> Result<String> {
+    let proxy_settings = config::get_proxy_settings()?;
+    let report = get_status_report().await?;
+
+    let rows = [
+        (
+            proxy_settings.enable_http_proxy,
+            "HTTP Proxy",
+            &report.http_proxy,
+        ),
+        (
+            proxy_settings.enable_https_proxy,
+            "HTTPS Proxy",
+            &report.https_proxy,
+        ),
+        (
+            proxy_settings.enable_ftp_proxy,
+            "FTP Proxy",
+            &report.ftp_proxy,
+        ),
+        (
+            proxy_settings.enable_all_proxy,
+            "All Proxy",
+            &report.all_proxy,
+        ),
+        (
+            proxy_settings.enable_proxy_rsync,
+            "Proxy Rsync",
+            &report.proxy_rsync,
+        ),
+        (proxy_settings.enable_no_proxy, "No Proxy", &report.no_proxy),
+    ];
+    let mut status_lines: Vec<String> = rows
+        .into_iter()
+        .filter(|(enabled, _, _)| *enabled)
+        .map(|(_, label, value)| render_status_line(label, value.as_deref()))
+        .collect();
+
+    if let Some(tag) = &report.session {
+        let session_line = format!("{}: \"{tag}\"", "Session".bold());
+        status_lines.insert(status_lines.len().min(1), session_line);
+    }
+    if let Some(profile) = &report.profile {
+        status_lines.push(format!("{}: {profile}", "Profile".bold()));
+    }
+    if let Some(activated) = report
+        .activated_at
+        .and_then(|timestamp| chrono::DateTime::from_timestamp(timestamp, 0))
+    {
+        status_lines.push(format!(
+            "{}: {}",
+            "Active since".bold(),
+            activated
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S %Z")
         ));
     }
 
     Ok(status_lines.join("\n"))
 }
 
-fn render_status_line(label: &str, state_value: Option<&str>, keys: &[&str]) -> String {
+/// Replace the session tag of the active proxy without changing its settings.
+pub async fn retag_session(tag: &str) -> Result<()> {
+    if !is_proxy_active().await? {
+        return Err(anyhow!("No proxy is enabled; nothing to tag"));
+    }
+    let mut state = load_env_state().await?;
+    state.session_tag = Some(tag.to_string());
+    save_env_state(&state).await
+}
+
+/// True when the database records at least one non-empty proxy variable.
+pub async fn is_proxy_active() -> Result<bool> {
+    let state = load_env_state().await?;
+    let active = [
+        &state.http_proxy,
+        &state.https_proxy,
+        &state.ftp_proxy,
+        &state.all_proxy,
+        &state.proxy_rsync,
+    ]
+    .into_iter()
+    .flatten()
+    .any(|value| !value.is_empty());
+    Ok(active)
+}
+
+/// A proxy variable whose value in this process differs from the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvInconsistency {
+    pub var_name: String,
+    pub db_value: Option<String>,
+    pub env_value: Option<String>,
+}
+
+/// Compare each managed proxy variable (both cases) against `state`. Empty
+/// values count as unset.
+pub fn check_env_vs_db(state: &db::EnvState) -> Vec<EnvInconsistency> {
+    let groups: [(&[&str], &Option<String>); 6] = [
+        (&HTTP_PROXY_KEYS, &state.http_proxy),
+        (&HTTPS_PROXY_KEYS, &state.https_proxy),
+        (&FTP_PROXY_KEYS, &state.ftp_proxy),
+        (&ALL_PROXY_KEYS, &state.all_proxy),
+        (&PROXY_RSYNC_KEYS, &state.proxy_rsync),
+        (&NO_PROXY_KEYS, &state.no_proxy),
+    ];
+
+    let mut inconsistencies = Vec::new();
+    for (keys, stored) in groups {
+        let db_value = stored.clone().filter(|value| !value.is_empty());
+        for key in keys {
+            let env_value = env::var(key).ok().filter(|value| !value.is_empty());
+            if env_value != db_value {
+                inconsistencies.push(EnvInconsistency {
+                    var_name: key.to_string(),
+                    db_value: db_value.clone(),
+                    env_value,
+                });
+            }
+        }
+    }
+    inconsistencies
+}
+
+pub async fn get_env_vs_db_status() -> Result<Vec<EnvInconsistency>> {
+    Ok(check_env_vs_db(&load_env_state().await?))
+}
+
+/// One `KEY=VALUE` line per managed variable, using the stored state. Unset
+/// variables render as `KEY=` unless `omit_unset` drops them.
+pub fn render_machine_status(state: &db::EnvState, omit_unset: bool) -> String {
+    let values = [
+        ("HTTP_PROXY", &state.http_proxy),
+        ("HTTPS_PROXY", &state.https_proxy),
+        ("FTP_PROXY", &state.ftp_proxy),
+        ("ALL_PROXY", &state.all_proxy),
+        ("PROXY_RSYNC", &state.proxy_rsync),
+        ("NO_PROXY", &state.no_proxy),
+    ];
+
+    values
+        .iter()
+        .filter_map(|(key, value)| {
+            let value = value.as_deref().unwrap_or_default().trim();
+            if value.is_empty() && omit_unset {
+                None
+            } else {
+                Some(format!("{key}={value}"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub async fn get_machine_status(omit_unset: bool) -> Result<String> {
+    Ok(render_machine_status(&load_env_state().await?, omit_unset))
+}
+
+/// True when every resolved shell profile contains the managed block.
+pub fn profiles_have_managed_block() -> Result<bool> {
+    let profiles = resolve_shell_profiles()?;
+    if profiles.is_empty() {
+        return Ok(false);
+    }
+
+    for profile in profiles {
+        if !profile_has_managed_block(&profile)? {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Whether the profile at `path` contains a complete managed block; a missing
+/// file has none.
+pub fn profile_has_managed_block(path: &Path) -> Result<bool> {
+    if !path.exists() {
+        return Ok(false);
+    }
+    let content = fs::read_to_string(path)?;
+    Ok(strip_managed_block(&content).1)
+}
+
+/// The masked value shown for one variable, preferring the stored state over
+/// the environment.
+fn status_value(state_value: Option<&str>, keys: &[&str]) -> Option<String> {
     let env_value = get_env_value(keys);
-    let value = state_value.or(env_value.as_deref());
+    state_value
+        .or(env_value.as_deref())
+        .filter(|value| !value.is_empty())
+        .map(mask_proxy_credentials)
+}
 
+fn render_status_line(label: &str, value: Option<&str>) -> String {
     let status = match value {
-        Some(v) if !v.is_empty() => v.green().bold().to_string(),
-        _ => "Not set".red().bold().to_string(),
+        Some(v) => mask_proxy_credentials(v).green().bold().to_string(),
+        None => "Not set".red().bold().to_string(),
     };
 
     format!("{}: {}", label.bold(), status)
 }
 
-#[derive(Debug, Clone)]
-pub struct ResolvedProxy {
-    pub proxy_url: String,
-    pub proxy_host: String,
+/// Proxy values read from a `.env` file without touching the process env.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EnvFileProxy {
+    pub proxy_url: Option<String>,
+    pub no_proxy: Vec<String>,
+}
+
+pub fn read_env_file(path: &Path) -> Result<EnvFileProxy> {
+    if !path.exists() {
+        return Err(anyhow!("env file {} does not exist", path.display()));
+    }
+
+    let mut values: HashMap<String, String> = HashMap::new();
+    for item in dotenvy::from_path_iter(path)
+        .map_err(|err| anyhow!("failed to read env file {}: {err}", path.display()))?
+    {
+        let (key, value) =
+            item.map_err(|err| anyhow!("failed to parse env file {}: {err}", path.display()))?;
+        values.insert(key, value);
+    }
+
+    let lookup = |keys: &[&str]| {
+        keys.iter()
+            .filter_map(|key| values.get(*key))
+            .map(|value| value.trim())
+            .find(|value| !value.is_empty())
+            .map(|value| value.to_string())
+    };
+
+    let proxy_url = lookup(&HTTP_PROXY_KEYS).or_else(|| lookup(&HTTPS_PROXY_KEYS));
+    let no_proxy = lookup(&NO_PROXY_KEYS)
+        .map(|value| split_no_proxy(&value))
+        .unwrap_or_default();
+
+    Ok(EnvFileProxy {
+        proxy_url,
+        no_proxy,
+    })
+}
+
+fn split_no_proxy(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|entry| entry.trim().to_string())
+        .filter(|entry| !entry.is_empty())
+        .collect()
+}
+
+fn merge_no_proxy(base: &str, extra: &[String]) -> String {
+    let mut entries = split_no_proxy(base);
+    for entry in extra {
+        if !entries.iter().any(|existing| existing == entry) {
+            entries.push(entry.clone());
+        }
+    }
+    entries.join(",")
+}
+
+/// Embed `username`/`password` into `proxy_url`, assuming `http://` when no
+/// scheme is present.
+pub fn with_credentials(proxy_url: &str, username: &str, password: &str) -> Result<String> {
+    let mut parsed = parse_with_scheme(proxy_url)
+        .ok_or_else(|| anyhow!("unable to parse proxy URL '{proxy_url}'"))?;
+    parsed
+        .set_username(username)
+        .map_err(|_| anyhow!("cannot set username on proxy URL '{proxy_url}'"))?;
+    parsed
+        .set_password(Some(password))
+        .map_err(|_| anyhow!("cannot set password on proxy URL '{proxy_url}'"))?;
+
+    let rendered = parsed.to_string();
+    Ok(strip_added_slash(proxy_url, rendered))
+}
+
+/// Replace any password embedded in `url` with `***` for display, including
+/// a bare `user:password@host:port`.
+pub fn mask_proxy_credentials(url: &str) -> String {
+    let Some(mut parsed) = parse_with_scheme(url) else {
+        return url.to_string();
+    };
+    if parsed.password().is_none() || parsed.set_password(Some("***")).is_err() {
+        return url.to_string();
+    }
+
+    let rendered = strip_added_slash(url, parsed.to_string());
+    if url.contains("://") {
+        rendered
+    } else {
+        rendered.trim_start_matches("http://").to_string()
+    }
+}
+
+// Whether `value` is a proxy URL with a username or password in it.
+fn has_credentials(value: &str) -> bool {
+    value.contains('@')
+        && parse_with_scheme(value)
+            .is_some_and(|url| !url.username().is_empty() || url.password().is_some())
+}
+
+/// `url` without its username and password, keeping a missing scheme missing.
+pub fn strip_proxy_credentials(url: &str) -> String {
+    let Some(mut parsed) = parse_with_scheme(url) else {
+        return url.to_string();
+    };
+    if parsed.username().is_empty() && parsed.password().is_none() {
+        return url.to_string();
+    }
+    if parsed.set_username("").is_err() || parsed.set_password(None).is_err() {
+        return url.to_string();
+    }
+
+    let rendered = strip_added_slash(url, parsed.to_string());
+    if url.contains("://") {
+        rendered
+    } else {
+        rendered.trim_start_matches("http://").to_string()
+    }
+}
+
+fn parse_with_scheme(value: &str) -> Option<reqwest::Url> {
+    let trimmed = value.trim();
+    reqwest::Url::parse(trimmed)
+        .ok()
+        .filter(|url| url.host_str().is_some())
+        .or_else(|| reqwest::Url::parse(&format!("http://{trimmed}")).ok())
+}
+
+// `Url` always renders a path, so drop the `/` it adds when the input had none.
+fn strip_added_slash(original: &str, rendered: String) -> String {
+    if !original.trim().ends_with('/') && rendered.ends_with('/') {
+        rendered.trim_end_matches('/').to_string()
+    } else {
+        rendered
+    }
+}
+
+pub use crate::config::{ProxyScheme, ResolvedProxy};
+
+/// Per-invocation overrides for `resolve_proxy`.
+#[derive(Debug, Clone, Default)]
+pub struct ResolveOptions {
+    /// Overrides `resolve_proxy_mode` from the config when set.
+    pub mode: Option<config::ResolveMode>,
+    /// Keep retrying WPAD for this long; defaults to the config's wpad_wait settings.
+    pub wpad_wait: Option<std::time::Duration>,
+    /// Environment variables checked after `extra_proxy_vars` from the config.
+    pub extra_vars: Vec<String>,
+    /// WPAD request timeouts; defaults to the config's.
+    pub wpad_client: Option<detect::WpadClientConfig>,
+    /// Accept an explicit proxy that fails [`config::validate_proxy_url`], as
+    /// `proxy on --force` does.
+    pub force: bool,
 }
 
-pub async fn resolve_proxy(proxy: Option<&str>) -> Result<ResolvedProxy> {
+/// Why [`resolve_proxy`] could not produce a proxy.
+#[derive(Debug, thiserror::Error)]
+pub enum ProxyResolutionError {
+    #[error("{reason}")]
+    ExplicitProxyInvalid { value: String, reason: String },
+    #[error("{}", describe_default_failure(value, reason, wpad_error.as_deref()))]
+    DefaultProxyInvalid {
+        value: String,
+        reason: String,
+        wpad_error: Option<String>,
+    },
+    #[error(transparent)]
+    WpadFailed(anyhow::Error),
+    #[error("{0}")]
+    NoCandidates(String),
+    #[error(transparent)]
+    Config(anyhow::Error),
+}
+
+fn describe_default_failure(value: &str, reason: &str, wpad_error: Option<&str>) -> String {
+    match wpad_error {
+        Some(wpad_error) => format!(
+            "WPAD detection also failed: {wpad_error}; then default proxy '{value}' could not be parsed: {reason}"
+        ),
+        None => format!("Failed to parse default proxy '{value}': {reason}"),
+    }
+}
+
+/// Pick the proxy to use, then add its login from the credentials file.
+pub async fn resolve_proxy(
+    proxy: Option<&str>,
+    options: &ResolveOptions,
+) -> Result<ResolvedProxy, ProxyResolutionError> {
+    let resolved = resolve_proxy_url(proxy, options).await?;
+    let proxy_url =
+        config::inject_credentials(&resolved.proxy_url).map_err(ProxyResolutionError::Config)?;
+    Ok(ResolvedProxy {
+        proxy_url,
+        ..resolved
+    })
+}
+
+async fn resolve_proxy_url(
+    proxy: Option<&str>,
+    options: &ResolveOptions,
+) -> Result<ResolvedProxy, ProxyResolutionError> {
     if let Some(value) = proxy {
-        return resolved_from_value(value);
+        return resolved_from_value(value, !options.force).map_err(|err| {
+            ProxyResolutionError::ExplicitProxyInvalid {
+                value: value.to_string(),
+                reason: err.to_string(),
+            }
+        });
+    }
+
+    let mode = options.mode.unwrap_or_else(config::get_resolve_mode);
+
+    if mode == config::ResolveMode::DefaultOnly {
+        // An explicit mode comes from --no-detect; otherwise it was configured.
+        let origin = match options.mode {
+            Some(_) => "--no-detect",
+            None => "resolve_proxy_mode = \"default_only\"",
+        };
+        return match config::get_default_proxy().map_err(ProxyResolutionError::Config)? {
+            Some(value) => resolve_default(&value, None),
+            None => Err(ProxyResolutionError::NoCandidates(format!(
+                "{origin} requires either --proxy <URL> or default_proxy in config"
+            ))),
+        };
     }
 
-    if let Some(env_proxy) = proxy_from_env() {
+    if let Some((_, env_proxy)) = proxy_from_env_with_source(&options.extra_vars) {
         return Ok(env_proxy);
     }
 
-    let default_proxy = config::get_default_proxy()?;
-    let mut last_error: Option<anyhow::Error> = None;
+    let default_proxy = match mode {
+        config::ResolveMode::WpadOnly => None,
+        _ => config::get_default_proxy().map_err(ProxyResolutionError::Config)?,
+    };
 
-    match detect::detect_proxy_candidates().await {
-        Ok(candidates) => {
+    let candidates = if mode == config::ResolveMode::NoWpad {
+        detect::cached_proxy_candidates().await
+    } else {
+        let wpad_client = options
+            .wpad_client
+            .unwrap_or_else(detect::WpadClientConfig::from_config);
+        let _spinner = output::spinner("Detecting proxy via WPAD...");
+        match options.wpad_wait.or_else(config::get_wpad_wait) {
+            Some(timeout) => detect::wait_for_proxy_candidates(timeout, &wpad_client).await,
+            None => detect::detect_proxy_candidates(&wpad_client, None).await,
+        }
+    };
+    let wpad_error = match candidates {
+        Ok((wpad_url, candidates)) => {
+            let mut last_error = None;
             for candidate in candidates {
-                match resolved_from_value(&candidate) {
-                    Ok(resolved) => return Ok(resolved),
+                match resolved_from_value(&candidate, true) {
+                    Ok(resolved) => {
+                        return Ok(ResolvedProxy {
+                            wpad_url: Some(wpad_url),
+                            ..resolved
+                        });
+                    }
                     Err(err) => last_error = Some(err),
                 }
             }
-
-            if let Some(value) = default_proxy {
-                return resolved_from_value(&value)
-                    .map_err(|err| anyhow!("Failed to parse default proxy '{value}': {err}"));
-            }
-
-            Err(last_error
-                .unwrap_or_else(|| anyhow!("No valid proxies discovered from WPAD response")))
-        }
-        Err(err) => {
-            if let Some(value) = default_proxy {
-                return resolved_from_value(&value).map_err(|parse_err| {
-                    anyhow!("Failed to parse default proxy '{value}': {parse_err}")
-                });
-            }
-            Err(err)
+            last_error
         }
+        Err(err) => Some(err),
+    };
+
+    match (default_proxy, wpad_error) {
+        (Some(value), wpad_error) => resolve_default(&value, wpad_error.map(|err| err.to_string())),
+        (None, Some(err)) => Err(ProxyResolutionError::WpadFailed(err)),
+        (None, None) => Err(ProxyResolutionError::NoCandidates(
+            "No valid proxies discovered from WPAD response".to_string(),
+        )),
     }
 }
 
+fn resolve_default(
+    value: &str,
+    wpad_error: Option<String>,
+) -> Result<ResolvedProxy, ProxyResolutionError> {
+    resolved_from_value(value, true).map_err(|err| ProxyResolutionError::DefaultProxyInvalid {
+        value: value.to_string(),
+        reason: err.to_string(),
+        wpad_error,
+    })
+}
+
 const HTTP_PROXY_KEYS: [&str; 2] = ["http_proxy", "HTTP_PROXY"];
 const HTTPS_PROXY_KEYS: [&str; 2] = ["https_proxy", "HTTPS_PROXY"];
 const FTP_PROXY_KEYS: [&str; 2] = ["ftp_proxy", "FTP_PROXY"];
 const ALL_PROXY_KEYS: [&str; 2] = ["all_proxy", "ALL_PROXY"];
 const PROXY_RSYNC_KEYS: [&str; 2] = ["proxy_rsync", "PROXY_RSYNC"];
 const NO_PROXY_KEYS: [&str; 2] = ["no_proxy", "NO_PROXY"];
-const MANAGED_START: &str = "### MANAGED BY PROXYCTL-RS START (DO NOT EDIT)";
-const MANAGED_END: &str = "### MANAGED BY PROXYCTL-RS END (DO NOT EDIT)";
-
-fn persist_proxy_settings(
-    proxy_settings: &config::ProxySettings,
-    proxy_url: &str,
-    no_proxy: Option<&str>,
-) -> Result<()> {
-    let profiles = resolve_shell_profiles()?;
-    if profiles.is_empty() {
-        return Ok(());
-    }
+const MANAGED_KEY_GROUPS: [&[&str]; 6] = [
+    &HTTP_PROXY_KEYS,
+    &HTTPS_PROXY_KEYS,
+    &FTP_PROXY_KEYS,
+    &ALL_PROXY_KEYS,
+    &PROXY_RSYNC_KEYS,
+    &NO_PROXY_KEYS,
+];
+pub(crate) const MANAGED_START: &str = "### MANAGED BY PROXYCTL-RS START (DO NOT EDIT)";
+pub(crate) const MANAGED_END: &str = "### MANAGED BY PROXYCTL-RS END (DO NOT EDIT)";
+pub(crate) const FISH_MANAGED_START: &str = "# MANAGED BY PROXYCTL-RS START (DO NOT EDIT)";
+pub(crate) const FISH_MANAGED_END: &str = "# MANAGED BY PROXYCTL-RS END (DO NOT EDIT)";
+
+/// How a profile sets environment variables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ShellSyntax {
+    /// `export KEY="value"` for sh-compatible shells.
+    PosixExport,
+    /// `set -gx KEY 'value'` for fish.
+    FishSet,
+    /// `$Env:KEY = "value"` for PowerShell.
+    PowerShellAssign,
+    /// `KEY="value"` in /etc/environment.
+    EtcEnvironment,
+    /// `setenv KEY value` in launchd.conf.
+    LaunchdSetenv,
+}
 
-    let exports = gather_proxy_exports(proxy_settings, proxy_url, no_proxy);
-    if exports.is_empty() {
-        for profile in profiles {
-            remove_managed_block(&profile)?;
+impl ShellSyntax {
+    fn for_profile(profile: &Path) -> Self {
+        let file_name = profile.file_name().and_then(|name| name.to_str());
+        let in_etc = profile
+            .parent()
+            .and_then(|parent| parent.file_name())
+            .is_some_and(|name| name == "etc");
+        match file_name {
+            Some("environment") if in_etc => ShellSyntax::EtcEnvironment,
+            Some("launchd.conf") if in_etc => ShellSyntax::LaunchdSetenv,
+            Some(name) if name.ends_with(".fish") => ShellSyntax::FishSet,
+            Some(name) if name.ends_with(".ps1") => ShellSyntax::PowerShellAssign,
+            _ => ShellSyntax::PosixExport,
         }
-        return Ok(());
     }
 
-    for profile in profiles {
-        write_managed_block(&profile, &exports)?;
+    fn managed_markers(self) -> (&'static str, &'static str) {
+        match self {
+            ShellSyntax::FishSet => (FISH_MANAGED_START, FISH_MANAGED_END),
+            _ => (MANAGED_START, MANAGED_END),
+        }
     }
+}
 
-    Ok(())
+// An empty block for every profile when there is nothing to export, so
+// stale blocks get removed.
+fn plan_profile_writes(profiles: Vec<PathBuf>, exports: &[(&str, &str)]) -> Vec<ProxyOperation> {
+    profiles
+        .into_iter()
+        .map(|profile| {
+            let content = format_exports(ShellSyntax::for_profile(&profile), exports).join("\n");
+            ProxyOperation::WriteProfile(profile, content)
+        })
+        .collect()
 }
 
 fn remove_persisted_settings() -> Result<()> {
+    warn_about_skipped_profiles()?;
     for profile in resolve_shell_profiles()? {
         remove_managed_block(&profile)?;
     }
+    remove_system_settings_in(Path::new("/"))
+}
+
+/// Write the managed block for the proxy stored in `state` to the user's
+/// shell profiles, as `proxy on` did when it was saved.
+pub fn persist_proxy_settings(state: &db::EnvState) -> Result<()> {
+    let proxy_settings = config::get_proxy_settings()?;
+    let mut exports = Vec::new();
+    for (keys, value) in [
+        (&HTTP_PROXY_KEYS, &state.http_proxy),
+        (&HTTPS_PROXY_KEYS, &state.https_proxy),
+        (&FTP_PROXY_KEYS, &state.ftp_proxy),
+        (&ALL_PROXY_KEYS, &state.all_proxy),
+        (&PROXY_RSYNC_KEYS, &state.proxy_rsync),
+        (&NO_PROXY_KEYS, &state.no_proxy),
+    ] {
+        if let Some(value) = value.as_deref().filter(|value| !value.is_empty()) {
+            add_export_lines(&mut exports, &proxy_settings, keys, value);
+        }
+    }
+    if exports.is_empty() {
+        return Err(anyhow!("no proxy is stored in the database"));
+    }
+    let profiles = resolve_shell_profiles()?;
+    if profiles.is_empty() {
+        return Err(anyhow!("no shell profile is configured"));
+    }
+    apply_proxy_operations(&plan_profile_writes(profiles, &exports))
+}
+
+/// System-wide environment files under `root` used by `--scope system`.
+pub fn system_profiles_in(root: &Path) -> Vec<PathBuf> {
+    if cfg!(target_os = "macos") {
+        vec![root.join("etc").join("launchd.conf")]
+    } else {
+        vec![
+            root.join("etc").join("environment"),
+            root.join("etc").join("profile.d").join("proxyctl-rs.sh"),
+        ]
+    }
+}
+
+/// Write the managed block to every system profile under `root`, failing
+/// before anything is written unless all of them are writable. Proxy URLs
+/// with credentials are refused, since every user can read these files.
+pub fn persist_system_settings_in(
+    root: &Path,
+    exports: &[(&str, &str)],
+    dry_run: DryRun,
+) -> Result<()> {
+    let operations = plan_system_writes_in(root, exports)?;
+    if dry_run.0 {
+        preview_proxy_operations(&operations)?.print();
+        return Ok(());
+    }
+    apply_proxy_operations(&operations)
+}
 
+fn plan_system_writes_in(root: &Path, exports: &[(&str, &str)]) -> Result<Vec<ProxyOperation>> {
+    if let Some((key, _)) = exports.iter().find(|(_, value)| has_credentials(value)) {
+        return Err(anyhow!(
+            "{key} holds proxy credentials, which would be readable by every user in the system profiles; use --scope user for an authenticated proxy"
+        ));
+    }
+    let profiles = system_profiles_in(root);
+    if profiles.iter().any(|path| !system_path_writable(path)) {
+        return Err(anyhow!(
+            "System-wide proxy requires sudo or root privileges — try: sudo proxyctl-rs on --scope system"
+        ));
+    }
+    Ok(plan_profile_writes(profiles, exports))
+}
+
+/// Remove the managed block from the system profiles under `root`. Files
+/// that can't be written are left alone with a warning.
+pub fn remove_system_settings_in(root: &Path) -> Result<()> {
+    for profile in system_profiles_in(root) {
+        let has_block = fs::read_to_string(&profile)
+            .map(|content| content.contains(MANAGED_START))
+            .unwrap_or(false);
+        if !has_block {
+            continue;
+        }
+        if system_path_writable(&profile) {
+            remove_managed_block(&profile)?;
+        } else {
+            output::warn(format!(
+                "{} still holds system-wide proxy settings; run `sudo proxyctl-rs off` to remove them",
+                profile.display()
+            ));
+        }
+    }
     Ok(())
 }
 
+/// Report each system profile under `root` and whether it can be written
+/// (or, when missing, created).
+pub fn check_system_writability_in(root: &Path) -> Vec<ProfileWriteCheck> {
+    system_profiles_in(root)
+        .into_iter()
+        .map(|path| ProfileWriteCheck {
+            writable: system_path_writable(&path),
+            path,
+        })
+        .collect()
+}
+
+fn system_path_writable(path: &Path) -> bool {
+    if path.exists() {
+        return profile_writable(path);
+    }
+    let Some(dir) = path.ancestors().skip(1).find(|dir| dir.exists()) else {
+        return false;
+    };
+    let read_only = fs::metadata(dir)
+        .map(|metadata| metadata.permissions().readonly())
+        .unwrap_or(true);
+    if read_only {
+        return false;
+    }
+    // Directory permission bits don't say whether this user may create files.
+    let probe = dir.join(".proxyctl-rs-write-test");
+    match fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+    {
+        Ok(_) => fs::remove_file(&probe).is_ok(),
+        Err(err) => err.kind() == std::io::ErrorKind::AlreadyExists,
+    }
+}
+
 fn set_env_vars(keys: &[&str], value: &str) {
     for key in keys {
+        output::detail(format!("Setting {key}={}", mask_proxy_credentials(value)));
         env::set_var(key, value);
     }
 }
@@ -269,16 +1337,79 @@ async fn load_env_state() -> Result<db::EnvState> {
     db::load_env_state(&db_path).await
 }
 
-fn resolved_from_value(value: &str) -> Result<ResolvedProxy> {
-    let host = extract_proxy_host(value)
+// `value` as a proxy, checked with `config::validate_proxy_url` (assuming http://
+// for a bare `host:port`) when `validate` is set.
+fn resolved_from_value(value: &str, validate: bool) -> Result<ResolvedProxy> {
+    let value = normalize_proxy_url(value);
+    if validate {
+        if value.contains("://") {
+            validate_proxy_url(&value)?;
+        } else {
+            validate_proxy_url(&format!("http://{value}"))?;
+        }
+    }
+    let host = extract_proxy_host(&value)
         .ok_or_else(|| anyhow!("unable to determine proxy host from '{value}'"))?;
-    Ok(ResolvedProxy {
-        proxy_url: value.to_string(),
-        proxy_host: host,
-    })
+    Ok(ResolvedProxy::new(value, host))
+}
+
+/// Send a HEAD request for `test_url` through `proxy_url` without touching
+/// any proxy state, failing unless a successful response comes back.
+pub async fn preflight_check(proxy_url: &str, test_url: &str) -> Result<()> {
+    let proxy_url = if proxy_url.contains("://") {
+        proxy_url.trim().to_string()
+    } else {
+        format!("http://{}", proxy_url.trim())
+    };
+    let masked = mask_proxy_credentials(&proxy_url);
+
+    let client = reqwest::Client::builder()
+        .proxy(reqwest::Proxy::all(&proxy_url)?)
+        .timeout(std::time::Duration::from_secs(10))
+        .build()?;
+
+    client
+        .head(test_url)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .map_err(|err| anyhow!("pre-flight request to {test_url} via {masked} failed: {err}"))?;
+
+    Ok(())
+}
+
+/// Drop a bare trailing `/` from `url` (`http://proxy:8080/` becomes
+/// `http://proxy:8080`) while leaving real paths such as
+/// `http://proxy.corp/authenticate` untouched.
+pub fn normalize_proxy_url(url: &str) -> String {
+    let trimmed = url.trim();
+    if !trimmed.ends_with('/') {
+        return trimmed.to_string();
+    }
+
+    let root_only = match trimmed.split_once("://") {
+        Some(_) => reqwest::Url::parse(trimmed).is_ok_and(|parsed| {
+            parsed.path() == "/" && parsed.query().is_none() && parsed.fragment().is_none()
+        }),
+        None => !trimmed.trim_end_matches('/').contains('/'),
+    };
+
+    if root_only {
+        trimmed.trim_end_matches('/').to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+/// The first usable proxy in the environment, checking the standard
+/// variables, then `extra_proxy_vars` from the config.
+pub fn proxy_from_env() -> Option<ResolvedProxy> {
+    proxy_from_env_with_source(&[]).map(|(_, resolved)| resolved)
 }
 
-fn proxy_from_env() -> Option<ResolvedProxy> {
+/// Like [`proxy_from_env`], also checking `extra_vars` last and returning the
+/// variable the proxy came from.
+pub fn proxy_from_env_with_source(extra_vars: &[String]) -> Option<(String, ResolvedProxy)> {
     const VARS: [&[&str]; 5] = [
         &HTTPS_PROXY_KEYS,
         &HTTP_PROXY_KEYS,
@@ -286,24 +1417,54 @@ fn proxy_from_env() -> Option<ResolvedProxy> {
         &FTP_PROXY_KEYS,
         &PROXY_RSYNC_KEYS,
     ];
-    for keys in VARS {
-        if let Some(value) = get_env_value(keys) {
-            if let Some(host) = extract_proxy_host(&value) {
-                return Some(ResolvedProxy {
-                    proxy_url: value,
-                    proxy_host: host,
-                });
-            }
+    let mut keys: Vec<String> = VARS
+        .into_iter()
+        .flatten()
+        .map(|key| key.to_string())
+        .collect();
+    keys.extend(config::get_extra_proxy_vars());
+    keys.extend(extra_vars.iter().cloned());
+
+    for key in keys {
+        let Some(value) = env::var(&key).ok().filter(|value| !value.is_empty()) else {
+            continue;
+        };
+        if let Some(host) = extract_proxy_host(&value) {
+            return Some((key, ResolvedProxy::new(value, host)));
         }
     }
     None
 }
 
-fn extract_proxy_host(value: &str) -> Option<String> {
+/// Like [`proxy_from_env_with_source`], but failing when no variable holds a
+/// usable proxy, for `--inherit-env` / `--from-env`.
+pub fn proxy_from_env_explicit(extra_vars: &[String]) -> Result<(String, ResolvedProxy)> {
+    proxy_from_env_with_source(extra_vars)
+        .ok_or_else(|| anyhow!("found no proxy variables set in the environment"))
+}
+
+/// The host (without IPv6 brackets) and port of `value`, as found by
+/// [`extract_proxy_host`].
+pub fn proxy_host_and_port(value: &str) -> Option<(String, u16)> {
+    let host = extract_proxy_host(value)?;
+    let (host, port) = host.rsplit_once(':')?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    Some((host.to_string(), port.parse().ok()?))
+}
+
+/// The `host:port` a proxy URL (or bare `host:port`) connects to, using the
+/// scheme's default port when none is given. IPv6 hosts come back in
+/// brackets (`[::1]:8080`); without brackets, as in `2001:db8::1:3128`, the
+/// last group is taken to be the port.
+pub fn extract_proxy_host(value: &str) -> Option<String> {
     let trimmed = value.trim();
     if trimmed.is_empty() {
         return None;
     }
+    // A bare IPv6 literal like `[::1]` has no port to connect to; don't guess one.
+    if trimmed.starts_with('[') && trimmed.ends_with(']') {
+        return None;
+    }
 
     let try_parse = |input: &str| -> Option<String> {
         if let Ok(url) = reqwest::Url::parse(input) {
@@ -320,8 +1481,10 @@ fn extract_proxy_host(value: &str) -> Option<String> {
         return Some(host);
     }
 
-    if let Some(host) = try_parse(&format!("http://{trimmed}")) {
-        return Some(host);
+    if !trimmed.contains("://") {
+        if let Some(host) = try_parse(&format!("http://{trimmed}")) {
+            return Some(host);
+        }
     }
 
     let mut candidate = trimmed;
@@ -337,6 +1500,10 @@ fn extract_proxy_host(value: &str) -> Option<String> {
     }
 
     candidate = candidate.trim_end_matches(';').trim().trim_end_matches('/');
+    // URLs with an unbracketed IPv6 host don't parse; split them by hand.
+    if let Some((_, rest)) = candidate.split_once("://") {
+        candidate = rest.rsplit_once('@').map_or(rest, |(_, host)| host);
+    }
     if candidate.is_empty() {
         return None;
     }
@@ -345,65 +1512,39 @@ fn extract_proxy_host(value: &str) -> Option<String> {
         return Some(host);
     }
 
-    if let Some((host_part, port_part)) = split_host_port(candidate) {
+    if let Some((host_part, port_part)) = config::split_host_port(candidate) {
         return Some(format!("{host_part}:{port_part}"));
     }
 
     None
 }
 
-fn split_host_port(input: &str) -> Option<(String, String)> {
-    let input = input.trim();
-    if input.starts_with('[') {
-        if let Some(idx) = input.find("]: ") {
-            let host = &input[..idx + 1];
-            let port = &input[idx + 2..];
-            return Some((host.trim().to_string(), port.trim().to_string()));
-        }
-        if let Some(idx) = input.rfind("]:") {
-            let host = &input[..=idx];
-            let port = &input[idx + 2..];
-            return Some((host.trim().to_string(), port.trim().to_string()));
-        }
-    }
-
-    if let Some((host, port)) = input.rsplit_once(':') {
-        let host = host.trim();
-        let port = port.trim();
-        if !host.is_empty() && !port.is_empty() {
-            return Some((host.to_string(), port.to_string()));
-        }
-    }
-
-    None
-}
-
-fn gather_proxy_exports(
+fn gather_proxy_exports<'a>(
     proxy_settings: &config::ProxySettings,
-    proxy_url: &str,
-    no_proxy: Option<&str>,
-) -> Vec<String> {
+    proxy_url: &'a str,
+    no_proxy: Option<&'a str>,
+) -> Vec<(&'static str, &'a str)> {
     let mut exports = Vec::new();
 
     if proxy_settings.enable_http_proxy {
-        add_export_lines(&mut exports, &HTTP_PROXY_KEYS, proxy_url);
+        add_export_lines(&mut exports, proxy_settings, &HTTP_PROXY_KEYS, proxy_url);
     }
     if proxy_settings.enable_https_proxy {
-        add_export_lines(&mut exports, &HTTPS_PROXY_KEYS, proxy_url);
+        add_export_lines(&mut exports, proxy_settings, &HTTPS_PROXY_KEYS, proxy_url);
     }
     if proxy_settings.enable_ftp_proxy {
-        add_export_lines(&mut exports, &FTP_PROXY_KEYS, proxy_url);
+        add_export_lines(&mut exports, proxy_settings, &FTP_PROXY_KEYS, proxy_url);
     }
     if proxy_settings.enable_all_proxy {
-        add_export_lines(&mut exports, &ALL_PROXY_KEYS, proxy_url);
+        add_export_lines(&mut exports, proxy_settings, &ALL_PROXY_KEYS, proxy_url);
     }
     if proxy_settings.enable_proxy_rsync {
-        add_export_lines(&mut exports, &PROXY_RSYNC_KEYS, proxy_url);
+        add_export_lines(&mut exports, proxy_settings, &PROXY_RSYNC_KEYS, proxy_url);
     }
     if proxy_settings.enable_no_proxy {
         if let Some(value) = no_proxy {
             if !value.is_empty() {
-                add_export_lines(&mut exports, &NO_PROXY_KEYS, value);
+                add_export_lines(&mut exports, proxy_settings, &NO_PROXY_KEYS, value);
             }
         }
     }
@@ -411,13 +1552,88 @@ fn gather_proxy_exports(
     exports
 }
 
-fn add_export_lines(target: &mut Vec<String>, keys: &[&str], value: &str) {
+fn add_export_lines<'a>(
+    target: &mut Vec<(&'static str, &'a str)>,
+    proxy_settings: &config::ProxySettings,
+    keys: &[&'static str],
+    value: &'a str,
+) {
     for key in keys {
-        target.push(format!("export {key}=\"{value}\""));
+        if proxy_settings.exports_key(key) {
+            target.push((key, value));
+        }
+    }
+}
+
+fn format_exports(syntax: ShellSyntax, exports: &[(&str, &str)]) -> Vec<String> {
+    exports
+        .iter()
+        .map(|(key, value)| match syntax {
+            ShellSyntax::PosixExport => format!("export {key}=\"{value}\""),
+            ShellSyntax::FishSet => format!("set -gx {key} {}", fish_quote(value)),
+            ShellSyntax::PowerShellAssign => {
+                format!("$Env:{key} = {}", powershell_quote(value))
+            }
+            ShellSyntax::EtcEnvironment => format!("{key}=\"{value}\""),
+            ShellSyntax::LaunchdSetenv => format!("setenv {key} {value}"),
+        })
+        .collect()
+}
+
+// Inside fish single quotes only `\\` and `\'` are escapes.
+fn fish_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+// Backtick is PowerShell's escape character inside double quotes, where `$`
+// would otherwise start a variable.
+fn powershell_quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for ch in value.chars() {
+        if matches!(ch, '`' | '"' | '$') {
+            quoted.push('`');
+        }
+        quoted.push(ch);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The shell profiles `proxy on` writes its managed block to.
+pub fn resolve_shell_profiles() -> Result<Vec<PathBuf>> {
+    Ok(select_shell_profiles()?.0)
+}
+
+// Warn about the read-only profiles `resolve_shell_profiles` skips; only for
+// commands that actually write the profiles.
+fn warn_about_skipped_profiles() -> Result<()> {
+    for warning in select_shell_profiles()?.1 {
+        output::warn(warning);
     }
+    Ok(())
 }
 
-fn resolve_shell_profiles() -> Result<Vec<PathBuf>> {
+// The selected profiles and a warning for each read-only one skipped.
+fn select_shell_profiles() -> Result<(Vec<PathBuf>, Vec<String>)> {
+    let mut profiles = Vec::new();
+    let mut warnings = Vec::new();
+    let mut seen = HashSet::new();
+
+    for candidates in profile_candidates()? {
+        let (profile, skipped) = select_profile(&candidates);
+        warnings.extend(skipped);
+        if let Some(profile) = profile {
+            push_unique_path(&mut profiles, &mut seen, profile);
+        }
+    }
+
+    Ok((profiles, warnings))
+}
+
+/// Candidate profile paths grouped by preference: each explicit
+/// `profile_paths` entry on its own, then one group per configured shell.
+fn profile_candidates() -> Result<Vec<Vec<PathBuf>>> {
     let integration = config::get_shell_integration()?;
     let home = dirs::home_dir().ok_or_else(|| anyhow!("Could not find home directory"))?;
 
@@ -428,20 +1644,18 @@ fn resolve_shell_profiles() -> Result<Vec<PathBuf>> {
         profile_paths,
     } = integration;
 
-    let mut profiles = Vec::new();
-    let mut seen = HashSet::new();
-
-    for path in profile_paths {
-        let expanded = expand_profile_path(&path, &home);
-        push_unique_path(&mut profiles, &mut seen, expanded);
-    }
+    let mut groups: Vec<Vec<PathBuf>> = profile_paths
+        .iter()
+        .map(|path| vec![expand_profile_path(path, &home)])
+        .collect();
 
     let mut shell_names: HashSet<String> = HashSet::new();
 
     if detect_shell {
         if let Ok(shell_value) = env::var("SHELL") {
+            // file_stem so `pwsh.exe` on Windows matches like `pwsh`.
             if let Some(name) = Path::new(shell_value.trim())
-                .file_name()
+                .file_stem()
                 .and_then(|value| value.to_str())
             {
                 if !name.is_empty() {
@@ -464,33 +1678,87 @@ fn resolve_shell_profiles() -> Result<Vec<PathBuf>> {
     }
 
     for shell in shell_names {
-        for profile in shell_profiles_for(&shell, &home) {
-            push_unique_path(&mut profiles, &mut seen, profile);
+        let candidates = shell_profiles_for(&shell, &home);
+        if !candidates.is_empty() {
+            groups.push(candidates);
         }
     }
 
-    Ok(profiles)
+    Ok(groups)
 }
 
 fn shell_profiles_for(shell: &str, home: &Path) -> Vec<PathBuf> {
-    match shell {
-        "zsh" => vec![select_profile(&[".zshenv", ".zprofile", ".zshrc"], home)],
-        "bash" => vec![select_profile(&[".bash_profile", ".bashrc"], home)],
-        _ => Vec::new(),
+    let names: &[&str] = match shell {
+        "zsh" => &[".zshenv", ".zprofile", ".zshrc"],
+        "bash" => &[".bash_profile", ".bashrc"],
+        "fish" => &[".config/fish/config.fish"],
+        // The real path is `$PROFILE`, which needs PowerShell to resolve.
+        "pwsh" | "powershell" if cfg!(windows) => {
+            &["Documents/PowerShell/Microsoft.PowerShell_profile.ps1"]
+        }
+        "pwsh" | "powershell" => &[".config/powershell/Microsoft.PowerShell_profile.ps1"],
+        _ => &[],
+    };
+    names.iter().map(|name| home.join(name)).collect()
+}
+
+/// Pick the first existing, writable candidate. Read-only files are skipped;
+/// if none qualify, fall back to the first missing candidate so it can be
+/// created. Also returns a warning for each skipped file.
+fn select_profile(candidates: &[PathBuf]) -> (Option<PathBuf>, Vec<String>) {
+    let mut warnings = Vec::new();
+    for path in candidates.iter().filter(|path| path.exists()) {
+        if profile_writable(path) {
+            return (Some(path.clone()), warnings);
+        }
+        warnings.push(format!(
+            "shell profile {} is not writable; skipping it",
+            path.display()
+        ));
     }
-    .into_iter()
-    .flatten()
-    .collect()
+
+    let missing = candidates.iter().find(|path| !path.exists()).cloned();
+    if let (Some(path), Some(warning)) = (&missing, warnings.last_mut()) {
+        warning.push_str(&format!("; {} will be created instead", path.display()));
+    }
+    (missing, warnings)
+}
+
+fn profile_writable(path: &Path) -> bool {
+    // Honour the read-only bit even when running as root, where open() would succeed.
+    let read_only = fs::metadata(path)
+        .map(|metadata| metadata.permissions().readonly())
+        .unwrap_or(false);
+    !read_only
+        && fs::OpenOptions::new().append(true).open(path).map_or_else(
+            |err| err.kind() != std::io::ErrorKind::PermissionDenied,
+            |_| true,
+        )
+}
+
+/// Writability of a shell profile proxyctl-rs may manage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileWriteCheck {
+    pub path: PathBuf,
+    pub writable: bool,
 }
 
-fn select_profile(candidates: &[&str], home: &Path) -> Option<PathBuf> {
-    for candidate in candidates {
-        let path = home.join(candidate);
-        if path.exists() {
-            return Some(path);
+/// Report every existing candidate profile and whether it can be written.
+pub fn check_profile_writability() -> Result<Vec<ProfileWriteCheck>> {
+    let mut checks = Vec::new();
+    let mut seen = HashSet::new();
+
+    for path in profile_candidates()?.into_iter().flatten() {
+        if !path.exists() || !seen.insert(path.clone()) {
+            continue;
         }
+        checks.push(ProfileWriteCheck {
+            writable: profile_writable(&path),
+            path,
+        });
     }
-    candidates.first().map(|candidate| home.join(candidate))
+
+    Ok(checks)
 }
 
 fn expand_profile_path(value: &str, home: &Path) -> PathBuf {
@@ -514,14 +1782,24 @@ fn push_unique_path(paths: &mut Vec<PathBuf>, seen: &mut HashSet<PathBuf>, path:
 }
 
 fn write_managed_block(profile: &Path, exports: &[String]) -> Result<()> {
+    output::detail(format!("Writing proxy settings to {}", profile.display()));
     ensure_parent_directory(profile)?;
-    let existing = if profile.exists() {
-        fs::read_to_string(profile)?
+    let existing = read_profile(profile)?;
+    fs::write(profile, render_managed_block(profile, &existing, exports))?;
+    Ok(())
+}
+
+fn read_profile(profile: &Path) -> Result<String> {
+    if profile.exists() {
+        Ok(fs::read_to_string(profile)?)
     } else {
-        String::new()
-    };
+        Ok(String::new())
+    }
+}
 
-    let (mut base, _) = strip_managed_block(&existing);
+// `existing` with its managed block replaced by one holding `exports`, at the end.
+fn render_managed_block(profile: &Path, existing: &str, exports: &[String]) -> String {
+    let (mut base, _) = strip_managed_block(existing);
 
     if !base.is_empty() && !base.ends_with('\n') {
         base.push('\n');
@@ -530,17 +1808,16 @@ fn write_managed_block(profile: &Path, exports: &[String]) -> Result<()> {
         base.push('\n');
     }
 
+    let (start, end) = ShellSyntax::for_profile(profile).managed_markers();
     let mut block_lines = Vec::with_capacity(exports.len() + 2);
-    block_lines.push(MANAGED_START.to_string());
+    block_lines.push(start.to_string());
     block_lines.extend(exports.iter().cloned());
-    block_lines.push(MANAGED_END.to_string());
+    block_lines.push(end.to_string());
     let block = block_lines.join("\n");
 
     base.push_str(&block);
     base.push('\n');
-
-    fs::write(profile, base)?;
-    Ok(())
+    base
 }
 
 fn remove_managed_block(profile: &Path) -> Result<()> {
@@ -551,39 +1828,50 @@ fn remove_managed_block(profile: &Path) -> Result<()> {
     let existing = fs::read_to_string(profile)?;
     let (updated, changed) = strip_managed_block(&existing);
     if changed {
+        output::detail(format!(
+            "Removing proxy settings from {}",
+            profile.display()
+        ));
         fs::write(profile, updated)?;
     }
 
     Ok(())
 }
 
+// The fish markers are a suffix of the POSIX ones, so POSIX blocks go first.
+#[allow(clippy::while_let_loop)]
 fn strip_managed_block(content: &str) -> (String, bool) {
     let mut current = content.to_string();
     let mut changed = false;
 
-    loop {
-        let Some(start_idx) = current.find(MANAGED_START) else {
-            break;
-        };
+    for (start, end) in [
+        (MANAGED_START, MANAGED_END),
+        (FISH_MANAGED_START, FISH_MANAGED_END),
+    ] {
+        loop {
+            let Some(start_idx) = current.find(start) else {
+                break;
+            };
 
-        let Some(rel_end) = current[start_idx..].find(MANAGED_END) else {
-            break;
-        };
+            let Some(rel_end) = current[start_idx..].find(end) else {
+                break;
+            };
 
-        let end_idx = start_idx + rel_end + MANAGED_END.len();
+            let end_idx = start_idx + rel_end + end.len();
 
-        let mut remove_start = start_idx;
-        while remove_start > 0 && matches!(current.as_bytes()[remove_start - 1], b'\n') {
-            remove_start -= 1;
-        }
+            let mut remove_start = start_idx;
+            while remove_start > 0 && matches!(current.as_bytes()[remove_start - 1], b'\n') {
+                remove_start -= 1;
+            }
 
-        let mut remove_end = end_idx;
-        while remove_end < current.len() && matches!(current.as_bytes()[remove_end], b'\n') {
-            remove_end += 1;
-        }
+            let mut remove_end = end_idx;
+            while remove_end < current.len() && matches!(current.as_bytes()[remove_end], b'\n') {
+                remove_end += 1;
+            }
 
-        current.replace_range(remove_start..remove_end, "");
-        changed = true;
+            current.replace_range(remove_start..remove_end, "");
+            changed = true;
+        }
     }
 
     (current, changed)
@@ -595,3 +1883,106 @@ fn ensure_parent_directory(path: &Path) -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod proxy_tests {
+    use super::*;
+    use std::sync::{Mutex, MutexGuard, OnceLock};
+
+    fn noop_clear(_keys: &[&str]) {}
+
+    struct ConfigDirGuard {
+        _dir: tempfile::TempDir,
+        originals: Vec<(&'static str, Option<String>)>,
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl ConfigDirGuard {
+        fn new() -> Self {
+            let lock = env_lock().lock().unwrap_or_else(|e| e.into_inner());
+            let dir = tempfile::tempdir().expect("temp config dir");
+            let home_dir = dir.path().join("home");
+            fs::create_dir_all(&home_dir).expect("home dir");
+
+            let entries = [
+                ("HOME", Some(home_dir)),
+                ("XDG_CONFIG_HOME", Some(dir.path().join("config"))),
+                ("XDG_DATA_HOME", Some(dir.path().join("data"))),
+                ("SHELL", Some(PathBuf::from("/bin/false"))),
+                ("SSH_CONFIG", None),
+            ]
+            .into_iter()
+            .chain(HTTP_PROXY_KEYS.into_iter().map(|key| (key, None)));
+
+            let originals = entries
+                .map(|(key, value)| {
+                    let original = env::var(key).ok();
+                    match value {
+                        Some(value) => env::set_var(key, value),
+                        None => env::remove_var(key),
+                    }
+                    (key, original)
+                })
+                .collect();
+            Self {
+                _dir: dir,
+                originals,
+                _lock: lock,
+            }
+        }
+    }
+
+    impl Drop for ConfigDirGuard {
+        fn drop(&mut self) {
+            for (key, original) in self.originals.drain(..) {
+                match original {
+                    Some(value) => env::set_var(key, value),
+                    None => env::remove_var(key),
+                }
+            }
+        }
+    }
+
+    fn env_lock() -> &'static Mutex<()> {
+        static LOCK: OnceLock<Mutex<()>> = OnceLock::new();
+        LOCK.get_or_init(|| Mutex::new(()))
+    }
+
+    #[test]
+    fn extract_proxy_host_handles_ipv6() {
+        assert_eq!(
+            extract_proxy_host("http://[::1]:8080").as_deref(),
+            Some("[::1]:8080")
+        );
+        assert_eq!(
+            extract_proxy_host("[2001:db8::1]:3128").as_deref(),
+            Some("[2001:db8::1]:3128")
+        );
+        assert_eq!(
+            extract_proxy_host("PROXY [::1]:8080").as_deref(),
+            Some("[::1]:8080")
+        );
+        assert_eq!(extract_proxy_host("[::1]"), None);
+    }
+
+    #[tokio::test]
+    async fn disable_fails_without_changes_when_env_persists() {
+        let _config_guard = ConfigDirGuard::new();
+
+        let state = db::EnvState {
+            http_proxy: Some("http://proxy.example.com:8080".to_string()),
+            ..db::EnvState::default()
+        };
+        save_env_state(&state).await.unwrap();
+        env::set_var("http_proxy", "http://proxy.example.com:8080");
+
+        assert!(!verify_env_cleared(&HTTP_PROXY_KEYS));
+        let err = disable_proxy_with(noop_clear).await.unwrap_err();
+        assert!(err.to_string().contains("still set"), "{err}");
+        assert_eq!(load_env_state().await.unwrap(), state);
+
+        disable_proxy_with(clear_env_vars).await.unwrap();
+        assert!(verify_env_cleared(&HTTP_PROXY_KEYS));
+        assert_eq!(load_env_state().await.unwrap(), db::EnvState::default());
+    }
+}
@@ -0,0 +1,111 @@
+use anyhow::{anyhow, Context, Result};
+use std::process::Command;
+
+/// The `networksetup` argument lists that point the web and secure web proxy
+/// of macOS network service `network_service` at `proxy_url`, or its SOCKS
+/// proxy for `socks4://` and `socks5://` URLs. Credentials in the URL are
+/// left out; macOS asks for them itself.
+pub fn macos_proxy_commands(proxy_url: &str, network_service: &str) -> Result<Vec<Vec<String>>> {
+    let url = if proxy_url.contains("://") {
+        reqwest::Url::parse(proxy_url)
+    } else {
+        reqwest::Url::parse(&format!("http://{}", proxy_url.trim()))
+    }
+    .with_context(|| format!("invalid proxy URL for networksetup: {proxy_url}"))?;
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow!("proxy URL has no host: {proxy_url}"))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| anyhow!("proxy URL has no port: {proxy_url}"))?
+        .to_string();
+
+    let options: &[&str] = if url.scheme().starts_with("socks") {
+        &["-setsocksfirewallproxy"]
+    } else {
+        &["-setwebproxy", "-setsecurewebproxy"]
+    };
+    Ok(options
+        .iter()
+        .map(|&option| {
+            [option, network_service, host, port.as_str()]
+                .map(str::to_string)
+                .to_vec()
+        })
+        .collect())
+}
+
+/// The `networksetup` argument lists that switch the web, secure web and
+/// SOCKS proxies of `network_service` off.
+pub fn macos_disable_commands(network_service: &str) -> Vec<Vec<String>> {
+    [
+        "-setwebproxystate",
+        "-setsecurewebproxystate",
+        "-setsocksfirewallproxystate",
+    ]
+    .into_iter()
+    .map(|option| {
+        [option, network_service, "off"]
+            .map(str::to_string)
+            .to_vec()
+    })
+    .collect()
+}
+
+/// Set the system proxy of `network_service` to `proxy_url` (see
+/// [`macos_proxy_commands`]).
+pub fn set_macos_proxy(proxy_url: &str, network_service: &str) -> Result<()> {
+    for args in macos_proxy_commands(proxy_url, network_service)? {
+        run_networksetup(&args)?;
+    }
+    Ok(())
+}
+
+/// Turn off the system web, secure web and SOCKS proxies of `network_service`.
+pub fn disable_macos_proxy(network_service: &str) -> Result<()> {
+    for args in macos_disable_commands(network_service) {
+        run_networksetup(&args)?;
+    }
+    Ok(())
+}
+
+/// The macOS network services, such as "Wi-Fi", that `networksetup` manages.
+pub fn list_macos_network_services() -> Result<Vec<String>> {
+    let output = networksetup(&["-listallnetworkservices".to_string()])?;
+    if !output.status.success() {
+        return Err(networksetup_error(&output));
+    }
+    // The first line explains that a leading `*` marks a disabled service.
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .skip(1)
+        .map(|line| line.trim_start_matches('*').trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect())
+}
+
+fn run_networksetup(args: &[String]) -> Result<()> {
+    let output = networksetup(args)?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(networksetup_error(&output))
+    }
+}
+
+fn networksetup(args: &[String]) -> Result<std::process::Output> {
+    Command::new("networksetup")
+        .args(args)
+        .output()
+        .context("running networksetup")
+}
+
+fn networksetup_error(output: &std::process::Output) -> anyhow::Error {
+    // networksetup reports most errors on stdout.
+    let message = [&output.stderr, &output.stdout]
+        .into_iter()
+        .map(|bytes| String::from_utf8_lossy(bytes).trim().to_string())
+        .find(|message| !message.is_empty())
+        .unwrap_or_else(|| format!("exited with {}", output.status));
+    anyhow!("networksetup failed: {message}")
+}
@@ -0,0 +1,104 @@
+mod support;
+
+use clap::{Arg, Command};
+use clap_complete::Shell;
+use proxyctl_rs::completions;
+use support::{stdout, CliEnv};
+
+fn sample_command() -> Command {
+    Command::new("proxyctl-rs")
+        .subcommand(Command::new("on").arg(Arg::new("proxy").long("proxy")))
+        .subcommand(Command::new("off"))
+}
+
+fn render(shell: Shell, with_instructions: bool) -> String {
+    let mut buffer = Vec::new();
+    completions::generate(shell, &mut sample_command(), with_instructions, &mut buffer)
+        .expect("generate completions");
+    String::from_utf8(buffer).expect("utf8 output")
+}
+
+#[test]
+fn completions_include_zsh_install_instructions() {
+    let output = render(Shell::Zsh, true);
+    assert!(output.starts_with("# Save to ~/.zfunc/_proxyctl-rs"));
+    assert!(output.contains("fpath=(~/.zfunc $fpath)"));
+}
+
+#[test]
+fn completions_include_bash_and_fish_install_paths() {
+    assert!(render(Shell::Bash, true).contains("~/.bash_completion.d/proxyctl-rs"));
+    assert!(render(Shell::Fish, true).contains("~/.config/fish/completions/proxyctl-rs.fish"));
+}
+
+#[test]
+fn completions_omit_instructions_by_default() {
+    let output = render(Shell::Bash, false);
+    assert!(!output.contains("# Save to"));
+    assert!(output.contains("proxyctl-rs"));
+}
+
+#[test]
+fn install_instructions_note_missing_directory() {
+    let home = tempfile::tempdir().unwrap();
+    let message = completions::install_instructions(Shell::Fish, "proxyctl-rs", home.path());
+    assert!(message.contains("does not exist yet"));
+
+    std::fs::create_dir_all(home.path().join(".config/fish/completions")).unwrap();
+    let message = completions::install_instructions(Shell::Fish, "proxyctl-rs", home.path());
+    assert!(!message.contains("does not exist yet"));
+}
+
+// How each shell's script lists `command` as a top-level completion.
+fn top_level_entry(shell: &str, command: &str) -> String {
+    match shell {
+        "bash" => format!("proxyctl__rs,{command})"),
+        "zsh" => format!("'{command}:"),
+        "fish" => format!("__fish_proxyctl_rs_needs_command\" -f -a \"{command}\""),
+        "elvish" => format!("cand {command} '"),
+        "powershell" => format!("[CompletionResult]::new('{command}', '{command}'"),
+        other => panic!("no entry format for {other}"),
+    }
+}
+
+#[test]
+fn completions_cover_every_shell_and_top_level_command() {
+    let env = CliEnv::new("");
+    for shell in ["bash", "zsh", "fish", "elvish", "powershell"] {
+        let output = env.run(&["completions", shell]);
+        assert!(output.status.success(), "{shell}: {output:?}");
+        let script = stdout(&output);
+        for command in ["on", "off", "detect", "ssh", "status", "doctor"] {
+            let entry = top_level_entry(shell, command);
+            assert!(
+                script.contains(&entry),
+                "{shell} completions miss `{command}` ({entry})"
+            );
+        }
+    }
+}
+
+#[test]
+fn fish_and_zsh_complete_nested_commands_and_flags() {
+    let env = CliEnv::new("");
+    let fish = stdout(&env.run(&["completions", "fish"]));
+    let zsh = stdout(&env.run(&["completions", "zsh"]));
+
+    for command in ["add", "remove", "proxy", "run", "config"] {
+        let entry = format!("-f -a \"{command}\"");
+        assert!(fish.contains(&entry), "fish completions miss {entry}");
+        let entry = format!("\n'{command}:");
+        assert!(zsh.contains(&entry), "zsh completions miss {entry:?}");
+    }
+    for flag in ["dry-run", "quiet", "verbose"] {
+        let entry = format!("-l {flag} -d");
+        assert!(fish.contains(&entry), "fish completions miss {entry}");
+        let entry = format!("--{flag}[");
+        assert!(zsh.contains(&entry), "zsh completions miss {entry}");
+    }
+
+    assert!(
+        fish.contains("__fish_proxyctl_rs_using_subcommand ssh; and not __fish_seen_subcommand_from add remove"),
+        "{fish}"
+    );
+}
@@ -0,0 +1,101 @@
+use proxyctl_rs::conflicts::{self, ConflictWarning};
+use std::fs;
+use std::path::Path;
+
+const MANAGED_BLOCK: &str = "### MANAGED BY PROXYCTL-RS START (DO NOT EDIT)\nexport ALL_PROXY=\"http://proxy.example.com:8080\"\n### MANAGED BY PROXYCTL-RS END (DO NOT EDIT)\n";
+
+fn write(path: &Path, contents: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+}
+
+fn tools(warnings: &[ConflictWarning]) -> Vec<&str> {
+    warnings
+        .iter()
+        .map(|warning| warning.tool.as_str())
+        .collect()
+}
+
+#[test]
+fn clean_system_has_no_conflicts() {
+    let dir = tempfile::tempdir().unwrap();
+    let home = dir.path().join("home");
+    write(
+        &home.join(".bashrc"),
+        &format!("alias ll='ls -l'\n{MANAGED_BLOCK}"),
+    );
+    write(
+        &home.join(".ssh").join("config"),
+        "Host a\n    ProxyCommand /usr/bin/nc -X connect -x proxy:8080 %h %p\n",
+    );
+
+    let warnings = conflicts::find_conflicting_tools_in(
+        &home,
+        &dir.path().join("root"),
+        &home.join(".ssh").join("config"),
+        Some("http://proxy.example.com:8080".to_string()),
+    );
+    assert!(warnings.is_empty(), "{warnings:?}");
+}
+
+#[test]
+fn detects_fabricated_conflicts() {
+    let dir = tempfile::tempdir().unwrap();
+    let home = dir.path().join("home");
+    let root = dir.path().join("root");
+    write(
+        &home.join(".proxychains").join("proxychains.conf"),
+        "[ProxyList]\n",
+    );
+    write(
+        &root.join("etc").join("privoxy").join("config"),
+        "listen-address 127.0.0.1:8118\n",
+    );
+    write(
+        &root.join("etc").join("tinyproxy").join("tinyproxy.conf"),
+        "Port 8888\n",
+    );
+    write(
+        &home.join(".bashrc"),
+        &format!(
+            "# proxy comments are ignored\nexport http_proxy=http://other:3128\n{MANAGED_BLOCK}"
+        ),
+    );
+    // A custom ssh_config_path is scanned instead of ~/.ssh/config.
+    let ssh_config = dir.path().join("ssh_config");
+    write(
+        &ssh_config,
+        "Host a\n    ProxyCommand ssh -W %h:%p bastion\n",
+    );
+
+    let warnings = conflicts::find_conflicting_tools_in(
+        &home,
+        &root,
+        &ssh_config,
+        Some("socks5://elsewhere:1080".to_string()),
+    );
+
+    assert_eq!(
+        tools(&warnings),
+        vec![
+            "proxychains",
+            "privoxy",
+            "tinyproxy",
+            "shell profile",
+            "ALL_PROXY",
+            "ssh"
+        ]
+    );
+    assert_eq!(
+        warnings[0].description,
+        "proxychains config found — ensure it doesn't override proxyctl-rs settings"
+    );
+    assert_eq!(warnings[3].path, home.join(".bashrc"));
+    assert!(warnings[3]
+        .description
+        .contains("export http_proxy=http://other:3128"));
+    assert_eq!(warnings[5].path, ssh_config);
+    assert!(warnings[5]
+        .description
+        .starts_with("1 ProxyCommand line(s)"));
+}
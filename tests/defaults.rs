@@ -0,0 +1,75 @@
+mod support;
+
+use proxyctl_rs::defaults;
+use support::CliEnv;
+
+const PROC_NET_ROUTE: &str =
+    "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+eth0\t00000000\t0100A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0
+eth0\t0000A8C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0
+wlan0\t0000100A\t00000000\t0001\t0\t0\t600\t0000F0FF\t0\t0\t0
+docker0\t000011AC\t00000000\t0001\t0\t0\t0\t0000FFFF\t0\t0\t0
+tun0\t0000000A\t0101100A\t0003\t0\t0\t0\t000000FF\t0\t0\t0
+lo\t0000007F\t00000000\t0001\t0\t0\t0\t000000FF\t0\t0\t0
+eth1\t0000A8C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0
+";
+
+const IFCONFIG: &str = "lo0: flags=8049<UP,LOOPBACK,RUNNING,MULTICAST> mtu 16384
+\tinet 127.0.0.1 netmask 0xff000000
+\tinet6 ::1 prefixlen 128
+en0: flags=8863<UP,BROADCAST,SMART,RUNNING,SIMPLEX,MULTICAST> mtu 1500
+\tether 3c:22:fb:00:00:00
+\tinet 192.168.1.23 netmask 0xffffff00 broadcast 192.168.1.255
+utun3: flags=8051<UP,POINTOPOINT,RUNNING,MULTICAST> mtu 1380
+\tinet 10.20.30.40 --> 10.20.30.40 netmask 0xffffffff
+bridge0: flags=8863<UP,BROADCAST,SMART,RUNNING,SIMPLEX,MULTICAST> mtu 1500
+\tinet 172.16.5.9 netmask 0xfffff000 broadcast 172.16.15.255
+";
+
+#[test]
+fn proc_net_route_yields_directly_connected_subnets() {
+    assert_eq!(
+        defaults::subnets_from_proc_net_route(PROC_NET_ROUTE),
+        ["192.168.0.0/24", "10.16.0.0/12", "172.17.0.0/16"]
+    );
+}
+
+#[test]
+fn ifconfig_output_yields_interface_subnets() {
+    assert_eq!(
+        defaults::subnets_from_ifconfig(IFCONFIG),
+        ["192.168.1.0/24", "172.16.0.0/20"]
+    );
+}
+
+#[test]
+fn malformed_routes_are_skipped() {
+    let table = "Iface\tDestination\tGateway\tFlags\tRefCnt\tUse\tMetric\tMask\neth0\tzzzz\t00000000\t0001\t0\t0\t0\t00FFFFFF\neth0\t0000A8C0\t00000000\t0001\t0\t0\t0\t00FF00FF\neth0\t0000A8C0\n";
+    assert!(defaults::subnets_from_proc_net_route(table).is_empty());
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn cidr_exclusions_flag_adds_local_subnets_to_no_proxy() {
+    let env = CliEnv::new("enable_wpad_discovery = false\nno_proxy = [\"localhost\"]\n");
+    let subnets = defaults::local_subnet_entries().expect("read routing table");
+
+    let output = env.run(&[
+        "proxy",
+        "on",
+        "--proxy",
+        "http://proxy.example.com:8080",
+        "--cidr-exclusions",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+
+    let profile = env.read(&env.home().join(".bash_profile"));
+    let expected = std::iter::once("localhost".to_string())
+        .chain(subnets)
+        .collect::<Vec<_>>()
+        .join(",");
+    assert!(
+        profile.contains(&format!("export no_proxy=\"{expected}\"")),
+        "{profile}"
+    );
+}
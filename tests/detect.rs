@@ -0,0 +1,747 @@
+mod support;
+
+use proxyctl_rs::detect;
+use std::time::Duration;
+use support::{stdout, CliEnv, MockWpadServer};
+
+const PAC_BODY: &str = r#"function FindProxyForURL(url, host) {
+    return "PROXY proxy-us.example.com:8080; DIRECT";
+}"#;
+
+fn detect_env(server: &MockWpadServer) -> CliEnv {
+    let env = CliEnv::new(&format!(
+        "default_hosts_file = \"hosts.txt\"\nwpad_url = \"{}\"\n",
+        server.url
+    ));
+    env.write(&env.config_dir().join("hosts.txt"), "host1.example.com\n");
+    env.write(
+        &env.ssh_config(),
+        "Host host1.example.com\n    User alice\n",
+    );
+    env
+}
+
+fn profile(env: &CliEnv) -> String {
+    env.read(&env.home().join(".bash_profile"))
+}
+
+#[test]
+fn detect_without_set_only_prints() {
+    let server = MockWpadServer::start(PAC_BODY);
+    let env = detect_env(&server);
+
+    let output = env.run(&["detect", "--no-latency-test"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("Best regional proxy: proxy-us.example.com:8080"));
+    assert!(!profile(&env).contains("proxy-us.example.com"));
+    assert!(!env.read(&env.ssh_config()).contains("ProxyCommand"));
+}
+
+#[test]
+fn detect_set_applies_proxy_and_ssh() {
+    let server = MockWpadServer::start(PAC_BODY);
+    let env = detect_env(&server);
+
+    let output = env.run(&["detect", "--set", "--no-latency-test"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(stdout(&output).contains("Best proxy detected and set: proxy-us.example.com:8080"));
+    assert!(profile(&env).contains("export http_proxy=\"proxy-us.example.com:8080\""));
+    assert!(env
+        .read(&env.ssh_config())
+        .contains("-x proxy-us.example.com:8080"));
+}
+
+#[test]
+fn detect_set_no_ssh_leaves_ssh_config() {
+    let server = MockWpadServer::start(PAC_BODY);
+    let env = detect_env(&server);
+
+    let output = env.run(&["detect", "--set", "--no-ssh", "--no-latency-test"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(profile(&env).contains("proxy-us.example.com:8080"));
+    assert!(!env.read(&env.ssh_config()).contains("ProxyCommand"));
+}
+
+#[test]
+fn detect_set_ssh_only_leaves_profiles() {
+    let server = MockWpadServer::start(PAC_BODY);
+    let env = detect_env(&server);
+
+    let output = env.run(&["detect", "--set", "--ssh-only", "--no-latency-test"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(!profile(&env).contains("proxy-us.example.com"));
+    assert!(env
+        .read(&env.ssh_config())
+        .contains("-x proxy-us.example.com:8080"));
+}
+
+#[test]
+fn detect_flags_require_set() {
+    let server = MockWpadServer::start(PAC_BODY);
+    let env = detect_env(&server);
+
+    assert!(!env.run(&["detect", "--no-ssh"]).status.success());
+    assert!(!env
+        .run(&["detect", "--set", "--no-ssh", "--ssh-only"])
+        .status
+        .success());
+}
+
+#[test]
+fn wait_for_wpad_retries_until_the_server_answers() {
+    let server = MockWpadServer::start_with_failures(PAC_BODY, 2);
+    let env = detect_env(&server);
+
+    let output = env.run(&["proxy", "on", "--no-latency-test", "--wait-for-wpad", "5"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(server.hits(), 3);
+    let stdout = stdout(&output);
+    assert!(stdout.contains("Waiting for WPAD... (attempt 1/4, 0s elapsed)"));
+    assert!(stdout.contains("Waiting for WPAD... (attempt 3/4, 3s elapsed)"));
+    assert!(profile(&env).contains("proxy-us.example.com:8080"));
+}
+
+#[test]
+fn wait_for_wpad_falls_back_to_default_proxy_after_timeout() {
+    let server = MockWpadServer::start_with_failures(PAC_BODY, usize::MAX);
+    let env = CliEnv::new(&format!(
+        "wpad_url = \"{}\"\ndefault_proxy = \"http://fallback.example.com:8080\"\nwpad_wait_on_startup = true\nwpad_wait_timeout_seconds = 1\n",
+        server.url
+    ));
+
+    let output = env.run(&["proxy", "on", "--no-latency-test"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(server.hits(), 2);
+    assert!(stdout(&output).contains("Waiting for WPAD... (attempt 2/2, 1s elapsed)"));
+    assert!(profile(&env).contains("http://fallback.example.com:8080"));
+}
+
+#[test]
+fn no_wpad_uses_cached_candidates_until_flushed() {
+    let server = MockWpadServer::start(PAC_BODY);
+    let env = CliEnv::new(&format!(
+        "wpad_url = \"{}\"\ndefault_proxy = \"http://fallback.example.com:8080\"\n",
+        server.url
+    ));
+
+    let output = env.run(&["proxy", "on", "--no-wpad"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(profile(&env).contains("http://fallback.example.com:8080"));
+
+    let output = env.run(&["proxy", "on", "--no-latency-test"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(server.hits(), 1);
+
+    let output = env.run(&["proxy", "on", "--no-wpad"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(server.hits(), 1);
+    assert!(profile(&env).contains("proxy-us.example.com:8080"));
+
+    let output = env.run(&["proxy", "flush-wpad-cache"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(stdout(&output).contains("WPAD cache removed"));
+
+    let output = env.run(&["proxy", "on", "--no-wpad"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(server.hits(), 1);
+    assert!(profile(&env).contains("http://fallback.example.com:8080"));
+
+    // The fetched WPAD file is gone too, so WPAD is asked again.
+    let output = env.run(&["proxy", "on", "--no-latency-test"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(server.hits(), 2);
+}
+
+#[test]
+fn no_wpad_ignores_cache_for_another_wpad_url() {
+    let server = MockWpadServer::start(PAC_BODY);
+    let env = CliEnv::new(&format!(
+        "wpad_url = \"{}\"\ndefault_proxy = \"http://fallback.example.com:8080\"\n",
+        server.url
+    ));
+    let output = env.run(&["proxy", "on", "--no-latency-test"]);
+    assert!(output.status.success(), "{output:?}");
+
+    env.write(
+        &env.config_dir().join("config.toml"),
+        "wpad_url = \"http://wpad.corp.example.com/wpad.dat\"\ndefault_proxy = \"http://fallback.example.com:8080\"\n",
+    );
+    let output = env.run(&["proxy", "on", "--no-wpad"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(profile(&env).contains("http://fallback.example.com:8080"));
+    assert!(!profile(&env).contains("proxy-us.example.com"));
+}
+
+#[test]
+fn no_wpad_ignores_an_expired_cache() {
+    let server = MockWpadServer::start(PAC_BODY);
+    let env = CliEnv::new(&format!(
+        "wpad_url = \"{}\"\ndefault_proxy = \"http://fallback.example.com:8080\"\nwpad_cache_ttl_seconds = 1\n",
+        server.url
+    ));
+    let output = env.run(&["proxy", "on", "--no-latency-test"]);
+    assert!(output.status.success(), "{output:?}");
+
+    std::thread::sleep(Duration::from_millis(1100));
+    let output = env.run(&["proxy", "on", "--no-wpad"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(profile(&env).contains("http://fallback.example.com:8080"));
+}
+
+#[test]
+fn timeout_read_flag_bounds_the_wpad_request() {
+    let server = MockWpadServer::start_with_delay(PAC_BODY, Duration::from_millis(600));
+    let env = detect_env(&server);
+
+    let output = env.run(&["proxy", "on", "--no-latency-test", "--timeout-read", "200"]);
+    assert!(!output.status.success(), "{output:?}");
+    assert!(!env.home().join(".bash_profile").exists());
+
+    let output = env.run(&[
+        "proxy",
+        "on",
+        "--no-latency-test",
+        "--timeout-connect",
+        "2000",
+        "--timeout-read",
+        "3000",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(profile(&env).contains("proxy-us.example.com:8080"));
+}
+
+#[test]
+fn timeout_flags_override_configured_wpad_timeouts() {
+    let server = MockWpadServer::start_with_delay(PAC_BODY, Duration::from_millis(600));
+    let env = CliEnv::new(&format!(
+        "wpad_url = \"{}\"\ndefault_proxy = \"http://fallback.example.com:8080\"\nwpad_read_timeout_ms = 200\n",
+        server.url
+    ));
+
+    let output = env.run(&["proxy", "on", "--no-latency-test"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(profile(&env).contains("http://fallback.example.com:8080"));
+
+    let output = env.run(&["proxy", "on", "--no-latency-test", "--timeout-read", "3000"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(profile(&env).contains("proxy-us.example.com:8080"));
+}
+
+#[test]
+fn detect_validate_prints_pac_structure() {
+    let server = MockWpadServer::start(PAC_BODY);
+    let env = detect_env(&server);
+
+    let output = env.run(&["detect", "--validate", "--no-latency-test"]);
+    assert!(output.status.success(), "{output:?}");
+    let out = stdout(&output);
+    assert!(out.contains("Proxies: 1"), "{out}");
+    assert!(out.contains("Regions: us"));
+    assert!(out.contains("DIRECT fallback: yes"));
+    assert!(out.contains("Style: FindProxyForURL function"));
+    assert!(out.contains("Best regional proxy: proxy-us.example.com:8080"));
+}
+
+fn on_validate_pac(env: &CliEnv, answer: &str) -> std::process::Output {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let mut child = env
+        .command(&["on", "--validate-pac", "--no-latency-test"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("run proxyctl-rs");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(answer.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn on_validate_pac_asks_before_enabling() {
+    let server = MockWpadServer::start(PAC_BODY);
+    let env = detect_env(&server);
+
+    let output = on_validate_pac(&env, "n\n");
+    assert!(!output.status.success());
+    assert!(stdout(&output).contains("Regions: us"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Aborted"));
+    assert!(!profile(&env).contains("proxy-us.example.com"));
+
+    let output = on_validate_pac(&env, "y\n");
+    assert!(output.status.success(), "{output:?}");
+    assert!(profile(&env).contains("proxy-us.example.com:8080"));
+}
+
+#[test]
+fn detect_evaluates_pac_for_the_requested_url() {
+    let server = MockWpadServer::start(
+        r#"function FindProxyForURL(url, host) {
+    if (dnsDomainIs(host, ".eu.example.org")) {
+        return "PROXY proxy-eu.example.com:8080";
+    }
+    return "PROXY proxy-us.example.com:8080; DIRECT";
+}"#,
+    );
+    let env = detect_env(&server);
+
+    let output = env.run(&["detect", "--no-latency-test"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(stdout(&output).contains("Best regional proxy: proxy-us.example.com:8080"));
+
+    let output = env.run(&[
+        "detect",
+        "--no-latency-test",
+        "--for-url",
+        "https://shop.eu.example.org/",
+    ]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(stdout(&output).contains("Best regional proxy: proxy-eu.example.com:8080"));
+}
+
+#[test]
+fn wpad_file_is_reused_within_the_cache_ttl() {
+    let server = MockWpadServer::start(PAC_BODY);
+    let env = detect_env(&server);
+
+    for _ in 0..2 {
+        let output = env.run(&["detect", "--no-latency-test"]);
+        assert!(output.status.success(), "{output:?}");
+        assert!(stdout(&output).contains("Best regional proxy: proxy-us.example.com:8080"));
+    }
+    assert_eq!(server.hits(), 1);
+
+    let output = env.run(&["detect", "--no-latency-test", "--refresh"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(server.hits(), 2);
+
+    let output = env.run(&["proxy", "on", "--no-latency-test", "--refresh"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(server.hits(), 3);
+
+    let output = env.run(&["-v", "proxy", "on", "--no-latency-test"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(server.hits(), 3);
+    assert!(
+        stdout(&output).contains("Using WPAD file cached"),
+        "{output:?}"
+    );
+}
+
+#[test]
+fn zero_cache_ttl_fetches_every_time() {
+    let server = MockWpadServer::start(PAC_BODY);
+    let env = CliEnv::new(&format!(
+        "wpad_url = \"{}\"\nwpad_cache_ttl_seconds = 0\n",
+        server.url
+    ));
+
+    for _ in 0..2 {
+        assert!(env.run(&["detect", "--no-latency-test"]).status.success());
+    }
+    assert_eq!(server.hits(), 2);
+}
+
+// A PAC file listing a proxy nothing listens on before one that accepts
+// connections.
+fn pac_with_listener() -> (String, String, std::net::TcpListener) {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let reachable = listener.local_addr().unwrap().to_string();
+    let closed = {
+        let probe = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        probe.local_addr().unwrap().to_string()
+    };
+    let pac = format!("var proxies = \"PROXY {closed}; PROXY {reachable}\";");
+    (pac, reachable, listener)
+}
+
+#[test]
+fn detect_picks_the_first_reachable_proxy() {
+    let (pac, reachable, _listener) = pac_with_listener();
+    let server = MockWpadServer::start(&pac);
+    let env = detect_env(&server);
+
+    let output = env.run(&["detect"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(stdout(&output).contains(&format!("Best regional proxy: {reachable}")));
+}
+
+#[test]
+fn detect_all_lists_reachable_proxies_before_unreachable_ones() {
+    let (pac, reachable, _listener) = pac_with_listener();
+    let server = MockWpadServer::start(&pac);
+    let env = detect_env(&server);
+
+    let output = env.run(&["detect", "--all"]);
+    assert!(output.status.success(), "{output:?}");
+    let out = stdout(&output);
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 2, "{out}");
+    assert!(lines[0].starts_with(&format!(" 1. {reachable}  ")), "{out}");
+    assert!(lines[0].ends_with("ms"), "{out}");
+    assert!(lines[1].starts_with(" 2. 127.0.0.1:"), "{out}");
+    assert!(lines[1].ends_with("  unreachable"), "{out}");
+
+    assert!(!env
+        .run(&["detect", "--all", "--no-latency-test"])
+        .status
+        .success());
+}
+
+#[test]
+fn on_prefers_a_reachable_proxy_unless_the_latency_test_is_skipped() {
+    let (pac, reachable, _listener) = pac_with_listener();
+    let server = MockWpadServer::start(&pac);
+    let env = detect_env(&server);
+
+    let output = env.run(&["proxy", "on"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(profile(&env).contains(&format!("http_proxy=\"{reachable}\"")));
+
+    let output = env.run(&["proxy", "on", "--no-latency-test", "--refresh"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(!profile(&env).contains(&format!("http_proxy=\"{reachable}\"")));
+}
+
+#[test]
+fn on_falls_back_to_default_proxy_when_no_wpad_proxy_is_reachable() {
+    let closed = {
+        let probe = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        probe.local_addr().unwrap().to_string()
+    };
+    let server = MockWpadServer::start(&format!("PROXY {closed}"));
+    let env = CliEnv::new(&format!("wpad_url = \"{}\"\n", server.url));
+
+    let output = env.run(&["proxy", "on"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("no proxy from the WPAD response is reachable (1 tried)"));
+    assert!(!env.home().join(".bash_profile").exists());
+
+    env.write(
+        &env.config_dir().join("config.toml"),
+        &format!(
+            "wpad_url = \"{}\"\ndefault_proxy = \"http://fallback.example.com:8080\"\n",
+            server.url
+        ),
+    );
+    let output = env.run(&["proxy", "on"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(profile(&env).contains("http://fallback.example.com:8080"));
+    assert!(!profile(&env).contains(&closed));
+}
+
+#[test]
+fn detect_fails_when_no_proxy_is_reachable() {
+    let server = MockWpadServer::start(PAC_BODY);
+    let env = CliEnv::new(&format!(
+        "wpad_url = \"{}\"\ndetect_timeout_ms = 200\n",
+        server.url
+    ));
+
+    let output = env.run(&["detect"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("no proxy from the WPAD response is reachable (1 tried)"));
+}
+
+#[test]
+fn wpad_requests_are_retried_after_connection_failures() {
+    let server = MockWpadServer::start_with_dropped_connections(PAC_BODY, 2);
+    let env = CliEnv::new(&format!(
+        "wpad_url = \"{}\"\nwpad_retry_delay_ms = 10\n",
+        server.url
+    ));
+
+    let output = env.run(&["detect", "--no-latency-test"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(server.hits(), 3);
+    assert!(stdout(&output).contains("Best regional proxy: proxy-us.example.com:8080"));
+}
+
+#[test]
+fn no_retry_and_exhausted_retries_report_the_last_error() {
+    let server = MockWpadServer::start_with_dropped_connections(PAC_BODY, 3);
+    let env = CliEnv::new(&format!(
+        "wpad_url = \"{}\"\nwpad_retry_delay_ms = 10\nwpad_max_retries = 1\n",
+        server.url
+    ));
+
+    let output = env.run(&["detect", "--no-latency-test", "--no-retry"]);
+    assert!(!output.status.success());
+    assert_eq!(server.hits(), 1);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("(1 attempt)"));
+
+    let output = env.run(&["detect", "--no-latency-test"]);
+    assert!(!output.status.success());
+    assert_eq!(server.hits(), 3);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("(2 attempts)"));
+
+    let output = env.run(&["detect", "--no-latency-test"]);
+    assert!(output.status.success(), "{output:?}");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn slow_wpad_response_reports_the_timeout() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/wpad.dat", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request).await;
+                tokio::time::sleep(Duration::from_millis(1500)).await;
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{PAC_BODY}",
+                    PAC_BODY.len()
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+    let env = CliEnv::new(&format!("wpad_url = \"{url}\"\nwpad_max_retries = 0\n"));
+
+    let output = tokio::task::spawn_blocking(move || {
+        let timed_out = env.run(&["detect", "--no-latency-test", "--timeout", "300"]);
+        let completed = env.run(&["detect", "--no-latency-test", "--timeout", "5000"]);
+        (timed_out, completed)
+    })
+    .await
+    .unwrap();
+
+    let (timed_out, completed) = output;
+    assert!(!timed_out.status.success());
+    let stderr = String::from_utf8_lossy(&timed_out.stderr);
+    assert!(
+        stderr.contains("WPAD request timed out after 300ms"),
+        "{stderr}"
+    );
+    assert!(completed.status.success(), "{completed:?}");
+    assert!(stdout(&completed).contains("proxy-us.example.com:8080"));
+}
+
+#[test]
+fn no_progress_leaves_stderr_free_of_spinners() {
+    let server = MockWpadServer::start(PAC_BODY);
+    let env = detect_env(&server);
+
+    for args in [
+        &["--no-progress", "proxy", "on", "--no-latency-test"][..],
+        &["proxy", "on", "--no-latency-test"][..],
+    ] {
+        let output = env.run(args);
+        assert!(output.status.success(), "{output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!stderr.contains("Detecting proxy via WPAD"), "{stderr}");
+        assert!(profile(&env).contains("proxy-us.example.com:8080"));
+    }
+}
+
+#[test]
+fn parse_dhcp_wpad_reads_dhclient_leases_and_nmcli_output() {
+    let leases = r#"option wpad code 252 = text;
+lease {
+  interface "eth0";
+  option wpad "http://old.corp/wpad.dat";
+}
+lease {
+  interface "eth0";
+  option wpad "http://wpad.corp/wpad.dat\000";
+}
+"#;
+    assert_eq!(
+        detect::parse_dhcp_wpad(leases).as_deref(),
+        Some("http://wpad.corp/wpad.dat")
+    );
+
+    let nmcli =
+        "DHCP4.OPTION[1]:domain_name = corp\nDHCP4.OPTION[2]:wpad = http://wpad.corp/proxy.pac\n";
+    assert_eq!(
+        detect::parse_dhcp_wpad(nmcli).as_deref(),
+        Some("http://wpad.corp/proxy.pac")
+    );
+
+    assert_eq!(
+        detect::parse_dhcp_wpad("option domain-name \"corp\";\n"),
+        None
+    );
+}
+
+#[test]
+fn newest_dhcp_wpad_prefers_the_most_recent_lease_file() {
+    use std::time::SystemTime;
+
+    let dir = tempfile::tempdir().unwrap();
+    let files = [
+        ("dhclient-eth0.leases", "http://new.corp/wpad.dat", 0),
+        ("dhclient-wlan0.leases", "http://old.corp/wpad.dat", 3_600),
+        ("dhclient-usb0.leases", "", 0),
+    ];
+    let mut paths = Vec::new();
+    for (name, url, age_secs) in files {
+        let path = dir.path().join(name);
+        let content = if url.is_empty() {
+            "lease {\n  option domain-name \"corp\";\n}\n".to_string()
+        } else {
+            format!("lease {{\n  option wpad \"{url}\";\n}}\n")
+        };
+        std::fs::write(&path, content).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(age_secs))
+            .unwrap();
+        paths.push(path);
+    }
+
+    assert_eq!(
+        detect::newest_dhcp_wpad(paths.clone()).as_deref(),
+        Some("http://new.corp/wpad.dat")
+    );
+    paths.reverse();
+    assert_eq!(
+        detect::newest_dhcp_wpad(paths).as_deref(),
+        Some("http://new.corp/wpad.dat")
+    );
+    assert_eq!(detect::newest_dhcp_wpad(Vec::new()), None);
+}
+
+#[test]
+fn wpad_url_for_resolv_conf_uses_the_first_search_domain() {
+    assert_eq!(
+        detect::wpad_url_for_resolv_conf("nameserver 10.0.0.1\nsearch eu.corp.com corp.com\n")
+            .as_deref(),
+        Some("http://wpad.eu.corp.com/wpad.dat")
+    );
+    assert_eq!(
+        detect::wpad_url_for_resolv_conf("domain corp.com.\n").as_deref(),
+        Some("http://wpad.corp.com/wpad.dat")
+    );
+    assert_eq!(
+        detect::wpad_url_for_resolv_conf("nameserver 10.0.0.1\n"),
+        None
+    );
+}
+
+#[test]
+fn static_wpad_discovery_requires_wpad_url() {
+    let server = MockWpadServer::start(PAC_BODY);
+    let env = CliEnv::new(&format!(
+        "wpad_discovery_method = \"static\"\nwpad_url = \"{}\"\n",
+        server.url
+    ));
+    let output = env.run(&["detect", "--no-latency-test"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(stdout(&output).contains("proxy-us.example.com:8080"));
+
+    let env = CliEnv::new("wpad_discovery_method = \"static\"\n");
+    let output = env.run(&["detect", "--no-latency-test"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("requires wpad_url to be set"));
+}
+
+#[test]
+fn dry_runs_and_previews_leave_the_wpad_caches_alone() {
+    let server = MockWpadServer::start(PAC_BODY);
+    let env = detect_env(&server);
+
+    for args in [
+        &["--dry-run", "proxy", "on", "--no-latency-test"][..],
+        &["proxy", "on", "--no-latency-test", "--preview"][..],
+        &["proxy", "on", "--no-latency-test", "--print-command"][..],
+    ] {
+        let output = env.run(args);
+        assert!(output.status.success(), "{args:?}: {output:?}");
+    }
+    assert_eq!(server.hits(), 3);
+    let output = env.run(&["proxy", "flush-wpad-cache"]);
+    assert!(
+        stdout(&output).contains("No WPAD cache to remove"),
+        "{output:?}"
+    );
+
+    let output = env.run(&["--dry-run", "doctor", "report", "--output", "report.json"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--dry-run is not supported"));
+}
+
+#[test]
+fn timed_out_wpad_requests_are_not_retried() {
+    let server = MockWpadServer::start_with_delay(PAC_BODY, Duration::from_millis(600));
+    let env = CliEnv::new(&format!(
+        "wpad_url = \"{}\"\nwpad_retry_delay_ms = 10\n",
+        server.url
+    ));
+
+    let output = env.run(&["detect", "--no-latency-test", "--timeout", "200"]);
+    assert!(!output.status.success());
+    assert_eq!(server.hits(), 1);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("(1 attempt)"));
+}
+
+#[test]
+fn on_no_retry_and_wait_for_wpad_make_one_request_per_attempt() {
+    let server = MockWpadServer::start_with_dropped_connections(PAC_BODY, usize::MAX);
+    let env = CliEnv::new(&format!(
+        "wpad_url = \"{}\"\nwpad_retry_delay_ms = 10\ndefault_proxy = \"http://fallback.example.com:8080\"\n",
+        server.url
+    ));
+
+    let output = env.run(&["proxy", "on", "--no-latency-test", "--no-retry"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(server.hits(), 1);
+    assert!(profile(&env).contains("http://fallback.example.com:8080"));
+
+    let output = env.run(&["proxy", "on", "--no-latency-test", "--wait-for-wpad", "1"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(server.hits(), 3);
+    assert!(stdout(&output).contains("Waiting for WPAD... (attempt 2/2, 1s elapsed)"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stalled_wpad_body_reports_the_timeout() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/wpad.dat", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut request = [0u8; 1024];
+                let _ = stream.read(&mut request).await;
+                let headers = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    PAC_BODY.len()
+                );
+                let _ = stream.write_all(headers.as_bytes()).await;
+                tokio::time::sleep(Duration::from_millis(1500)).await;
+                let _ = stream.write_all(PAC_BODY.as_bytes()).await;
+            });
+        }
+    });
+    let env = CliEnv::new(&format!(
+        "wpad_url = \"{url}\"\nwpad_read_timeout_ms = 300\n"
+    ));
+
+    let output = tokio::task::spawn_blocking(move || env.run(&["detect", "--no-latency-test"]))
+        .await
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("could not read the WPAD file from"),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains("WPAD request timed out (connect timeout 5000ms, read timeout 300ms)"),
+        "{stderr}"
+    );
+}
@@ -1,4 +1,4 @@
-use proxyctl_rs::{config, doctor};
+use proxyctl_rs::{config, db, doctor, proxy};
 use std::sync::{Mutex, MutexGuard, OnceLock};
 use tempfile::TempDir;
 
@@ -60,6 +60,14 @@ impl TestEnv {
             ("XDG_DATA_HOME", data_dir.to_string_lossy().into_owned()),
             ("HOME", home_dir.to_string_lossy().into_owned()),
             ("SHELL", "/bin/false".to_string()),
+            (
+                "SSH_CONFIG",
+                home_dir
+                    .join(".ssh")
+                    .join("config")
+                    .to_string_lossy()
+                    .into_owned(),
+            ),
         ]);
 
         Self {
@@ -94,3 +102,289 @@ async fn test_doctor_reports_missing_hosts() {
     let result = doctor::run().await;
     assert!(result.is_err());
 }
+
+#[tokio::test]
+async fn test_doctor_report_contains_all_sections() {
+    let _env = TestEnv::new();
+    config::initialize_config().unwrap();
+
+    let report = doctor::generate_report().await.unwrap();
+    let json = serde_json::to_value(&report).unwrap();
+
+    for key in [
+        "timestamp",
+        "tool_version",
+        "config",
+        "env_state",
+        "ssh_status",
+        "doctor_checks",
+        "system_info",
+    ] {
+        assert!(json.get(key).is_some(), "missing key {key}");
+    }
+    assert_eq!(json["tool_version"], env!("CARGO_PKG_VERSION"));
+    let names: Vec<&str> = json["doctor_checks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|check| check["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        &names[..9],
+        [
+            "Config",
+            "Database",
+            "Proxy",
+            "Profiles",
+            "Profile",
+            "SSH permissions",
+            "Credentials",
+            "No-proxy coverage",
+            "Git"
+        ]
+    );
+    // Env and conflict warnings depend on the host system, but each reports at least one entry.
+    assert!(names[9..]
+        .iter()
+        .all(|name| name.starts_with("Env") || name.starts_with("Conflicts")));
+    assert!(names.iter().any(|name| name.starts_with("Env")));
+    assert!(names.iter().any(|name| name.starts_with("Conflicts")));
+}
+
+#[tokio::test]
+async fn test_doctor_report_writes_json_file() {
+    let env = TestEnv::new();
+    config::initialize_config().unwrap();
+
+    let output = env._dir.path().join("report.json");
+    doctor::write_report(Some(&output)).await.unwrap();
+
+    let contents = std::fs::read_to_string(&output).unwrap();
+    let parsed: serde_json::Value = serde_json::from_str(&contents).unwrap();
+    assert_eq!(parsed["system_info"]["os"], std::env::consts::OS);
+}
+
+#[test]
+fn test_no_proxy_coverage_reports_missing_local_entries() {
+    let warnings = doctor::check_no_proxy_coverage_with("localhost,127.0.0.1", &[]);
+    assert_eq!(
+        warnings,
+        vec![doctor::NoproxyCoverageWarning::MissingLocal(
+            "::1".to_string()
+        )]
+    );
+
+    let warnings = doctor::check_no_proxy_coverage_with("example.com", &[]);
+    assert_eq!(warnings.len(), 3);
+
+    assert!(doctor::check_no_proxy_coverage_with("localhost,127.0.0.0/8,[::1]", &[]).is_empty());
+    assert!(doctor::check_no_proxy_coverage_with("*", &[]).is_empty());
+}
+
+#[test]
+fn test_no_proxy_coverage_flags_invalid_entries() {
+    let warnings = doctor::check_no_proxy_coverage_with(
+        "localhost,127.0.0.1,::1,10.0.0.0/255.0.0.0,192.168.*,172.16.0.0,10.1.,10.0.0.0/33,http://intranet,10.0.0.0/8,192.168.1.5",
+        &[],
+    );
+    let invalid: Vec<(&str, String)> = warnings
+        .iter()
+        .map(|warning| match warning {
+            doctor::NoproxyCoverageWarning::InvalidEntry { entry, reason } => {
+                (entry.as_str(), reason.clone())
+            }
+            other => panic!("unexpected warning {other:?}"),
+        })
+        .collect();
+    let entries: Vec<&str> = invalid.iter().map(|(entry, _)| *entry).collect();
+    assert_eq!(
+        entries,
+        [
+            "10.0.0.0/255.0.0.0",
+            "192.168.*",
+            "172.16.0.0",
+            "10.1.",
+            "10.0.0.0/33",
+            "http://intranet"
+        ]
+    );
+    assert!(invalid[0].1.contains("use 10.0.0.0/8"));
+}
+
+#[test]
+fn test_no_proxy_coverage_cross_references_hosts_file() {
+    let _env = TestEnv::new();
+    config::initialize_config().unwrap();
+    std::fs::write(
+        config::get_hosts_file_path().unwrap(),
+        "*.corp.example.com\ndb1.corp.example.com proxy=other:8080\nbuild.internal.example.net\npublic.example.org\n",
+    )
+    .unwrap();
+
+    let warnings = doctor::check_no_proxy_coverage("localhost,127.0.0.1,::1");
+    assert_eq!(
+        warnings,
+        vec![
+            doctor::NoproxyCoverageWarning::MissingDomain {
+                domain: "corp.example.com".to_string(),
+                host: "*.corp.example.com".to_string(),
+            },
+            doctor::NoproxyCoverageWarning::MissingDomain {
+                domain: "internal.example.net".to_string(),
+                host: "build.internal.example.net".to_string(),
+            },
+        ]
+    );
+
+    let warnings =
+        doctor::check_no_proxy_coverage("localhost,127.0.0.1,::1,.corp.example.com,*.example.net");
+    assert!(warnings.is_empty(), "{warnings:?}");
+}
+
+#[tokio::test]
+async fn test_doctor_fix_recreates_config_and_hosts_files() {
+    let _env = TestEnv::new();
+    config::initialize_config().unwrap();
+    let config_path = config::get_config_dir().unwrap().join("config.toml");
+    let hosts_path = config::get_hosts_file_path().unwrap();
+    std::fs::remove_file(&config_path).unwrap();
+    std::fs::remove_file(&hosts_path).unwrap();
+
+    doctor::run_with_fixes().await.unwrap();
+
+    assert!(config_path.exists());
+    assert!(hosts_path.exists());
+    assert!(doctor::collect_fixes()
+        .await
+        .iter()
+        .all(|fix| !fix.can_fix()));
+}
+
+#[tokio::test]
+async fn test_doctor_fix_recreates_missing_tables() {
+    let _env = TestEnv::new();
+    config::initialize_config().unwrap();
+    let db_path = db::get_db_path();
+    db::init_db(&db_path).await.unwrap();
+    turso::Builder::new_local(&db_path)
+        .build()
+        .await
+        .unwrap()
+        .connect()
+        .unwrap()
+        .execute("DROP TABLE env_state", ())
+        .await
+        .unwrap();
+    assert!(db::verify_schema(&db_path).await.is_err());
+
+    doctor::run_with_fixes().await.unwrap();
+
+    db::verify_schema(&db_path).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_doctor_fix_restores_profile_block() {
+    let _env = TestEnv::new();
+    let _shell = EnvGuard::set([("SHELL", "/bin/bash")]);
+    config::initialize_config().unwrap();
+    let db_path = db::get_db_path();
+    db::init_db(&db_path).await.unwrap();
+    let state = db::EnvState {
+        http_proxy: Some("http://proxy.example.com:8080".to_string()),
+        https_proxy: Some("http://proxy.example.com:8080".to_string()),
+        ..Default::default()
+    };
+    db::save_env_state(&db_path, &state).await.unwrap();
+    assert!(!proxy::profiles_have_managed_block().unwrap());
+
+    let _ = doctor::run_with_fixes().await;
+
+    assert!(proxy::profiles_have_managed_block().unwrap());
+    let profile = std::fs::read_to_string(dirs::home_dir().unwrap().join(".bash_profile")).unwrap();
+    assert!(
+        profile.contains("http://proxy.example.com:8080"),
+        "{profile}"
+    );
+}
+
+async fn save_proxy(url: &str) {
+    let db_path = db::get_db_path();
+    db::init_db(&db_path).await.unwrap();
+    let state = db::EnvState {
+        http_proxy: Some(url.to_string()),
+        https_proxy: Some(url.to_string()),
+        ..Default::default()
+    };
+    db::save_env_state(&db_path, &state).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_proxy_reachable_without_proxy() {
+    let _env = TestEnv::new();
+    config::initialize_config().unwrap();
+    db::init_db(&db::get_db_path()).await.unwrap();
+
+    assert_eq!(
+        doctor::check_proxy_reachable().await.unwrap(),
+        "no proxy configured"
+    );
+}
+
+#[tokio::test]
+async fn test_proxy_reachable_connects_to_stored_proxy() {
+    let _env = TestEnv::new();
+    config::initialize_config().unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    save_proxy(&format!("http://{address}")).await;
+
+    assert_eq!(
+        doctor::check_proxy_reachable().await.unwrap(),
+        format!("reachable at {address}")
+    );
+
+    drop(listener);
+    let err = doctor::check_proxy_reachable().await.unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!("cannot connect to {address} (proxy may be offline)")
+    );
+}
+
+#[tokio::test]
+async fn test_git_proxy_check_warns_when_git_disagrees() {
+    let _env = TestEnv::new();
+    let gitconfig = dirs::home_dir().unwrap().join(".gitconfig");
+    let _git = EnvGuard::set([(
+        "GIT_CONFIG_GLOBAL",
+        gitconfig.to_string_lossy().into_owned(),
+    )]);
+    config::initialize_config().unwrap();
+
+    let check = doctor::check_git_proxy().await;
+    assert_eq!(check.status, doctor::CheckStatus::Ok, "{}", check.message);
+
+    std::fs::write(&gitconfig, "[http]\n\tproxy = http://old.proxy:8080\n").unwrap();
+    let check = doctor::check_git_proxy().await;
+    assert_eq!(check.status, doctor::CheckStatus::Warn);
+    assert!(
+        check.message.contains("no proxy is enabled"),
+        "{}",
+        check.message
+    );
+
+    save_proxy("http://proxy.corp:8080").await;
+    let check = doctor::check_git_proxy().await;
+    assert_eq!(check.status, doctor::CheckStatus::Warn);
+    assert!(
+        check
+            .message
+            .contains("git http.proxy is http://old.proxy:8080 but the stored proxy is http://proxy.corp:8080"),
+        "{}",
+        check.message
+    );
+
+    std::fs::write(&gitconfig, "[http]\n\tproxy = http://proxy.corp:8080\n").unwrap();
+    let check = doctor::check_git_proxy().await;
+    assert_eq!(check.status, doctor::CheckStatus::Ok, "{}", check.message);
+}
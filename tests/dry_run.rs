@@ -0,0 +1,43 @@
+use proxyctl_rs::dry_run::{DiffLine, DiffWriter};
+
+#[test]
+fn diff_records_only_changed_lines() {
+    let mut diff = DiffWriter::new();
+    diff.diff(
+        "~/.ssh/config",
+        "Host a\n    User alice\nHost b\n    User bob\n",
+        "Host a\n    ProxyCommand nc\n    User alice\nHost b\n    User robert\n",
+    );
+
+    assert_eq!(
+        diff.lines("~/.ssh/config"),
+        [
+            DiffLine::Added("    ProxyCommand nc".to_string()),
+            DiffLine::Removed("    User bob".to_string()),
+            DiffLine::Added("    User robert".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn unchanged_content_records_nothing() {
+    let mut diff = DiffWriter::new();
+    diff.diff("profile", "export A=1\n", "export A=1\n");
+
+    assert!(diff.is_empty());
+    assert_eq!(diff.render(), "No changes");
+}
+
+#[test]
+fn render_groups_lines_by_target() {
+    colored::control::set_override(false);
+    let mut diff = DiffWriter::new();
+    diff.add("environment", "http_proxy=http://proxy:8080");
+    diff.diff("~/.bashrc", "", "export http_proxy=\"http://proxy:8080\"\n");
+    diff.remove("environment", "no_proxy=localhost");
+
+    assert_eq!(
+        diff.render(),
+        "environment\n+http_proxy=http://proxy:8080\n-no_proxy=localhost\n~/.bashrc\n+export http_proxy=\"http://proxy:8080\""
+    );
+}
@@ -0,0 +1,5 @@
+if status is-interactive
+    set -g fish_greeting
+end
+
+fish_add_path ~/.local/bin
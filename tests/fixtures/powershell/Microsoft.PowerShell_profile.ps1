@@ -0,0 +1,7 @@
+Import-Module posh-git
+
+function Prompt {
+    "PS $($executionContext.SessionState.Path.CurrentLocation)> "
+}
+
+Set-PSReadLineOption -EditMode Emacs
@@ -0,0 +1,42 @@
+mod support;
+
+use support::{stdout, CliEnv};
+
+const INVALID_PROXY: &str = "ftp://vpn-proxy.internal:21";
+
+fn profile(env: &CliEnv) -> String {
+    env.read(&env.home().join(".bash_profile"))
+}
+
+#[test]
+fn invalid_proxy_is_rejected_without_force() {
+    let env = CliEnv::new("enable_wpad_discovery = false\n");
+
+    let output = env.run(&["proxy", "on", "--proxy", INVALID_PROXY]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unsupported scheme"));
+    assert!(!profile(&env).contains(INVALID_PROXY));
+}
+
+#[test]
+fn force_with_yes_applies_invalid_proxy() {
+    let env = CliEnv::new("enable_wpad_discovery = false\n");
+
+    let output = env.run(&["proxy", "on", "--proxy", INVALID_PROXY, "--force", "--yes"]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(!stdout(&output).contains("Continue?"));
+    assert!(profile(&env).contains(INVALID_PROXY));
+}
+
+#[test]
+fn force_without_confirmation_aborts() {
+    let env = CliEnv::new("enable_wpad_discovery = false\n");
+
+    // stdin is empty, so the prompt reads no answer and defaults to "no".
+    let output = env.run(&["proxy", "on", "--proxy", INVALID_PROXY, "--force"]);
+    assert!(!output.status.success());
+    assert!(
+        stdout(&output).contains("This proxy URL may be invalid or unreachable. Continue? [y/N]")
+    );
+    assert!(!profile(&env).contains(INVALID_PROXY));
+}
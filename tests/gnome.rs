@@ -0,0 +1,166 @@
+mod support;
+
+use anyhow::{anyhow, Result};
+use proxyctl_rs::db::EnvState;
+use proxyctl_rs::doctor::{check_gnome_proxy_with, CheckStatus};
+use proxyctl_rs::gnome::{self, GSettingsBackend};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use support::{stdout, CliEnv};
+
+#[derive(Default)]
+struct FakeGSettings {
+    unavailable: bool,
+    values: RefCell<BTreeMap<(String, String), String>>,
+    writes: RefCell<Vec<String>>,
+}
+
+impl FakeGSettings {
+    fn with(values: &[(&str, &str, &str)]) -> Self {
+        let fake = Self::default();
+        for (schema, key, value) in values {
+            fake.values
+                .borrow_mut()
+                .insert((schema.to_string(), key.to_string()), value.to_string());
+        }
+        fake
+    }
+}
+
+impl GSettingsBackend for FakeGSettings {
+    fn is_available(&self) -> bool {
+        !self.unavailable
+    }
+
+    fn get(&self, schema: &str, key: &str) -> Result<String> {
+        self.values
+            .borrow()
+            .get(&(schema.to_string(), key.to_string()))
+            .cloned()
+            .ok_or_else(|| anyhow!("no key {schema} {key}"))
+    }
+
+    fn set(&self, schema: &str, key: &str, value: &str) -> Result<()> {
+        self.writes
+            .borrow_mut()
+            .push(format!("{schema} {key} {value}"));
+        self.values
+            .borrow_mut()
+            .insert((schema.to_string(), key.to_string()), value.to_string());
+        Ok(())
+    }
+}
+
+fn state_with_proxy(proxy: &str) -> EnvState {
+    EnvState {
+        http_proxy: Some(proxy.to_string()),
+        https_proxy: Some(proxy.to_string()),
+        ..EnvState::default()
+    }
+}
+
+#[test]
+fn set_proxy_writes_http_and_https_then_switches_to_manual() {
+    let fake = FakeGSettings::default();
+    assert!(gnome::set_proxy_with(&fake, "proxy.corp", 3128).unwrap());
+    assert_eq!(
+        *fake.writes.borrow(),
+        vec![
+            "org.gnome.system.proxy.http host 'proxy.corp'",
+            "org.gnome.system.proxy.http port 3128",
+            "org.gnome.system.proxy.https host 'proxy.corp'",
+            "org.gnome.system.proxy.https port 3128",
+            "org.gnome.system.proxy mode 'manual'",
+        ]
+    );
+    assert_eq!(
+        gnome::current_proxy_with(&fake).unwrap(),
+        Some(("proxy.corp".to_string(), 3128))
+    );
+
+    assert!(gnome::disable_proxy_with(&fake).unwrap());
+    assert_eq!(
+        fake.writes.borrow().last().unwrap(),
+        "org.gnome.system.proxy mode 'none'"
+    );
+    assert_eq!(gnome::current_proxy_with(&fake).unwrap(), None);
+}
+
+#[test]
+fn missing_gsettings_is_a_no_op() {
+    let fake = FakeGSettings {
+        unavailable: true,
+        ..FakeGSettings::default()
+    };
+    assert!(!gnome::set_proxy_with(&fake, "proxy.corp", 3128).unwrap());
+    assert!(!gnome::disable_proxy_with(&fake).unwrap());
+    assert!(fake.writes.borrow().is_empty());
+
+    let check = check_gnome_proxy_with(&fake, &EnvState::default());
+    assert_eq!(check.status, CheckStatus::Warn);
+    assert!(check.message.contains("gsettings is not installed"));
+}
+
+#[test]
+fn doctor_compares_gnome_proxy_with_stored_proxy() {
+    let fake = FakeGSettings::with(&[
+        ("org.gnome.system.proxy", "mode", "'manual'"),
+        ("org.gnome.system.proxy.http", "host", "'proxy.corp'"),
+        ("org.gnome.system.proxy.http", "port", "3128"),
+    ]);
+
+    let check = check_gnome_proxy_with(&fake, &state_with_proxy("http://proxy.corp:3128"));
+    assert_eq!(check.status, CheckStatus::Ok, "{}", check.message);
+
+    let check = check_gnome_proxy_with(&fake, &state_with_proxy("http://other.corp:8080"));
+    assert_eq!(check.status, CheckStatus::Warn);
+    assert!(check
+        .message
+        .contains("GNOME uses proxy.corp:3128 but the stored proxy is other.corp:8080"));
+
+    let check = check_gnome_proxy_with(&fake, &EnvState::default());
+    assert_eq!(check.status, CheckStatus::Warn);
+    assert!(check.message.contains("no proxy is enabled"));
+
+    gnome::disable_proxy_with(&fake).unwrap();
+    let check = check_gnome_proxy_with(&fake, &EnvState::default());
+    assert_eq!(check.status, CheckStatus::Ok, "{}", check.message);
+}
+
+#[cfg(unix)]
+#[test]
+fn failing_gsettings_only_warns_after_the_proxy_is_recorded() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let env = CliEnv::new("enable_wpad_discovery = false\nmanage_gnome_proxy = true\n");
+    let bin = env.dir.path().join("bin");
+    let gsettings = bin.join("gsettings");
+    env.write(
+        &gsettings,
+        "#!/bin/sh\n[ \"$1\" = --version ] && exit 0\necho 'No such schema' >&2\nexit 1\n",
+    );
+    std::fs::set_permissions(&gsettings, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!(
+        "{}:{}",
+        bin.display(),
+        std::env::var("PATH").unwrap_or_default()
+    );
+
+    let output = env
+        .command(&["proxy", "on", "--proxy", "http://proxy.corp:8080"])
+        .env("PATH", &path)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Warning: gsettings set"));
+    assert!(stdout(&env.run(&["status", "proxy"])).contains("http://proxy.corp:8080"));
+
+    let output = env
+        .command(&["proxy", "off"])
+        .env("PATH", &path)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Warning: gsettings set"));
+    assert!(!stdout(&env.run(&["status", "proxy"])).contains("http://proxy.corp:8080"));
+}
@@ -0,0 +1,94 @@
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use proxyctl_rs::keychain::{self, CredentialStore, ProxyCredentials};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+
+#[derive(Default)]
+struct CountingStore {
+    entries: RefCell<HashMap<String, ProxyCredentials>>,
+    reads: Cell<usize>,
+    writes: Cell<usize>,
+}
+
+impl CredentialStore for CountingStore {
+    fn load(&self, profile: &str) -> Result<Option<ProxyCredentials>> {
+        self.reads.set(self.reads.get() + 1);
+        Ok(self.entries.borrow().get(profile).cloned())
+    }
+
+    fn store(&self, profile: &str, credentials: &ProxyCredentials) -> Result<()> {
+        self.writes.set(self.writes.get() + 1);
+        self.entries
+            .borrow_mut()
+            .insert(profile.to_string(), credentials.clone());
+        Ok(())
+    }
+}
+
+fn credentials(username: &str, expires_at: Option<String>) -> ProxyCredentials {
+    ProxyCredentials {
+        username: username.to_string(),
+        password: "s3cret".to_string(),
+        expires_at,
+    }
+}
+
+#[test]
+fn fresh_credentials_are_read_each_time_without_prompting() {
+    let store = CountingStore::default();
+    let tomorrow = (Utc::now() + Duration::days(1)).to_rfc3339();
+    store
+        .store("corporate", &credentials("alice", Some(tomorrow)))
+        .unwrap();
+
+    for _ in 0..2 {
+        let loaded = keychain::load_proxy_credentials(&store, "corporate", |_| {
+            panic!("valid credentials must not prompt")
+        })
+        .unwrap();
+        assert_eq!(loaded.username, "alice");
+    }
+    assert_eq!(store.reads.get(), 2);
+    assert_eq!(store.writes.get(), 1);
+}
+
+#[test]
+fn expired_credentials_are_prompted_for_and_stored() {
+    let store = CountingStore::default();
+    let yesterday = (Utc::now() - Duration::days(1)).to_rfc3339();
+    store
+        .store("corporate", &credentials("alice", Some(yesterday)))
+        .unwrap();
+
+    let loaded = keychain::load_proxy_credentials(&store, "corporate", |profile| {
+        assert_eq!(profile, "corporate");
+        Ok(credentials("bob", None))
+    })
+    .unwrap();
+
+    assert_eq!(loaded.username, "bob");
+    assert_eq!(store.reads.get(), 1);
+    assert_eq!(store.writes.get(), 2);
+    assert_eq!(store.entries.borrow()["corporate"].username, "bob");
+}
+
+#[test]
+fn missing_credentials_are_prompted_for() {
+    let store = CountingStore::default();
+
+    let loaded =
+        keychain::load_proxy_credentials(&store, "corporate", |_| Ok(credentials("carol", None)))
+            .unwrap();
+
+    assert_eq!(loaded.username, "carol");
+    assert_eq!(store.writes.get(), 1);
+}
+
+#[test]
+fn expiry_checks_handle_missing_and_invalid_timestamps() {
+    let now = Utc::now();
+    assert!(!credentials("alice", None).is_expired(now));
+    assert!(credentials("alice", Some("not a date".to_string())).is_expired(now));
+    assert!(!credentials("alice", Some((now + Duration::hours(1)).to_rfc3339())).is_expired(now));
+}
@@ -0,0 +1,59 @@
+mod support;
+
+use support::{stdout, CliEnv, MockWpadServer};
+
+const TEST_URL: &str = "http://reachable.example.com/";
+
+fn profile(env: &CliEnv) -> String {
+    env.read(&env.home().join(".bash_profile"))
+}
+
+#[test]
+fn test_url_passes_through_working_proxy() {
+    // The mock answers every request with 200, standing in for a forwarding proxy.
+    let proxy_server = MockWpadServer::start("");
+    let proxy_url = proxy_server.url.trim_end_matches("/wpad.dat").to_string();
+    let env = CliEnv::new("enable_wpad_discovery = false\n");
+
+    let output = env.run(&["proxy", "on", "--proxy", &proxy_url, "--test-url", TEST_URL]);
+    assert!(output.status.success(), "{output:?}");
+    assert!(stdout(&output).contains("Proxy enabled"));
+    assert_eq!(proxy_server.hits(), 1);
+    assert!(profile(&env).contains(&proxy_url));
+}
+
+#[test]
+fn failed_test_url_leaves_state_untouched() {
+    let env = CliEnv::new("enable_wpad_discovery = false\n");
+    let unreachable = "http://127.0.0.1:9";
+
+    let output = env.run(&[
+        "proxy",
+        "on",
+        "--proxy",
+        unreachable,
+        "--test-url",
+        TEST_URL,
+    ]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("pre-flight request"));
+    assert!(!profile(&env).contains(unreachable));
+    assert!(!stdout(&env.run(&["proxy", "history"])).contains(unreachable));
+}
+
+#[test]
+fn pre_flight_test_url_from_config_is_used() {
+    let proxy_server = MockWpadServer::start_with_failures("", 1);
+    let proxy_url = proxy_server.url.trim_end_matches("/wpad.dat").to_string();
+    let env = CliEnv::new(&format!(
+        "enable_wpad_discovery = false\npre_flight_test_url = \"{TEST_URL}\"\n"
+    ));
+
+    let output = env.run(&["proxy", "on", "--proxy", &proxy_url]);
+    assert!(
+        !output.status.success(),
+        "503 from the proxy must fail the pre-flight"
+    );
+    assert_eq!(proxy_server.hits(), 1);
+    assert!(!profile(&env).contains(&proxy_url));
+}
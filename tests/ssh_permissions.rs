@@ -0,0 +1,116 @@
+#![cfg(unix)]
+
+mod support;
+
+use proxyctl_rs::doctor;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use support::{stdout, CliEnv};
+
+fn set_mode(path: &Path, mode: u32) {
+    fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+}
+
+fn mode(path: &Path) -> u32 {
+    fs::metadata(path).unwrap().permissions().mode() & 0o777
+}
+
+fn loosen(env: &CliEnv) -> (std::path::PathBuf, std::path::PathBuf) {
+    let ssh_dir = env.home().join(".ssh");
+    let backup = ssh_dir.join("config.proxyctl-rs.bak");
+    env.write(&env.ssh_config(), "Host example\n");
+    env.write(&backup, "Host example\n");
+    set_mode(&ssh_dir, 0o755);
+    set_mode(&env.ssh_config(), 0o644);
+    set_mode(&backup, 0o664);
+    (ssh_dir, backup)
+}
+
+#[test]
+fn fix_ssh_permissions_with_yes_restricts_dir_config_and_backups() {
+    let env = CliEnv::new("");
+    let (ssh_dir, backup) = loosen(&env);
+    let known_hosts = ssh_dir.join("known_hosts");
+    env.write(&known_hosts, "");
+    set_mode(&known_hosts, 0o644);
+
+    let output = env.run(&["doctor", "fix-ssh-permissions", "--yes"]);
+    assert!(output.status.success(), "{output:?}");
+    let out = stdout(&output);
+    assert!(out.contains(&format!("Would run: chmod 700 {}", ssh_dir.display())));
+    assert!(out.contains("SSH permissions: OK"), "{out}");
+
+    assert_eq!(mode(&ssh_dir), 0o700);
+    assert_eq!(mode(&env.ssh_config()), 0o600);
+    assert_eq!(mode(&backup), 0o600);
+    assert_eq!(mode(&known_hosts), 0o644);
+
+    let output = env.run(&["doctor", "fix-ssh-permissions", "--yes"]);
+    assert!(output.status.success());
+    assert!(stdout(&output).contains("nothing to fix"));
+}
+
+#[test]
+fn fix_ssh_permissions_without_confirmation_changes_nothing() {
+    let env = CliEnv::new("");
+    let (ssh_dir, backup) = loosen(&env);
+
+    let output = env.run(&["doctor", "fix-ssh-permissions"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Aborted"));
+    assert!(stdout(&output).contains(&format!(
+        "Would run: chmod 600 {} (was 664)",
+        backup.display()
+    )));
+
+    assert_eq!(mode(&ssh_dir), 0o755);
+    assert_eq!(mode(&env.ssh_config()), 0o644);
+    assert_eq!(mode(&backup), 0o664);
+}
+
+#[test]
+fn check_ssh_permissions_warns_until_fixed() {
+    let env = CliEnv::new("");
+    let (ssh_dir, _) = loosen(&env);
+    set_mode(&ssh_dir, 0o700);
+
+    let check = doctor::check_ssh_permissions(&ssh_dir, &env.ssh_config());
+    assert_eq!(check.status, doctor::CheckStatus::Warn);
+    assert!(check.message.contains("config (644)"), "{}", check.message);
+    assert!(!check.message.contains(&format!("{} (", ssh_dir.display())));
+
+    let planned = doctor::plan_ssh_permission_fixes(&ssh_dir, &env.ssh_config()).unwrap();
+    let report = doctor::fix_ssh_permissions(&ssh_dir, &env.ssh_config()).unwrap();
+    assert_eq!(report, planned);
+    assert_eq!(report.actions.len(), 2);
+    assert_eq!(
+        doctor::check_ssh_permissions(&ssh_dir, &env.ssh_config()).status,
+        doctor::CheckStatus::Ok
+    );
+}
+
+#[test]
+fn check_ssh_permissions_fails_when_config_or_dir_is_writable() {
+    let env = CliEnv::new("");
+    let (ssh_dir, backup) = loosen(&env);
+    set_mode(&ssh_dir, 0o700);
+    set_mode(&env.ssh_config(), 0o664);
+
+    let check = doctor::check_ssh_permissions(&ssh_dir, &env.ssh_config());
+    assert_eq!(check.status, doctor::CheckStatus::Err);
+    assert!(check.message.contains("config (664)"), "{}", check.message);
+    assert!(!check.message.contains(&backup.display().to_string()));
+
+    set_mode(&env.ssh_config(), 0o600);
+    set_mode(&ssh_dir, 0o775);
+    let check = doctor::check_ssh_permissions(&ssh_dir, &env.ssh_config());
+    assert_eq!(check.status, doctor::CheckStatus::Err);
+    assert!(check
+        .message
+        .contains(&format!("{} (775)", ssh_dir.display())));
+
+    set_mode(&ssh_dir, 0o700);
+    let output = env.run(&["doctor", "run"]);
+    assert!(stdout(&output).contains("SSH permissions: WARN"));
+}
@@ -0,0 +1,143 @@
+#![allow(dead_code)]
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Minimal HTTP server answering every request with `body`, after closing
+/// the first `dropped` connections unanswered, failing the next `failures`
+/// requests with a 503 and waiting `delay` before each reply.
+pub struct MockWpadServer {
+    pub url: String,
+    hits: Arc<AtomicUsize>,
+}
+
+impl MockWpadServer {
+    pub fn start(body: &str) -> Self {
+        Self::start_with_failures(body, 0)
+    }
+
+    pub fn start_with_failures(body: &str, failures: usize) -> Self {
+        Self::spawn(body, 0, failures, Duration::ZERO)
+    }
+
+    pub fn start_with_dropped_connections(body: &str, dropped: usize) -> Self {
+        Self::spawn(body, dropped, 0, Duration::ZERO)
+    }
+
+    pub fn start_with_delay(body: &str, delay: Duration) -> Self {
+        Self::spawn(body, 0, 0, delay)
+    }
+
+    fn spawn(body: &str, dropped: usize, failures: usize, delay: Duration) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock wpad server");
+        let url = format!("http://{}/wpad.dat", listener.local_addr().unwrap());
+        let hits = Arc::new(AtomicUsize::new(0));
+        let body = body.to_string();
+        let counter = Arc::clone(&hits);
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let mut buffer = [0u8; 4096];
+                let _ = stream.read(&mut buffer);
+                let attempt = counter.fetch_add(1, Ordering::SeqCst);
+                if attempt < dropped {
+                    continue;
+                }
+                thread::sleep(delay);
+                let response = if attempt < dropped + failures {
+                    "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                        .to_string()
+                } else {
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/x-ns-proxy-autoconfig\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                };
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+
+        Self { url, hits }
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::SeqCst)
+    }
+}
+
+/// Isolated HOME/XDG layout for running the compiled binary.
+pub struct CliEnv {
+    pub dir: tempfile::TempDir,
+}
+
+impl CliEnv {
+    pub fn new(config_toml: &str) -> Self {
+        let dir = tempfile::tempdir().expect("cli temp dir");
+        let env = Self { dir };
+        std::fs::create_dir_all(env.config_dir()).unwrap();
+        std::fs::create_dir_all(env.home().join(".ssh")).unwrap();
+        std::fs::create_dir_all(env.dir.path().join("data")).unwrap();
+        std::fs::write(env.config_dir().join("config.toml"), config_toml).unwrap();
+        env
+    }
+
+    pub fn home(&self) -> PathBuf {
+        self.dir.path().join("home")
+    }
+
+    pub fn config_dir(&self) -> PathBuf {
+        self.dir.path().join("config").join("proxyctl-rs")
+    }
+
+    pub fn ssh_config(&self) -> PathBuf {
+        self.home().join(".ssh").join("config")
+    }
+
+    pub fn write(&self, path: &Path, contents: &str) {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).unwrap();
+        }
+        std::fs::write(path, contents).unwrap();
+    }
+
+    pub fn read(&self, path: &Path) -> String {
+        std::fs::read_to_string(path).unwrap_or_default()
+    }
+
+    pub fn run(&self, args: &[&str]) -> Output {
+        self.command(args).output().expect("run proxyctl-rs")
+    }
+
+    /// Command for the binary in this environment, for callers that need to
+    /// add variables before running it.
+    pub fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_proxyctl-rs"));
+        command
+            .args(args)
+            .current_dir(self.dir.path())
+            .env("HOME", self.home())
+            .env("XDG_CONFIG_HOME", self.dir.path().join("config"))
+            .env("XDG_DATA_HOME", self.dir.path().join("data"))
+            .env("SHELL", "/bin/bash")
+            .env("NO_COLOR", "1")
+            .env_remove("SSH_CONFIG");
+        for (key, _) in std::env::vars() {
+            if key.to_ascii_lowercase().contains("proxy") {
+                command.env_remove(key);
+            }
+        }
+        command
+    }
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
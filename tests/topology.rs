@@ -0,0 +1,218 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+/// Modules each source file may import from the crate (`main` imports the
+/// library as `proxyctl_rs`). Adding an edge here is a deliberate decision;
+/// the graph must stay acyclic.
+const ALLOWED_IMPORTS: [(&str, &[&str]); 15] = [
+    (
+        "main",
+        &[
+            "completions",
+            "config",
+            "db",
+            "detect",
+            "doctor",
+            "dry_run",
+            "export",
+            "keychain",
+            "output",
+            "proxy",
+        ],
+    ),
+    (
+        "proxy",
+        &[
+            "config",
+            "db",
+            "defaults",
+            "detect",
+            "dry_run",
+            "gnome",
+            "output",
+            "systemctl",
+        ],
+    ),
+    ("detect", &["config", "db", "defaults", "output"]),
+    ("db", &["config"]),
+    (
+        "doctor",
+        &[
+            "config",
+            "conflicts",
+            "db",
+            "defaults",
+            "export",
+            "gnome",
+            "output",
+            "proxy",
+            "systemctl",
+        ],
+    ),
+    ("conflicts", &["config", "proxy"]),
+    ("config", &["defaults", "dry_run", "output"]),
+    ("defaults", &[]),
+    ("dry_run", &[]),
+    ("export", &["db", "dry_run", "proxy"]),
+    ("output", &[]),
+    ("keychain", &[]),
+    ("systemctl", &[]),
+    ("gnome", &[]),
+    ("completions", &[]),
+];
+
+/// Helper types that are implementation details of their module and must not
+/// become part of the public API.
+const INTERNAL_TYPES: [(&str, &str); 6] = [
+    ("doctor", "ValueSnapshot"),
+    ("doctor", "PendingComment"),
+    ("doctor", "RenderedLine"),
+    ("doctor", "ValueKind"),
+    ("doctor", "Palette"),
+    ("doctor", "DoctorSummary"),
+];
+
+fn source_dir() -> &'static Path {
+    Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/src"))
+}
+
+fn read_module(module: &str) -> String {
+    fs::read_to_string(source_dir().join(format!("{module}.rs"))).expect("read module source")
+}
+
+// Crate modules named by `use crate::...` (or `use proxyctl_rs::...` in main),
+// including grouped imports like `use crate::{config, db};`.
+fn imported_modules(source: &str) -> BTreeSet<String> {
+    let mut modules = BTreeSet::new();
+    for line in source.lines() {
+        let line = line.trim();
+        let Some(path) = ["use crate::", "use proxyctl_rs::"]
+            .iter()
+            .find_map(|prefix| line.strip_prefix(prefix))
+        else {
+            continue;
+        };
+        let path = path.trim_end_matches(';');
+        let entries: Vec<&str> = match path.strip_prefix('{') {
+            Some(group) => group.trim_end_matches('}').split(',').collect(),
+            None => vec![path],
+        };
+        for entry in entries {
+            let module = entry.trim().split("::").next().unwrap_or_default();
+            if !module.is_empty() && module != "self" {
+                modules.insert(module.to_string());
+            }
+        }
+    }
+    modules
+}
+
+fn import_graph() -> BTreeMap<String, BTreeSet<String>> {
+    let mut graph = BTreeMap::new();
+    for entry in fs::read_dir(source_dir()).expect("read src") {
+        let path = entry.expect("dir entry").path();
+        let Some(module) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        if path.extension().is_some_and(|ext| ext == "rs") && module != "lib" {
+            graph.insert(module.to_string(), imported_modules(&read_module(module)));
+        }
+    }
+    graph
+}
+
+fn find_cycle(graph: &BTreeMap<String, BTreeSet<String>>) -> Option<Vec<String>> {
+    fn visit(
+        module: &str,
+        graph: &BTreeMap<String, BTreeSet<String>>,
+        stack: &mut Vec<String>,
+        done: &mut BTreeSet<String>,
+    ) -> Option<Vec<String>> {
+        if let Some(start) = stack.iter().position(|entry| entry == module) {
+            let mut cycle = stack[start..].to_vec();
+            cycle.push(module.to_string());
+            return Some(cycle);
+        }
+        if done.contains(module) {
+            return None;
+        }
+        stack.push(module.to_string());
+        for dependency in graph.get(module).into_iter().flatten() {
+            if let Some(cycle) = visit(dependency, graph, stack, done) {
+                return Some(cycle);
+            }
+        }
+        stack.pop();
+        done.insert(module.to_string());
+        None
+    }
+
+    let mut done = BTreeSet::new();
+    graph
+        .keys()
+        .find_map(|module| visit(module, graph, &mut Vec::new(), &mut done))
+}
+
+#[test]
+fn imports_are_parsed_from_single_and_grouped_use_declarations() {
+    let source = "use crate::config;\nuse crate::output::ColorMode;\nuse crate::{db, proxy::MANAGED_START};\nuse std::fs;\n";
+    let modules: Vec<String> = imported_modules(source).into_iter().collect();
+    assert_eq!(modules, ["config", "db", "output", "proxy"]);
+}
+
+#[test]
+fn module_imports_follow_the_allowed_hierarchy() {
+    let allowed: BTreeMap<&str, &[&str]> = ALLOWED_IMPORTS.into_iter().collect();
+    for (module, imports) in import_graph() {
+        let permitted = allowed
+            .get(module.as_str())
+            .unwrap_or_else(|| panic!("new module `{module}` missing from ALLOWED_IMPORTS"));
+        for import in &imports {
+            assert!(
+                permitted.contains(&import.as_str()),
+                "`{module}` imports `{import}`, which ALLOWED_IMPORTS does not permit"
+            );
+        }
+    }
+}
+
+#[test]
+fn module_graph_has_no_cycles() {
+    let graph = import_graph();
+    assert!(graph.contains_key("proxy"));
+    assert_eq!(find_cycle(&graph), None);
+
+    let allowed: BTreeMap<String, BTreeSet<String>> = ALLOWED_IMPORTS
+        .iter()
+        .map(|(module, imports)| {
+            let imports = imports.iter().map(|import| import.to_string()).collect();
+            (module.to_string(), imports)
+        })
+        .collect();
+    assert_eq!(find_cycle(&allowed), None);
+
+    let mut cyclic = allowed;
+    cyclic
+        .get_mut("config")
+        .unwrap()
+        .insert("proxy".to_string());
+    assert!(find_cycle(&cyclic).is_some());
+}
+
+#[test]
+fn internal_types_are_not_public() {
+    for (module, name) in INTERNAL_TYPES {
+        let source = read_module(module);
+        let declared = ["struct", "enum"]
+            .iter()
+            .any(|kind| source.contains(&format!("{kind} {name} ")));
+        assert!(declared, "`{name}` no longer declared in `{module}`");
+        for kind in ["struct", "enum"] {
+            assert!(
+                !source.contains(&format!("pub {kind} {name} ")),
+                "`{module}::{name}` should stay private (or pub(crate))"
+            );
+        }
+    }
+}